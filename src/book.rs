@@ -0,0 +1,112 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::{NbconvertError, Result};
+
+/// A notebook of a book, see [`crate::Converter::convert_book`].
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    pub path: PathBuf,
+    /// The chapter heading, defaults to the file name.
+    pub title: Option<String>,
+}
+
+impl Chapter {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Chapter { path: path.into(), title: None }
+    }
+
+    /// The chapter heading: the given title or the file name.
+    pub fn heading(&self) -> String {
+        self.title.clone().unwrap_or_else(|| {
+            self.path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+    }
+}
+
+/// A book manifest (.toml, .yaml or .yml), e.g.
+///
+/// ```toml
+/// title = "Internship Report"
+/// output = "report.pdf"
+/// chapters = ["Task1.ipynb", { path = "Task2.ipynb", title = "Task Two" }]
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub title: Option<String>,
+    /// The output path, relative to the manifest.
+    pub output: Option<PathBuf>,
+    pub chapters: Vec<ManifestChapter>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ManifestChapter {
+    Path(PathBuf),
+    Titled { path: PathBuf, title: Option<String> },
+}
+
+impl Manifest {
+    /// Read the manifest, the paths in it are resolved against its directory.
+    pub fn from_file(path: &Path) -> Result<Manifest> {
+        let content = fs::read_to_string(path)?;
+        let invalid = |message: String| NbconvertError::InvalidArgument(format!("invalid manifest {}: {}", path.display(), message));
+        let mut manifest: Manifest = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&content).map_err(|e| invalid(e.to_string()))?,
+            _ => toml::from_str(&content).map_err(|e| invalid(e.to_string()))?,
+        };
+
+        let dir = path.parent().unwrap_or(Path::new("."));
+        manifest.output = manifest.output.map(|output| dir.join(output));
+        for chapter in &mut manifest.chapters {
+            match chapter {
+                ManifestChapter::Path(path) | ManifestChapter::Titled { path, .. } => *path = dir.join(&*path),
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn chapters(&self) -> Vec<Chapter> {
+        self.chapters
+            .iter()
+            .map(|chapter| match chapter {
+                ManifestChapter::Path(path) => Chapter::new(path),
+                ManifestChapter::Titled { path, title } => Chapter { path: path.clone(), title: title.clone() },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_a_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.toml");
+        fs::write(&path, "title = \"Report\"\nchapters = [\"a.ipynb\", { path = \"b.ipynb\", title = \"B\" }]").unwrap();
+        let manifest = Manifest::from_file(&path).unwrap();
+        let chapters = manifest.chapters();
+        assert_eq!(chapters[0].path, dir.path().join("a.ipynb"));
+        assert_eq!((chapters[0].heading(), chapters[1].heading()), ("a".to_string(), "B".to_string()));
+    }
+
+    #[test]
+    fn invalid_manifests_name_their_file() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in [("book.toml", "chapters = ["), ("book.yaml", "chapters: [")] {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            let error = Manifest::from_file(&path).unwrap_err().to_string();
+            assert!(error.contains(&format!("invalid manifest {}: ", path.display())), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,505 @@
+use std::fmt;
+use std::path::{self, Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{NbconvertError, Result, Severity};
+use crate::packages::missing_packages;
+
+/// How to compile the Typst source.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CompileOptions {
+    /// The typst binary to run. Without it, the embedded compiler is used if built with the
+    /// `embed-typst` feature, or else `typst` from the `PATH`.
+    pub typst_bin: Option<PathBuf>,
+    /// The directories of fonts, in addition to the ones of the system.
+    pub font_paths: Vec<PathBuf>,
+    /// The package cache, see [`package_cache_dir`](crate::packages::package_cache_dir).
+    pub package_cache: Option<PathBuf>,
+    /// Fail if a package isn't installed, instead of downloading it.
+    pub offline: bool,
+}
+
+impl CompileOptions {
+    /// The binary to run, `None` for the embedded compiler.
+    fn bin(&self) -> Option<&Path> {
+        match &self.typst_bin {
+            Some(bin) => Some(bin),
+            None if cfg!(feature = "embed-typst") => None,
+            None => Some(Path::new("typst")),
+        }
+    }
+}
+
+/// The resolution of the PNG images by default, in pixels per inch.
+pub const DEFAULT_DPI: f32 = 144.0;
+
+/// What the Typst source is compiled to.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// The Typst source and its assets only, not compiled.
+    Typst,
+    #[default]
+    Pdf,
+    /// An image per page.
+    Png,
+    /// An image per page.
+    Svg,
+    /// With the HTML export of typst 0.13 or later, so only with a typst binary.
+    Html,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Typst => "typ",
+            OutputFormat::Pdf => "pdf",
+            OutputFormat::Png => "png",
+            OutputFormat::Svg => "svg",
+            OutputFormat::Html => "html",
+        }
+    }
+
+    /// The format of an output file extension, e.g. `png`.
+    pub fn from_extension(extension: &str) -> Option<OutputFormat> {
+        match extension.to_ascii_lowercase().as_str() {
+            "typ" | "typst" => Some(OutputFormat::Typst),
+            "pdf" => Some(OutputFormat::Pdf),
+            "png" => Some(OutputFormat::Png),
+            "svg" => Some(OutputFormat::Svg),
+            "html" | "htm" => Some(OutputFormat::Html),
+            _ => None,
+        }
+    }
+
+    /// Whether a file is written per page.
+    pub fn is_paged(self) -> bool {
+        matches!(self, OutputFormat::Png | OutputFormat::Svg)
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = NbconvertError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "typst" => Ok(OutputFormat::Typst),
+            "pdf" => Ok(OutputFormat::Pdf),
+            "png" => Ok(OutputFormat::Png),
+            "svg" => Ok(OutputFormat::Svg),
+            "html" => Ok(OutputFormat::Html),
+            other => Err(NbconvertError::InvalidArgument(format!(
+                "unknown output format \"{}\", expected typst, pdf, png, svg or html",
+                other
+            ))),
+        }
+    }
+}
+
+/// A range of 1-based pages: `3`, `2-5`, or open as `-5` and `7-`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageRange {
+    pub start: Option<usize>,
+    pub end: Option<usize>,
+}
+
+impl PageRange {
+    pub fn contains(&self, page: usize) -> bool {
+        self.start.is_none_or(|start| start <= page) && self.end.is_none_or(|end| page <= end)
+    }
+}
+
+impl fmt::Display for PageRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.start, self.end) {
+            (Some(start), Some(end)) if start == end => write!(f, "{}", start),
+            (start, end) => {
+                if let Some(start) = start {
+                    write!(f, "{}", start)?;
+                }
+                write!(f, "-")?;
+                if let Some(end) = end {
+                    write!(f, "{}", end)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Parse comma-separated page ranges, e.g. `1-3,5,8-`.
+pub fn parse_pages(pages: &str) -> Result<Vec<PageRange>> {
+    let invalid = || {
+        NbconvertError::InvalidArgument(format!("invalid pages \"{}\", expected ranges such as 1-3,5,8-", pages))
+    };
+    let page = |page: &str| -> Result<Option<usize>> {
+        match page.trim() {
+            "" => Ok(None),
+            page => page.parse().ok().filter(|page| *page > 0).map(Some).ok_or_else(invalid),
+        }
+    };
+    pages
+        .split(',')
+        .map(|range| {
+            let range = match range.split_once('-') {
+                Some((start, end)) => PageRange { start: page(start)?, end: page(end)? },
+                None => {
+                    let page = page(range)?.ok_or_else(invalid)?;
+                    PageRange { start: Some(page), end: Some(page) }
+                }
+            };
+            match (range.start, range.end) {
+                (Some(start), Some(end)) if start > end => Err(invalid()),
+                _ => Ok(range),
+            }
+        })
+        .collect()
+}
+
+/// How the compiled document is written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportOptions {
+    pub format: OutputFormat,
+    /// The pages to write, all of them if empty.
+    pub pages: Vec<PageRange>,
+    /// The resolution of the PNG images, in pixels per inch.
+    pub dpi: f32,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions { format: OutputFormat::default(), pages: Vec::new(), dpi: DEFAULT_DPI }
+    }
+}
+
+impl ExportOptions {
+    /// Whether the page is written.
+    pub fn exports(&self, page: usize) -> bool {
+        self.pages.is_empty() || self.pages.iter().any(|range| range.contains(page))
+    }
+}
+
+/// Whether the path has a page placeholder, see [`page_path`].
+pub fn has_page_pattern(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    ["{p}", "{0p}", "{n}"].iter().any(|pattern| path.contains(pattern))
+}
+
+/// The path of a page of a paged output: `{p}` is the page number, `{0p}` the page number
+/// padded with zeros to the width of `{t}`, the number of pages. `{n}` is `{p}`, as in
+/// typst 0.11.
+pub fn page_path(pattern: &Path, page: usize, total: usize) -> PathBuf {
+    let width = total.to_string().len();
+    let path = pattern
+        .to_string_lossy()
+        .replace("{0p}", &format!("{:0width$}", page, width = width))
+        .replace("{p}", &page.to_string())
+        .replace("{n}", &page.to_string())
+        .replace("{t}", &total.to_string());
+    PathBuf::from(path)
+}
+
+/// An error or warning of the Typst compiler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileDiagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// The file, relative to the directory of the compiled file, or in a package as
+    /// `@preview/name:version/file.typ`.
+    pub file: Option<String>,
+    /// The 1-based line and column in the file.
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub hints: Vec<String>,
+}
+
+impl fmt::Display for CompileDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: ")?,
+            Severity::Warning => write!(f, "warning: ")?,
+        }
+        if let Some(file) = &self.file {
+            write!(f, "{}", file)?;
+            if let (Some(line), Some(column)) = (self.line, self.column) {
+                write!(f, ":{}:{}", line, column)?;
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "{}", self.message)?;
+        for hint in &self.hints {
+            write!(f, "\n  hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compile a Typst file to `output` in the format of `export`, and return the warnings.
+/// The path of a paged format is a pattern, see [`page_path`].
+/// A failed compilation returns [`NbconvertError::TypstError`] with the errors and warnings.
+/// Offline, the packages the file imports must be installed, or it fails with
+/// [`NbconvertError::OfflineError`].
+pub fn compile(typ: &Path, output: &Path, options: &CompileOptions, export: &ExportOptions) -> Result<Vec<CompileDiagnostic>> {
+    if export.format == OutputFormat::Typst {
+        return Ok(Vec::new());
+    }
+    if options.offline {
+        let missing = missing_packages(typ, options.package_cache.as_deref());
+        if !missing.is_empty() {
+            return Err(NbconvertError::OfflineError(missing));
+        }
+    }
+    match options.bin() {
+        Some(bin) => compile_external(bin, typ, output, options, export),
+        None => embedded_compile(typ, output, options, export),
+    }
+}
+
+/// The font families the compiler finds, in the system and the font paths,
+/// `None` if typst can't run.
+pub fn font_families(options: &CompileOptions) -> Option<Vec<String>> {
+    let Some(bin) = options.bin() else {
+        return embedded_font_families(options);
+    };
+    let mut command = Command::new(bin);
+    command.arg("fonts");
+    for path in &options.font_paths {
+        command.arg("--font-path").arg(path);
+    }
+    let output = command.output().ok().filter(|output| output.status.success())?;
+    let families = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+    Some(families)
+}
+
+#[cfg(feature = "embed-typst")]
+fn embedded_compile(typ: &Path, output: &Path, options: &CompileOptions, export: &ExportOptions) -> Result<Vec<CompileDiagnostic>> {
+    crate::world::compile(typ, output, options, export)
+}
+
+#[cfg(not(feature = "embed-typst"))]
+fn embedded_compile(_: &Path, _: &Path, _: &CompileOptions, _: &ExportOptions) -> Result<Vec<CompileDiagnostic>> {
+    unreachable!("the embedded compiler is only used with the embed-typst feature")
+}
+
+#[cfg(feature = "embed-typst")]
+fn embedded_font_families(options: &CompileOptions) -> Option<Vec<String>> {
+    Some(crate::world::font_families(&options.font_paths))
+}
+
+#[cfg(not(feature = "embed-typst"))]
+fn embedded_font_families(_: &CompileOptions) -> Option<Vec<String>> {
+    None
+}
+
+/// Run `typst compile` and parse its diagnostics, printed in the short format.
+/// The page ranges need typst 0.12 or later, the HTML export typst 0.13 or later.
+fn compile_external(
+    bin: &Path,
+    typ: &Path,
+    output: &Path,
+    options: &CompileOptions,
+    export: &ExportOptions,
+) -> Result<Vec<CompileDiagnostic>> {
+    // typst prints the paths relative to its working directory, run it in the one of `typ`
+    // so that they are the same as with the embedded compiler.
+    let (typ, output) = (path::absolute(typ)?, path::absolute(output)?);
+    let bin = if bin.components().count() > 1 { path::absolute(bin)? } else { bin.to_path_buf() };
+    let mut command = Command::new(&bin);
+    command.arg("compile").arg("--diagnostic-format").arg("short");
+    command.arg("--format").arg(export.format.extension());
+    match export.format {
+        OutputFormat::Png => {
+            command.arg("--ppi").arg(export.dpi.to_string());
+        }
+        OutputFormat::Html => {
+            command.arg("--features").arg("html");
+        }
+        _ => {}
+    }
+    if !export.pages.is_empty() {
+        let pages: Vec<String> = export.pages.iter().map(PageRange::to_string).collect();
+        command.arg("--pages").arg(pages.join(","));
+    }
+    for path in &options.font_paths {
+        command.arg("--font-path").arg(path::absolute(path)?);
+    }
+    if let Some(cache) = &options.package_cache {
+        command.arg("--package-cache-path").arg(path::absolute(cache)?);
+    }
+    let output = command
+        .arg(&typ)
+        .arg(&output)
+        .current_dir(typ.parent().unwrap_or(Path::new("/")))
+        .output()
+        .map_err(|e| NbconvertError::CompileError(format!("{}: {}", bin.display(), e)))?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let mut diagnostics: Vec<CompileDiagnostic> = stderr.lines().filter_map(parse_short_diagnostic).collect();
+
+    if output.status.success() {
+        return Ok(diagnostics);
+    }
+    if !diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+        let message = match stderr.trim() {
+            "" => format!("typst exited with {}", output.status),
+            stderr => stderr.to_string(),
+        };
+        diagnostics.push(CompileDiagnostic {
+            severity: Severity::Error,
+            message,
+            file: None,
+            line: None,
+            column: None,
+            hints: Vec::new(),
+        });
+    }
+    Err(NbconvertError::TypstError(diagnostics))
+}
+
+/// Parse a diagnostic of `typst compile --diagnostic-format short`:
+/// `file:line:column: error: message`, or `error: message` without a location.
+fn parse_short_diagnostic(line: &str) -> Option<CompileDiagnostic> {
+    [(Severity::Error, "error: "), (Severity::Warning, "warning: ")]
+        .into_iter()
+        .find_map(|(severity, marker)| {
+            let (location, message) = match line.strip_prefix(marker) {
+                Some(message) => (None, message),
+                None => {
+                    let (location, message) = line.split_once(&format!(": {}", marker))?;
+                    (Some(location), message)
+                }
+            };
+            let (file, line, column) = match location {
+                Some(location) => {
+                    let mut parts = location.rsplitn(3, ':');
+                    let column = parts.next()?.parse().ok()?;
+                    let line = parts.next()?.parse().ok()?;
+                    (Some(parts.next()?.to_string()), Some(line), Some(column))
+                }
+                None => (None, None, None),
+            };
+            Some(CompileDiagnostic {
+                severity,
+                message: message.to_string(),
+                file,
+                line,
+                column,
+                hints: Vec::new(),
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: Option<usize>, end: Option<usize>) -> PageRange {
+        PageRange { start, end }
+    }
+
+    #[test]
+    fn page_ranges() {
+        assert_eq!(parse_pages("1-3,5").unwrap(), vec![range(Some(1), Some(3)), range(Some(5), Some(5))]);
+        assert_eq!(parse_pages(" 2 - 4 , 7").unwrap(), vec![range(Some(2), Some(4)), range(Some(7), Some(7))]);
+        assert_eq!(parse_pages("-2,8-").unwrap(), vec![range(None, Some(2)), range(Some(8), None)]);
+        assert_eq!(parse_pages("3-3").unwrap(), vec![range(Some(3), Some(3))]);
+    }
+
+    #[test]
+    fn overlapping_page_ranges() {
+        let export = ExportOptions { pages: parse_pages("1-3,2-4,3").unwrap(), ..ExportOptions::default() };
+        let pages: Vec<usize> = (1..=6).filter(|page| export.exports(*page)).collect();
+        assert_eq!(pages, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn invalid_page_ranges() {
+        for pages in ["0", "0-2", "3-1", "", "1,,2", "a", "1-b", "-1-2", "1.5"] {
+            assert!(parse_pages(pages).is_err(), "{:?}", pages);
+        }
+    }
+
+    #[test]
+    fn all_pages_without_ranges() {
+        let export = ExportOptions::default();
+        assert!(export.exports(1) && export.exports(100));
+    }
+
+    fn diagnostic(severity: Severity, message: &str, location: Option<(&str, usize, usize)>) -> CompileDiagnostic {
+        CompileDiagnostic {
+            severity,
+            message: message.to_string(),
+            file: location.map(|(file, _, _)| file.to_string()),
+            line: location.map(|(_, line, _)| line),
+            column: location.map(|(_, _, column)| column),
+            hints: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn short_diagnostics() {
+        assert_eq!(
+            parse_short_diagnostic("report.typ:12:5: error: unknown variable: foo"),
+            Some(diagnostic(Severity::Error, "unknown variable: foo", Some(("report.typ", 12, 5))))
+        );
+        assert_eq!(
+            parse_short_diagnostic("@preview/cuti:0.3.0/lib.typ:3:1: warning: unknown font family: simsun"),
+            Some(diagnostic(Severity::Warning, "unknown font family: simsun", Some(("@preview/cuti:0.3.0/lib.typ", 3, 1))))
+        );
+        assert_eq!(
+            parse_short_diagnostic("error: file not found (searched at report.typ)"),
+            Some(diagnostic(Severity::Error, "file not found (searched at report.typ)", None))
+        );
+        assert_eq!(parse_short_diagnostic("  = hint: did you mean foo?"), None);
+        assert_eq!(parse_short_diagnostic("compiled with warnings"), None);
+    }
+
+    #[test]
+    fn short_diagnostics_with_drive_letters() {
+        assert_eq!(
+            parse_short_diagnostic(r"C:\Users\me\report.typ:7:14: error: expected expression"),
+            Some(diagnostic(Severity::Error, "expected expression", Some((r"C:\Users\me\report.typ", 7, 14))))
+        );
+        assert_eq!(
+            parse_short_diagnostic(r"D:\notes\template.typ:1:1: warning: unused import"),
+            Some(diagnostic(Severity::Warning, "unused import", Some((r"D:\notes\template.typ", 1, 1))))
+        );
+    }
+
+    /// A file importing `package`, with the package cache of the tests, offline.
+    fn offline_compile(package: &str) -> Result<Vec<CompileDiagnostic>> {
+        let dir = tempfile::tempdir().unwrap();
+        let typ = dir.path().join("main.typ");
+        std::fs::write(&typ, format!("#import \"{}\": *\n", package)).unwrap();
+        let options = CompileOptions {
+            // It only runs if the packages are installed.
+            typst_bin: Some(PathBuf::from("true")),
+            package_cache: Some(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/packages")),
+            offline: true,
+            ..CompileOptions::default()
+        };
+        compile(&typ, &dir.path().join("main.pdf"), &options, &ExportOptions::default())
+    }
+
+    #[test]
+    fn offline_without_a_package() {
+        let error = offline_compile("@preview/nbconvert-missing:0.1.0").unwrap_err();
+        assert!(matches!(&error, NbconvertError::OfflineError(packages) if packages.len() == 1));
+        let message = error.to_string();
+        assert!(message.contains("@preview/nbconvert-missing:0.1.0"), "{}", message);
+        assert!(message.contains("nbconvert-rust packages install"), "{}", message);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn offline_with_the_packages_cached() {
+        assert!(offline_compile("@preview/cuti:0.3.0").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,473 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use std::collections::BTreeMap;
+
+use nbformat::Notebook;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::compile::{parse_pages, CompileOptions, ExportOptions, OutputFormat, DEFAULT_DPI};
+use crate::error::{NbconvertError, Result};
+use crate::media::FallbackAction;
+use crate::notebook::notebook_config;
+use crate::tags::TagAction;
+use crate::template::builtin_template;
+use crate::typst_content::{Author, Fonts};
+use crate::vars::resolve_var_paths;
+
+/// The default name of the configuration file.
+pub const CONFIG_FILE_NAME: &str = "nbconvert-rust.toml";
+
+/// Settings read from a `nbconvert-rust.toml` file, the defaults of the CLI arguments.
+///
+/// The unset fields are taken from the configs of lower precedence, see [`Config::merge`].
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// The output directory, relative to the config file.
+    pub output_dir: Option<PathBuf>,
+
+    /// The directory of the figures and downloaded files, relative to the output directory.
+    pub downloads_dir: Option<PathBuf>,
+
+    pub title: Option<String>,
+
+    /// The authors file, relative to the config file.
+    pub authors_file: Option<PathBuf>,
+
+    /// The date, `YYYY-MM-DD`, `today`, `file-mtime` or `SOURCE_DATE_EPOCH`.
+    pub date: Option<String>,
+
+    /// The language, e.g. `en` or `zh-CN`.
+    pub lang: Option<String>,
+
+    /// The region, e.g. `us` or `cn`.
+    pub region: Option<String>,
+
+    /// The template, a built-in name or a `.typ` file relative to the config file.
+    pub template: Option<String>,
+
+    /// The Typst paper name, e.g. `a4` or `us-letter`.
+    pub paper: Option<String>,
+
+    /// Turn the pages to landscape.
+    pub landscape: Option<bool>,
+
+    /// The directories of fonts given to typst, relative to the config file.
+    pub font_paths: Option<Vec<PathBuf>>,
+
+    /// The typst binary to run instead of the embedded compiler, a path relative to the
+    /// config file or a name in the `PATH`.
+    pub typst_bin: Option<PathBuf>,
+
+    /// The package cache given to the compiler, relative to the config file.
+    pub package_cache: Option<PathBuf>,
+
+    /// Never download the Typst packages, see `--offline`.
+    pub offline: Option<bool>,
+
+    /// The output format, PDF by default.
+    pub to: Option<OutputFormat>,
+
+    /// The pages to export, e.g. `1-3,5`, all of them by default.
+    pub pages: Option<String>,
+
+    /// The resolution of the PNG images, in pixels per inch.
+    pub dpi: Option<f32>,
+
+    /// The MIME types to render, from the most to the least preferred.
+    pub mime_priority: Option<Vec<String>>,
+
+    /// Hide what the author collapsed in Jupyter, see `--respect-hidden`.
+    pub respect_hidden: Option<bool>,
+
+    /// Fail if any warning or error is reported, see `--strict`.
+    pub strict: Option<bool>,
+
+    /// The authors, after the ones of the authors file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<Author>,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub fonts: Fonts,
+
+    /// The fallback action per unsupported MIME type, `*` matches every type.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub mime_fallback: BTreeMap<String, FallbackAction>,
+
+    /// The action per cell tag, in addition to the built-in tags.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tag_actions: BTreeMap<String, TagAction>,
+
+    /// The template variables, a `{ path = "..." }` table is a file relative to the config file.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vars: BTreeMap<String, Value>,
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+impl Config {
+    /// Read the configuration from the given TOML file.
+    /// The paths in it are resolved against its directory.
+    pub fn from_file(path: &Path) -> Result<Config> {
+        let content = fs::read_to_string(path)?;
+        let mut config: Config = toml::from_str(&content)?;
+
+        config.resolve_paths(path.parent().unwrap_or(Path::new(".")));
+        Ok(config)
+    }
+
+    /// Resolve the relative paths of the config against `dir`.
+    pub fn resolve_paths(&mut self, dir: &Path) {
+        for path in [&mut self.output_dir, &mut self.authors_file, &mut self.package_cache] {
+            *path = path.take().map(|path| dir.join(path));
+        }
+        for path in self.font_paths.iter_mut().flatten() {
+            *path = dir.join(&path);
+        }
+        if let Some(bin) = self.typst_bin.as_mut().filter(|bin| bin.components().count() > 1) {
+            *bin = dir.join(&bin);
+        }
+        if let Some(template) = self.template.take() {
+            self.template = Some(match builtin_template(&template) {
+                Some(_) => template,
+                None => dir.join(template).display().to_string(),
+            });
+        }
+        resolve_var_paths(&mut self.vars, dir);
+    }
+
+    /// Fill the fields not set yet from `base`, a config of lower precedence.
+    /// The authors are taken as a whole, the MIME fallbacks, the tag actions and the
+    /// variables one by one.
+    pub fn merge(&mut self, base: Config) {
+        self.output_dir = self.output_dir.take().or(base.output_dir);
+        self.downloads_dir = self.downloads_dir.take().or(base.downloads_dir);
+        self.title = self.title.take().or(base.title);
+        if self.authors.is_empty() && self.authors_file.is_none() {
+            self.authors = base.authors;
+            self.authors_file = base.authors_file;
+        }
+        self.date = self.date.take().or(base.date);
+        if self.lang.is_none() {
+            self.lang = base.lang;
+            self.region = self.region.take().or(base.region);
+        }
+        self.template = self.template.take().or(base.template);
+        self.paper = self.paper.take().or(base.paper);
+        self.landscape = self.landscape.or(base.landscape);
+        self.fonts.body = self.fonts.body.take().or(base.fonts.body);
+        self.fonts.cjk = self.fonts.cjk.take().or(base.fonts.cjk);
+        self.fonts.emphasis = self.fonts.emphasis.take().or(base.fonts.emphasis);
+        self.fonts.mono = self.fonts.mono.take().or(base.fonts.mono);
+        self.font_paths = self.font_paths.take().or(base.font_paths);
+        self.typst_bin = self.typst_bin.take().or(base.typst_bin);
+        self.package_cache = self.package_cache.take().or(base.package_cache);
+        self.offline = self.offline.or(base.offline);
+        self.to = self.to.or(base.to);
+        self.pages = self.pages.take().or(base.pages);
+        self.dpi = self.dpi.or(base.dpi);
+        self.mime_priority = self.mime_priority.take().or(base.mime_priority);
+        for (mime, action) in base.mime_fallback {
+            self.mime_fallback.entry(mime).or_insert(action);
+        }
+        for (tag, action) in base.tag_actions {
+            self.tag_actions.entry(tag).or_insert(action);
+        }
+        for (name, value) in base.vars {
+            self.vars.entry(name).or_insert(value);
+        }
+        self.respect_hidden = self.respect_hidden.or(base.respect_hidden);
+        self.strict = self.strict.or(base.strict);
+    }
+
+    /// Find the configuration files, from the highest to the lowest precedence:
+    /// the explicitly given path, or else the first `nbconvert-rust.toml` found walking up
+    /// from `dir`, then the user config.
+    pub fn find(explicit: Option<&Path>, dir: &Path) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+
+        match explicit {
+            Some(path) => paths.push(path.to_path_buf()),
+            None => {
+                let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+                if let Some(path) = dir
+                    .ancestors()
+                    .map(|dir| dir.join(CONFIG_FILE_NAME))
+                    .find(|path| path.is_file())
+                {
+                    paths.push(path);
+                }
+            }
+        }
+
+        if let Some(path) = Config::user_path().filter(|path| path.is_file()) {
+            paths.push(path);
+        }
+
+        paths
+    }
+
+    /// The user config: `$XDG_CONFIG_HOME/nbconvert-rust/config.toml`, by default in
+    /// `~/.config`, or `%APPDATA%\nbconvert-rust\config.toml` on Windows.
+    pub fn user_path() -> Option<PathBuf> {
+        let dir = if cfg!(windows) {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        };
+        dir.map(|dir| dir.join("nbconvert-rust").join("config.toml"))
+    }
+
+    /// The settings in the metadata of a notebook in `dir` over `self`.
+    pub fn with_notebook(self, notebook: &Notebook, dir: &Path) -> Result<Config> {
+        match notebook_config(notebook)? {
+            Some(settings) => {
+                let mut config = settings.into_config(dir);
+                config.merge(self);
+                Ok(config)
+            }
+            None => Ok(self),
+        }
+    }
+
+    /// Read and merge the given files, from the highest to the lowest precedence, under `self`.
+    pub fn load(mut self, paths: &[PathBuf]) -> Result<Config> {
+        for path in paths {
+            self.merge(Config::from_file(path)?);
+        }
+        Ok(self)
+    }
+
+    /// How to compile the Typst source.
+    pub fn compile_options(&self) -> CompileOptions {
+        CompileOptions {
+            typst_bin: self.typst_bin.clone(),
+            font_paths: self.font_paths.clone().unwrap_or_default(),
+            package_cache: self.package_cache.clone(),
+            offline: self.offline.unwrap_or(false),
+        }
+    }
+
+    /// How to write the compiled document.
+    pub fn export_options(&self) -> Result<ExportOptions> {
+        let dpi = self.dpi.unwrap_or(DEFAULT_DPI);
+        if dpi.is_nan() || dpi <= 0.0 {
+            return Err(NbconvertError::InvalidArgument(format!("invalid dpi {}, expected a positive number", dpi)));
+        }
+        Ok(ExportOptions {
+            format: self.to.unwrap_or_default(),
+            pages: self.pages.as_deref().map(parse_pages).transpose()?.unwrap_or_default(),
+            dpi,
+        })
+    }
+
+    /// The configuration as TOML, e.g. for `config show`.
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self)
+            .map_err(|e| NbconvertError::InvalidArgument(format!("can't write the config as TOML: {}", e)))
+    }
+}
+
+/// The conversion settings stored in a notebook, under `metadata.nbconvert_rust`.
+/// They override the config files, and the CLI options override them.
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct NotebookConfig {
+    pub title: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<Author>,
+    pub date: Option<String>,
+    pub lang: Option<String>,
+    pub region: Option<String>,
+    /// The template, a built-in name or a `.typ` file relative to the notebook.
+    pub template: Option<String>,
+    pub paper: Option<String>,
+    pub landscape: Option<bool>,
+    #[serde(default)]
+    pub fonts: Fonts,
+    pub mime_priority: Option<Vec<String>>,
+    #[serde(default)]
+    pub mime_fallback: BTreeMap<String, FallbackAction>,
+    #[serde(default)]
+    pub tag_actions: BTreeMap<String, TagAction>,
+    pub respect_hidden: Option<bool>,
+    #[serde(default)]
+    pub vars: BTreeMap<String, Value>,
+}
+
+impl NotebookConfig {
+    /// The settings as a config, the template and the path variables are resolved against
+    /// `dir`, the notebook directory.
+    pub fn into_config(self, dir: &Path) -> Config {
+        let mut config = Config {
+            title: self.title,
+            authors: self.authors,
+            date: self.date,
+            lang: self.lang,
+            region: self.region,
+            template: self.template,
+            paper: self.paper,
+            landscape: self.landscape,
+            fonts: self.fonts,
+            mime_priority: self.mime_priority,
+            mime_fallback: self.mime_fallback,
+            tag_actions: self.tag_actions,
+            respect_hidden: self.respect_hidden,
+            vars: self.vars,
+            ..Config::default()
+        };
+        config.resolve_paths(dir);
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn merged(high: &str, base: &str) -> Config {
+        let mut config = config(high);
+        config.merge(self::config(base));
+        config
+    }
+
+    #[test]
+    fn merge_unset_fields() {
+        let config = merged(
+            "title = \"High\"\nlandscape = false\n[vars]\na = 1",
+            "title = \"Base\"\npaper = \"a5\"\nlandscape = true\nstrict = true\nauthors = [{ name = \"B\" }]\n[vars]\na = 2\nb = 2",
+        );
+        assert_eq!(config.title.as_deref(), Some("High"));
+        assert_eq!(config.paper.as_deref(), Some("a5"));
+        // A false of higher precedence is kept.
+        assert_eq!(config.landscape, Some(false));
+        assert_eq!(config.strict, Some(true));
+        assert_eq!(config.authors.len(), 1);
+        assert_eq!(config.vars.get("a"), Some(&Value::from(1)));
+        assert_eq!(config.vars.get("b"), Some(&Value::from(2)));
+    }
+
+    #[test]
+    fn merge_authors_as_a_whole() {
+        let config = merged("authors = [{ name = \"A\" }]", "authors = [{ name = \"B\" }, { name = \"C\" }]");
+        let names: Vec<&str> = config.authors.iter().map(|author| author.name.as_str()).collect();
+        assert_eq!(names, vec!["A"]);
+    }
+
+    #[test]
+    fn merge_lang_with_its_region() {
+        let base = "lang = \"zh\"\nregion = \"tw\"";
+        // A language of higher precedence drops the region of another language.
+        let config = merged("lang = \"en\"", base);
+        assert_eq!((config.lang.as_deref(), config.region.as_deref()), (Some("en"), None));
+        // A region alone applies to the language of lower precedence.
+        let config = merged("region = \"cn\"", base);
+        assert_eq!((config.lang.as_deref(), config.region.as_deref()), (Some("zh"), Some("cn")));
+        let config = merged("", base);
+        assert_eq!((config.lang.as_deref(), config.region.as_deref()), (Some("zh"), Some("tw")));
+    }
+
+    #[test]
+    fn load_files_in_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let (high, low) = (dir.path().join("high.toml"), dir.path().join("low.toml"));
+        fs::write(&high, "paper = \"a5\"").unwrap();
+        fs::write(&low, "paper = \"a4\"\nlandscape = true\ntemplate = \"mine.typ\"").unwrap();
+        let cli = Config { landscape: Some(false), ..Config::default() };
+        let config = cli.load(&[high, low]).unwrap();
+        assert_eq!(config.paper.as_deref(), Some("a5"));
+        assert_eq!(config.landscape, Some(false));
+        // Relative to its file.
+        assert_eq!(config.template, Some(dir.path().join("mine.typ").display().to_string()));
+    }
+
+    #[test]
+    fn find_the_nearest_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let nested = root.join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.join(CONFIG_FILE_NAME), "").unwrap();
+        assert_eq!(Config::find(None, &nested).first(), Some(&root.join(CONFIG_FILE_NAME)));
+
+        fs::write(root.join("a").join(CONFIG_FILE_NAME), "").unwrap();
+        let paths = Config::find(None, &nested);
+        assert_eq!(paths.first(), Some(&root.join("a").join(CONFIG_FILE_NAME)));
+        // Only the nearest one, then the user config if there is one.
+        assert!(!paths.contains(&root.join(CONFIG_FILE_NAME)));
+        if let Some(user) = Config::user_path().filter(|path| path.is_file()) {
+            assert_eq!(paths.last(), Some(&user));
+        }
+
+        // An explicit config replaces the project one.
+        let explicit = root.join("other.toml");
+        let paths = Config::find(Some(&explicit), &nested);
+        assert_eq!(paths.first(), Some(&explicit));
+        assert!(!paths.contains(&root.join("a").join(CONFIG_FILE_NAME)));
+    }
+
+    fn notebook(settings: serde_json::Value) -> Notebook {
+        let notebook = serde_json::json!({
+            "nbformat": 4, "nbformat_minor": 5, "cells": [],
+            "metadata": {"nbconvert_rust": settings},
+        });
+        nbformat::parse_notebook(&notebook.to_string()).unwrap()
+    }
+
+    #[test]
+    fn notebook_settings_as_a_config() {
+        let settings: NotebookConfig = serde_json::from_value(serde_json::json!({
+            "title": "Lab", "template": "mine.typ", "landscape": false,
+            "vars": {"logo": {"path": "logo.png"}, "course": "CS101"},
+        }))
+        .unwrap();
+        let config = settings.into_config(Path::new("notes"));
+        assert_eq!(config.title.as_deref(), Some("Lab"));
+        assert_eq!(config.landscape, Some(false));
+        // The files are relative to the notebook.
+        assert_eq!(config.template, Some(Path::new("notes").join("mine.typ").display().to_string()));
+        assert_eq!(config.vars["logo"], crate::vars::path_var(&Path::new("notes").join("logo.png").display().to_string()));
+        assert_eq!(config.vars["course"], Value::from("CS101"));
+
+        let settings = NotebookConfig { template: Some("report".to_string()), ..NotebookConfig::default() };
+        assert_eq!(settings.into_config(Path::new("notes")).template.as_deref(), Some("report"));
+    }
+
+    #[test]
+    fn notebook_settings_over_the_config() {
+        let files = config("paper = \"a5\"\nlandscape = true\nlang = \"zh\"\nregion = \"tw\"\n[vars]\na = 1\nb = 1");
+        let notebook = notebook(serde_json::json!({"landscape": false, "lang": "en", "vars": {"a": 2}}));
+        let config = files.with_notebook(&notebook, Path::new(".")).unwrap();
+        assert_eq!(config.paper.as_deref(), Some("a5"));
+        assert_eq!(config.landscape, Some(false));
+        assert_eq!((config.lang.as_deref(), config.region.as_deref()), (Some("en"), None));
+        assert_eq!((config.vars["a"].clone(), config.vars["b"].clone()), (Value::from(2), Value::from(1)));
+
+        // The CLI is merged over both.
+        let mut cli = Config { landscape: Some(true), ..Config::default() };
+        cli.merge(config);
+        assert_eq!(cli.landscape, Some(true));
+    }
+
+    #[test]
+    fn notebook_without_settings() {
+        let notebook = nbformat::parse_notebook(r#"{"nbformat": 4, "nbformat_minor": 5, "metadata": {}, "cells": []}"#).unwrap();
+        let config = config("paper = \"a5\"").with_notebook(&notebook, Path::new(".")).unwrap();
+        assert_eq!(config.paper.as_deref(), Some("a5"));
+
+        let error = config.with_notebook(&self::notebook(serde_json::json!({"papr": "a4"})), Path::new(".")).unwrap_err();
+        assert!(error.to_string().contains("unknown field `papr`"), "{}", error);
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use markdown::unist::Position;
+use uuid::Uuid;
+
+use crate::document::{Block, Inline, Metadata};
+use crate::error::{CellLocation, Diagnostics, NbconvertError, Result, Span};
+use crate::media::MediaOptions;
+use crate::tags::TagOptions;
+
+/// The state of converting one notebook.
+/// Each conversion has its own context, so that nothing leaks between notebooks.
+pub struct Context {
+    /// The language of the code cells, e.g. `python`.
+    pub language: String,
+    /// Where the figures and downloaded files are saved.
+    pub download_dir: PathBuf,
+    /// How the generated source refers to `download_dir`, e.g. `./downloads`.
+    pub asset_prefix: String,
+    /// The files written to `download_dir`.
+    pub assets: Vec<PathBuf>,
+    pub media: MediaOptions,
+    pub tags: TagOptions,
+    /// Hide what the author collapsed in Jupyter, and cut the scrolled outputs.
+    pub respect_hidden: bool,
+    pub diagnostics: Diagnostics,
+    /// The metadata read from the front matter, the first one wins.
+    pub metadata: Metadata,
+    /// The notebook being converted, set when several notebooks make a book.
+    pub notebook: Option<Arc<str>>,
+    /// The cell being converted.
+    pub cell: CellLocation,
+    /// The markdown cells converted so far, only the first one may start with a front matter.
+    pub markdown_cells: usize,
+    /// The markdown link definitions, `[x]: url`, of the current markdown source.
+    pub definitions: HashMap<String, String>,
+    /// The markdown footnote definitions, `[^x]: text`, of the current markdown source.
+    pub footnotes: HashMap<String, Vec<Block>>,
+    /// The attachments of the current markdown cell, name to path.
+    pub attachments: HashMap<String, String>,
+}
+
+impl Context {
+    pub fn new(download_dir: PathBuf, asset_prefix: String, media: MediaOptions) -> Self {
+        Context {
+            language: "text".to_owned(),
+            download_dir,
+            asset_prefix,
+            assets: Vec::new(),
+            media,
+            tags: TagOptions::default(),
+            respect_hidden: false,
+            diagnostics: Diagnostics::default(),
+            metadata: Metadata::default(),
+            notebook: None,
+            cell: CellLocation::default(),
+            markdown_cells: 0,
+            definitions: HashMap::new(),
+            footnotes: HashMap::new(),
+            attachments: HashMap::new(),
+        }
+    }
+
+    /// Start converting the `index`-th cell.
+    pub fn enter_cell(&mut self, index: usize, id: Option<String>) {
+        self.cell = CellLocation { notebook: self.notebook.clone(), index, id: id.map(Into::into), span: None };
+    }
+
+    /// The location of the current cell, narrowed to the markdown position if known.
+    pub fn location(&self, position: Option<&Position>) -> CellLocation {
+        let mut location = self.cell.clone();
+        if let Some(position) = position {
+            location.span = Some(Span {
+                line: position.start.line as u32,
+                column: position.start.column as u32,
+                end_line: position.end.line as u32,
+                end_column: position.end.column as u32,
+            });
+        }
+        location
+    }
+
+    /// Write an asset to the download directory, return the path to use in the Typst source.
+    pub fn write_asset(&mut self, file_name: &str, data: impl AsRef<[u8]>) -> Result<String> {
+        let file_path = self.download_dir.join(file_name);
+        fs::write(&file_path, data)?;
+        self.assets.push(file_path);
+        Ok(self.asset_path(file_name))
+    }
+
+    /// Write an asset named by the notebook, e.g. an attachment, under a unique plain file
+    /// name, see [`unique_file_name`].
+    pub fn write_named_asset(&mut self, prefix: &str, name: &str, data: impl AsRef<[u8]>) -> Result<String> {
+        self.write_asset(&unique_file_name(prefix, name), data)
+    }
+
+    /// The path of an asset as referred to in the Typst source.
+    pub fn asset_path(&self, file_name: &str) -> String {
+        format!("{}/{}", self.asset_prefix.trim_end_matches('/'), file_name)
+    }
+
+    pub fn warn(&mut self, error: NbconvertError) {
+        self.diagnostics.warn(error);
+    }
+
+    /// Record the error and return a visible error box in its place.
+    pub fn error_block(&mut self, error: NbconvertError) -> Block {
+        let message = error.to_string();
+        self.diagnostics.error(error);
+        Block::Error(message)
+    }
+
+    /// Like [`Context::error_block`], in the middle of a paragraph.
+    pub fn error_inline(&mut self, error: NbconvertError) -> Inline {
+        let message = error.to_string();
+        self.diagnostics.error(error);
+        Inline::Error(message)
+    }
+}
+
+/// A file name for an asset named by a notebook or a URL, unique in the shared download
+/// directory: `prefix_<uuid>_name`. The name is reduced to its last component, in the safe
+/// characters, so that it can't point out of the directory.
+pub fn unique_file_name(prefix: &str, name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    let name = name.trim_start_matches('.');
+    let name = if name.is_empty() { "file" } else { name };
+    format!("{}_{}_{}", prefix, Uuid::new_v4().simple(), name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_file_name_is_plain() {
+        for (name, expected) in [
+            ("image.png", "image.png"),
+            ("../../x.png", "x.png"),
+            ("/etc/passwd", "passwd"),
+            ("C:\\temp\\a b.png", "a_b.png"),
+            ("..", "file"),
+            ("", "file"),
+        ] {
+            let file_name = unique_file_name("attachment", name);
+            assert!(file_name.starts_with("attachment_"), "{}", file_name);
+            assert!(file_name.ends_with(&format!("_{}", expected)), "{} for {:?}", file_name, name);
+            assert!(!file_name.contains(['/', '\\']));
+        }
+        assert_ne!(unique_file_name("attachment", "image.png"), unique_file_name("attachment", "image.png"));
+    }
+}
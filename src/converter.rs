@@ -0,0 +1,541 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use nbformat::Notebook;
+use serde_json::Value;
+
+use crate::book::Chapter;
+use crate::config::Config;
+use crate::context::{unique_file_name, Context};
+use crate::document::Metadata;
+use crate::error::{Diagnostics, NbconvertError, Result};
+use crate::media::{default_mime_priority, normalize_mime, normalize_mime_priority, FallbackAction, MediaOptions};
+use crate::locale::{parse_lang, parse_region, ScriptCounts, DEFAULT_LANG};
+use crate::tags::TagAction;
+use crate::notebook::{convert_notebook, first_heading, read_notebook, script_counts};
+use crate::template::{create_blocks, create_template, template_source, DEFAULT_TEMPLATE};
+use crate::typst_content::{escape_content, read_authors, Author, Date, Fonts, Style, TypstContent};
+use crate::source_map::SourceMap;
+use crate::typst_writer::write_document_mapped;
+use crate::vars::{check_var_name, resolve_var_paths, var_path};
+
+/// Builder of a notebook conversion to Typst.
+///
+/// All the options have defaults, e.g. `Converter::new().convert_path("a.ipynb")`
+/// writes the figures to `./downloads` and uses the default template.
+///
+/// The settings of a notebook, in `metadata.nbconvert_rust`, apply to the options the
+/// converter doesn't set, over its [base config](Converter::base_config).
+#[derive(Debug, Clone)]
+pub struct Converter {
+    title: Option<String>,
+    authors: Vec<Author>,
+    date: Option<Date>,
+    lang: Option<String>,
+    region: Option<String>,
+    style: Style,
+    /// The template given to the converter, `None` for the default one.
+    template: Option<String>,
+    asset_dir: PathBuf,
+    asset_prefix: String,
+    /// `None` for the default priority.
+    mime_priority: Option<Vec<String>>,
+    mime_fallbacks: HashMap<String, FallbackAction>,
+    /// The actions of the tags, in addition to the built-in ones.
+    tag_actions: HashMap<String, TagAction>,
+    respect_hidden: bool,
+    vars: BTreeMap<String, Value>,
+    /// The settings under the ones of the notebooks, e.g. of the config files.
+    base_config: Config,
+}
+
+/// The result of a conversion.
+#[derive(Debug)]
+pub struct Conversion {
+    /// The generated Typst source, importing `template.typ`.
+    pub source: String,
+    /// The source of `template.typ`.
+    pub template: String,
+    /// Whether `template` replaces an existing `template.typ`: true for a template given
+    /// to the converter, false for the default one, which the user may have edited.
+    pub replace_template: bool,
+    /// The figures and downloaded files referred to by `source`.
+    pub assets: Vec<PathBuf>,
+    /// The warnings and errors reported during the conversion.
+    pub diagnostics: Diagnostics,
+    /// The cells the lines of `source` come from.
+    pub source_map: SourceMap,
+}
+
+impl Default for Converter {
+    fn default() -> Self {
+        Converter {
+            title: None,
+            authors: Vec::new(),
+            date: None,
+            lang: None,
+            region: None,
+            style: Style::default(),
+            template: None,
+            asset_dir: PathBuf::from("downloads"),
+            asset_prefix: "./downloads".to_string(),
+            mime_priority: None,
+            mime_fallbacks: HashMap::new(),
+            tag_actions: HashMap::new(),
+            respect_hidden: false,
+            vars: BTreeMap::new(),
+            base_config: Config::default(),
+        }
+    }
+}
+
+impl Converter {
+    pub fn new() -> Self {
+        Converter::default()
+    }
+
+    /// The title of the document. By default it is the title of the front matter or of
+    /// the notebook metadata, the first heading of the notebook, or the file name when
+    /// converting a path.
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// The authors of the document. By default they are read from the front matter or
+    /// the notebook metadata, or "Anonymous".
+    pub fn authors(mut self, authors: Vec<Author>) -> Self {
+        self.authors = authors;
+        self
+    }
+
+    /// The date in the preface, `None` for the date of the front matter,
+    /// or else the date of compilation.
+    pub fn date(mut self, date: Option<Date>) -> Self {
+        self.date = date;
+        self
+    }
+
+    /// The language of the document, an ISO 639 code such as `en` or `zh`, or a tag such
+    /// as `zh-TW` that also sets the region. It sets the date format and the language
+    /// of the template. By default it is the `lang` of the front matter, or `zh`, `ja` or
+    /// `ko` for a notebook mostly written in CJK characters, or else `en`.
+    pub fn lang(mut self, lang: impl Into<String>) -> Self {
+        self.lang = Some(lang.into());
+        self
+    }
+
+    /// The region of the document, an ISO 3166 code such as `us` or `cn`.
+    pub fn region(mut self, region: impl Into<String>) -> Self {
+        self.region = Some(region.into());
+        self
+    }
+
+    /// The source of `template.typ`, it must define `project` with the arguments described
+    /// in the README, e.g. a [`BuiltinTemplate`](crate::template::BuiltinTemplate) source.
+    pub fn template(mut self, source: impl Into<String>) -> Self {
+        self.template = Some(source.into());
+        self
+    }
+
+    /// The paper size, a Typst paper name such as `a4` (the default) or `us-letter`.
+    pub fn paper(mut self, paper: impl Into<String>) -> Self {
+        self.style.paper = Some(paper.into());
+        self
+    }
+
+    /// Turn the pages to landscape.
+    pub fn landscape(mut self, landscape: bool) -> Self {
+        self.style.landscape = landscape;
+        self
+    }
+
+    /// The font families, the unset ones keep the default of the template.
+    pub fn fonts(mut self, fonts: Fonts) -> Self {
+        self.style.fonts = fonts;
+        self
+    }
+
+    /// Where the figures and downloaded files are written, and how the
+    /// generated source refers to that directory (relative to the `.typ` file).
+    pub fn asset_dir(mut self, dir: impl Into<PathBuf>, prefix: impl Into<String>) -> Self {
+        self.asset_dir = dir.into();
+        self.asset_prefix = prefix.into();
+        self
+    }
+
+    /// The MIME display priority, short names such as `png` are accepted.
+    pub fn mime_priority(mut self, priority: &[String]) -> Self {
+        self.mime_priority = Some(normalize_mime_priority(priority));
+        self
+    }
+
+    /// The fallback action of an unsupported MIME type, `*` matches every type.
+    pub fn mime_fallback(mut self, mime: &str, action: FallbackAction) -> Self {
+        self.mime_fallbacks.insert(normalize_mime(mime), action);
+        self
+    }
+
+    /// The action on the cells tagged `tag`, in addition to (or replacing) the built-in tags
+    /// such as `remove-cell`.
+    pub fn tag_action(mut self, tag: &str, action: TagAction) -> Self {
+        self.tag_actions.insert(tag.trim().to_string(), action);
+        self
+    }
+
+    /// Render what the author chose to show in Jupyter: the collapsed sources and outputs
+    /// are hidden, the scrolled outputs are cut. Off by default.
+    pub fn respect_hidden(mut self, respect_hidden: bool) -> Self {
+        self.respect_hidden = respect_hidden;
+        self
+    }
+
+    /// A template variable, passed to `project` as a named argument. It overrides the
+    /// variable of the same name in the front matter. A path variable, an object
+    /// `{"path": "..."}`, is copied to the asset directory.
+    pub fn var(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.vars.insert(name.into(), value.into());
+        self
+    }
+
+    /// The settings under the ones of the notebooks, which override them, e.g. the merged
+    /// config files. The options of the converter override both.
+    pub fn base_config(mut self, config: Config) -> Self {
+        self.base_config = config;
+        self
+    }
+
+    /// Take the settings of `config` the converter doesn't set. A date such as `file-mtime`
+    /// is resolved against `sources`, see [`Date::resolve`]. The flags (`landscape`,
+    /// `respect-hidden`) can only be turned on.
+    pub fn apply_config(mut self, config: &Config, sources: &[PathBuf]) -> Result<Converter> {
+        if self.title.is_none() {
+            self.title = config.title.clone();
+        }
+        if self.authors.is_empty() {
+            if let Some(path) = &config.authors_file {
+                self.authors = read_authors(path)?;
+            }
+            self.authors.extend(config.authors.iter().cloned());
+        }
+        if self.date.is_none() {
+            if let Some(date) = &config.date {
+                self.date = Some(Date::resolve(date, sources)?);
+            }
+        }
+        if self.region.is_none() {
+            if let Some(region) = &config.region {
+                self.region = Some(parse_region(region)?);
+            }
+        }
+        if self.lang.is_none() {
+            if let Some(lang) = &config.lang {
+                let (lang, region) = parse_lang(lang)?;
+                self.lang = Some(lang);
+                self.region = self.region.or(region);
+            }
+        }
+        if self.template.is_none() {
+            if let Some(template) = &config.template {
+                self.template = Some(template_source(template)?);
+            }
+        }
+        self.style.paper = self.style.paper.or(config.paper.clone());
+        self.style.landscape |= config.landscape.unwrap_or(false);
+        let fonts = &mut self.style.fonts;
+        fonts.body = fonts.body.take().or(config.fonts.body.clone());
+        fonts.cjk = fonts.cjk.take().or(config.fonts.cjk.clone());
+        fonts.emphasis = fonts.emphasis.take().or(config.fonts.emphasis.clone());
+        fonts.mono = fonts.mono.take().or(config.fonts.mono.clone());
+        if self.mime_priority.is_none() {
+            self.mime_priority = config.mime_priority.as_deref().map(normalize_mime_priority);
+        }
+        for (mime, action) in &config.mime_fallback {
+            self.mime_fallbacks.entry(normalize_mime(mime)).or_insert(*action);
+        }
+        for (tag, action) in &config.tag_actions {
+            self.tag_actions.entry(tag.trim().to_string()).or_insert(*action);
+        }
+        self.respect_hidden |= config.respect_hidden.unwrap_or(false);
+        for (name, value) in &config.vars {
+            check_var_name(name)?;
+            self.vars.entry(name.clone()).or_insert_with(|| value.clone());
+        }
+        Ok(self)
+    }
+
+    /// The converter of a notebook: its settings over the base config, for the options the
+    /// converter doesn't set.
+    fn for_notebook(&self, notebook: &Notebook, path: Option<&Path>) -> Result<Converter> {
+        let mut config = notebook_settings(notebook, path)?;
+        config.merge(self.base_config.clone());
+        let sources: Vec<PathBuf> = path.map(Path::to_path_buf).into_iter().collect();
+        self.clone().apply_config(&config, &sources)
+    }
+
+    /// Convert the notebook at the given path.
+    pub fn convert_path(&self, path: impl AsRef<Path>) -> Result<Conversion> {
+        let notebook = read_notebook(&path)?;
+        self.convert_file(&notebook, path.as_ref())
+    }
+
+    /// Convert a notebook read from `path`, its file name is the default title.
+    /// The paths of the front matter variables are relative to its directory.
+    pub fn convert_file(&self, notebook: &Notebook, path: &Path) -> Result<Conversion> {
+        self.convert_titled(notebook, Some(path))
+    }
+
+    /// Convert the notebook, the assets are written to the asset directory.
+    pub fn convert(&self, notebook: &Notebook) -> Result<Conversion> {
+        self.convert_titled(notebook, None)
+    }
+
+    fn convert_titled(&self, notebook: &Notebook, path: Option<&Path>) -> Result<Conversion> {
+        self.for_notebook(notebook, path)?.convert_layered(notebook, path)
+    }
+
+    /// Convert the notebook with the settings of the converter only.
+    fn convert_layered(&self, notebook: &Notebook, path: Option<&Path>) -> Result<Conversion> {
+        fs::create_dir_all(&self.asset_dir)?;
+
+        let mut ctx = self.context();
+
+        let mut document = convert_notebook(notebook, &mut ctx)?;
+        if let Some(dir) = path.and_then(Path::parent) {
+            resolve_var_paths(&mut document.metadata.vars, dir);
+        }
+        if self.lang.is_none() && document.metadata.lang.is_none() {
+            let mut counts = ScriptCounts::default();
+            script_counts(notebook, &mut counts);
+            document.metadata.lang = counts.detect().map(String::from);
+        }
+        let file_stem = path.and_then(Path::file_stem).map(|stem| stem.to_string_lossy().to_string());
+        let mut metadata = self.preface_metadata(document.metadata.clone(), || {
+            first_heading(notebook)
+                .or(file_stem)
+                .unwrap_or_else(|| "Untitled Notebook".to_string())
+        });
+        self.copy_var_files(&mut metadata, &mut ctx.assets)?;
+
+        let (content, mut source_map) = write_document_mapped(&document, None);
+        let mut typst_content = TypstContent { content };
+        add_mapped_preface(&mut typst_content, &mut source_map, &metadata, &self.style);
+
+        Ok(Conversion {
+            source: typst_content.content,
+            template: self.template.clone().unwrap_or_else(|| DEFAULT_TEMPLATE.to_string()),
+            replace_template: self.template.is_some(),
+            assets: ctx.assets,
+            diagnostics: ctx.diagnostics,
+            source_map,
+        })
+    }
+
+    /// Convert the notebooks into one document. Each notebook is a chapter, its headings
+    /// are shifted down by one level under the chapter heading. The chapters share the
+    /// preface, the table of contents, the numbering and the asset directory.
+    pub fn convert_book(&self, chapters: &[Chapter]) -> Result<Conversion> {
+        let notebooks = chapters
+            .iter()
+            .map(|chapter| read_notebook(&chapter.path))
+            .collect::<Result<Vec<_>>>()?;
+
+        // The settings of the book are the ones of the first chapters with some, the
+        // titles of the chapters are their headings.
+        let mut config = Config::default();
+        for (chapter, notebook) in chapters.iter().zip(&notebooks) {
+            let mut settings = notebook_settings(notebook, Some(&chapter.path))?;
+            settings.title = None;
+            config.merge(settings);
+        }
+        config.merge(self.base_config.clone());
+        let sources: Vec<PathBuf> = chapters.iter().map(|chapter| chapter.path.clone()).collect();
+        let book = self.clone().apply_config(&config, &sources)?;
+        fs::create_dir_all(&book.asset_dir)?;
+
+        let mut content = String::new();
+        content += "#set heading(numbering: \"1.1\")\n\n";
+        // Unless the template shows one, see `has-outline` in `nbconvert.typ`.
+        content += "#book-outline()\n\n";
+
+        let mut assets = Vec::new();
+        let mut diagnostics = Diagnostics::default();
+        let mut source_map = SourceMap::default();
+        // The authors, date... of the first chapters with some, not their titles.
+        let mut book_metadata = Metadata::default();
+        let mut counts = ScriptCounts::default();
+
+        for (chapter, notebook) in chapters.iter().zip(&notebooks) {
+            script_counts(notebook, &mut counts);
+
+            let mut ctx = self.for_notebook(notebook, Some(&chapter.path))?.context();
+            ctx.notebook = Some(chapter.path.display().to_string().into());
+
+            let mut document = convert_notebook(notebook, &mut ctx)?;
+            document.shift_headings(1);
+            if let Some(dir) = chapter.path.parent() {
+                resolve_var_paths(&mut document.metadata.vars, dir);
+            }
+            content += "#pagebreak(weak: true)\n\n";
+            let heading = chapter
+                .title
+                .clone()
+                .or(notebook_settings(notebook, Some(&chapter.path))?.title)
+                .or(document.metadata.title.take())
+                .unwrap_or_else(|| chapter.heading());
+            content += format!(
+                "#heading(level: 1, \"{}\")\n\n",
+                escape_content(&heading)
+            ).as_str();
+            book_metadata.merge(document.metadata.clone());
+            let (chapter_content, chapter_map) = write_document_mapped(&document, ctx.notebook.clone());
+            source_map.append(chapter_map, content.matches('\n').count());
+            content += chapter_content.as_str();
+
+            assets.extend(ctx.assets);
+            diagnostics.append(ctx.diagnostics);
+        }
+
+        if book_metadata.lang.is_none() {
+            book_metadata.lang = counts.detect().map(String::from);
+        }
+        let mut metadata = book.preface_metadata(book_metadata, || "Untitled Book".to_string());
+        book.copy_var_files(&mut metadata, &mut assets)?;
+        let mut typst_content = TypstContent { content };
+        add_mapped_preface(&mut typst_content, &mut source_map, &metadata, &book.style);
+
+        Ok(Conversion {
+            source: typst_content.content,
+            template: book.template.clone().unwrap_or_else(|| DEFAULT_TEMPLATE.to_string()),
+            replace_template: book.template.is_some(),
+            assets,
+            diagnostics,
+            source_map,
+        })
+    }
+
+    /// The metadata of the preface: the options of the converter override the metadata
+    /// of the document.
+    fn preface_metadata(&self, mut metadata: Metadata, default_title: impl FnOnce() -> String) -> Metadata {
+        metadata.title = self.title.clone().or(metadata.title).or_else(|| Some(default_title()));
+        if !self.authors.is_empty() {
+            metadata.authors = self.authors.clone();
+        }
+        if metadata.authors.is_empty() {
+            metadata.authors = vec![Author::new("Anonymous")];
+        }
+        metadata.date = self.date.or(metadata.date);
+        if let Some(lang) = &self.lang {
+            // The region of another language doesn't apply.
+            if metadata.lang.as_ref() != Some(lang) {
+                metadata.region = None;
+            }
+            metadata.lang = Some(lang.clone());
+        }
+        metadata.lang.get_or_insert_with(|| DEFAULT_LANG.to_string());
+        metadata.region = self.region.clone().or(metadata.region);
+        for (name, value) in &self.vars {
+            metadata.vars.insert(name.clone(), value.clone());
+        }
+        metadata
+    }
+
+    /// Copy the files of the path variables to the asset directory, the variables become
+    /// their paths in the Typst source.
+    fn copy_var_files(&self, metadata: &mut Metadata, assets: &mut Vec<PathBuf>) -> Result<()> {
+        for (name, value) in metadata.vars.iter_mut() {
+            let Some(path) = var_path(value).map(PathBuf::from) else {
+                continue;
+            };
+            let file_name = path.file_name().ok_or_else(|| {
+                NbconvertError::InvalidArgument(format!("variable {}: {} is not a file", name, path.display()))
+            })?;
+            // Unique, as `a/logo.png` and `b/logo.png` share the asset directory.
+            let file_name = unique_file_name("var", &file_name.to_string_lossy());
+            let target = self.asset_dir.join(&file_name);
+            fs::copy(&path, &target).map_err(|e| {
+                NbconvertError::InvalidArgument(format!("variable {}: can't copy {}: {}", name, path.display(), e))
+            })?;
+            *value = Value::String(format!("{}/{}", self.asset_prefix.trim_end_matches('/'), file_name));
+            assets.push(target);
+        }
+        Ok(())
+    }
+
+    fn context(&self) -> Context {
+        let media = MediaOptions {
+            priority: self.mime_priority.clone().unwrap_or_else(default_mime_priority),
+            fallbacks: self.mime_fallbacks.clone(),
+        };
+        let mut ctx = Context::new(self.asset_dir.clone(), self.asset_prefix.clone(), media);
+        ctx.tags.actions.extend(self.tag_actions.clone());
+        ctx.respect_hidden = self.respect_hidden;
+        ctx
+    }
+}
+
+/// The settings in the metadata of the notebook, relative to its directory.
+fn notebook_settings(notebook: &Notebook, path: Option<&Path>) -> Result<Config> {
+    let dir = path.and_then(Path::parent).unwrap_or(Path::new("."));
+    Config::default().with_notebook(notebook, dir)
+}
+
+/// Add the preface to the content, and move the source map of the content after it.
+fn add_mapped_preface(content: &mut TypstContent, source_map: &mut SourceMap, metadata: &Metadata, style: &Style) {
+    let lines = content.content.matches('\n').count();
+    content.add_preface(metadata, style);
+    source_map.shift(content.content.matches('\n').count() - lines);
+}
+
+impl Conversion {
+    /// Write the source to `typ_path`, and `nbconvert.typ` and `template.typ` next to it
+    /// (the template unless it exists and `replace_template` is false).
+    pub fn write(&self, typ_path: &Path) -> Result<()> {
+        let dir = typ_path.parent().unwrap_or(Path::new("."));
+        self.write_source(typ_path)?;
+        create_blocks(dir)?;
+        create_template(dir, &self.template, self.replace_template)?;
+        Ok(())
+    }
+
+    /// Write only the source to `typ_path`, when the files next to it are written once for
+    /// several notebooks with [`write_support_files`](crate::template::write_support_files).
+    pub fn write_source(&self, typ_path: &Path) -> Result<()> {
+        fs::write(typ_path, &self.source)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vars::path_var;
+
+    #[test]
+    fn copy_var_files_with_the_same_name() {
+        let dir = tempfile::tempdir().unwrap();
+        for (sub, data) in [("a", "first"), ("b", "second")] {
+            fs::create_dir(dir.path().join(sub)).unwrap();
+            fs::write(dir.path().join(sub).join("logo.png"), data).unwrap();
+        }
+        let notebook = dir.path().join("notes.ipynb");
+        fs::write(&notebook, r#"{"nbformat": 4, "nbformat_minor": 5, "metadata": {}, "cells": []}"#).unwrap();
+
+        let conversion = Converter::new()
+            .asset_dir(dir.path().join("downloads"), "./downloads")
+            .var("first", path_var(&dir.path().join("a/logo.png").display().to_string()))
+            .var("second", path_var(&dir.path().join("b/logo.png").display().to_string()))
+            .convert_path(&notebook)
+            .unwrap();
+        assert_eq!(conversion.assets.len(), 2);
+        assert_ne!(conversion.assets[0], conversion.assets[1]);
+        assert_eq!(fs::read_to_string(&conversion.assets[0]).unwrap(), "first");
+        assert_eq!(fs::read_to_string(&conversion.assets[1]).unwrap(), "second");
+        for asset in &conversion.assets {
+            let path = format!("./downloads/{}", asset.file_name().unwrap().to_string_lossy());
+            assert!(path.ends_with("_logo.png"));
+            assert!(conversion.source.contains(&format!("\"{}\"", path)), "{}", conversion.source);
+        }
+    }
+}
@@ -0,0 +1,364 @@
+//! The document model between the front-ends (notebook, markdown, media)
+//! and the backends (Typst).
+//!
+//! The front-ends build a [`Document`] of cells, each holding [`Block`]s of
+//! [`Inline`]s. Passes such as applying the tag actions or shifting the headings
+//! run on this model, and a writer turns it into the output format.
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::error::{NbconvertError, Result};
+use crate::locale::{parse_lang, parse_region};
+use crate::tags::TagAction;
+use crate::typst_content::{Author, Date};
+use crate::vars::check_var_name;
+
+/// A converted notebook.
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    pub metadata: Metadata,
+    pub cells: Vec<Cell>,
+}
+
+/// The title, authors... of a document, from the front matter or the notebook metadata.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub authors: Vec<Author>,
+    pub date: Option<Date>,
+    pub r#abstract: Option<String>,
+    pub keywords: Vec<String>,
+    /// The ISO 639 language code, e.g. `zh`.
+    pub lang: Option<String>,
+    /// The ISO 3166 region code, e.g. `cn`.
+    pub region: Option<String>,
+    /// The template variables, passed to `project` as named arguments.
+    pub vars: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellKind {
+    Markdown,
+    Code,
+    Raw,
+}
+
+impl CellKind {
+    /// The name of the kind, as the `cell_type` of the notebook.
+    pub fn name(self) -> &'static str {
+        match self {
+            CellKind::Markdown => "markdown",
+            CellKind::Code => "code",
+            CellKind::Raw => "raw",
+        }
+    }
+}
+
+/// A notebook cell, its source and its outputs.
+#[derive(Debug, Clone)]
+pub struct Cell {
+    /// The 0-based index of the cell in the notebook.
+    pub index: usize,
+    /// The `id` of the cell, only in nbformat >= 4.5.
+    pub id: Option<String>,
+    pub kind: CellKind,
+    /// The actions of the tags, and of the collapsed parts with `respect-hidden`, applied
+    /// by [`Document::apply_tag_actions`].
+    pub actions: Vec<TagAction>,
+    /// The source: the code of a code cell, the content of a markdown or raw cell.
+    pub input: Vec<Block>,
+    /// The 1-based line of the cell source where each block of `input` starts.
+    pub input_lines: Vec<usize>,
+    /// The outputs of a code cell.
+    pub outputs: Vec<Block>,
+    /// The source of the cell as written, to quote it in the diagnostics of the compiler.
+    pub source: String,
+}
+
+#[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
+pub enum Block {
+    Heading { level: u8, content: Vec<Inline> },
+    Paragraph(Vec<Inline>),
+    BlockQuote(Vec<Block>),
+    /// Each item is a list of blocks.
+    List { ordered: bool, items: Vec<Vec<Block>> },
+    /// A code block in markdown, or an unsupported output shown as code.
+    CodeBlock { lang: Option<String>, code: String },
+    /// The source of a code cell.
+    Code { lang: String, source: String, execution_count: Option<i32> },
+    /// A stream output or a traceback.
+    Output(String),
+    /// LaTeX math, including its delimiters, e.g. `$$x^2$$`.
+    Math(String),
+    /// LaTeX text, which may contain math, e.g. from a raw cell.
+    Latex(String),
+    /// An image output, the path refers to an asset.
+    Figure { path: String },
+    Table(Table),
+    ThematicBreak,
+    /// A labelled box in place of an output that can't be rendered.
+    Placeholder(String),
+    /// A visible box in place of a part that failed to convert.
+    Error(String),
+    /// Source of the output format, written as is.
+    Raw(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Inline {
+    Text(String),
+    Emphasis(Vec<Inline>),
+    Strong(Vec<Inline>),
+    Underline(Vec<Inline>),
+    Strike(Vec<Inline>),
+    Code(String),
+    /// LaTeX math, without the delimiters.
+    Math(String),
+    Link { url: String, content: Vec<Inline> },
+    /// An image, the width is a percentage of the line width.
+    Image { path: String, width: Option<u32> },
+    Footnote(Vec<Block>),
+    LineBreak,
+    /// A visible box in place of a part that failed to convert.
+    Error(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Auto,
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    pub columns: usize,
+    /// The alignment per column, empty for the default.
+    pub align: Vec<Align>,
+    pub header: Vec<Vec<TableCell>>,
+    pub rows: Vec<Vec<TableCell>>,
+    /// A line below the table, e.g. "5 rows × 3 columns".
+    pub caption: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableCell {
+    pub content: Vec<Inline>,
+    pub rowspan: usize,
+    pub colspan: usize,
+    pub align: Option<Align>,
+}
+
+impl TableCell {
+    pub fn new(content: Vec<Inline>) -> Self {
+        TableCell { content, rowspan: 1, colspan: 1, align: None }
+    }
+}
+
+impl Metadata {
+    /// Read the known keys of a front matter or of the notebook metadata:
+    /// `title`, `author(s)`, `date`, `abstract`, `keywords`, `lang` (e.g. `zh` or `zh-CN`),
+    /// `region` and the `vars` table.
+    /// An author is a `"Name <email> (Affiliation)"` string, or an object with a `name`,
+    /// an `email`, `affiliations`, an `orcid`, a `student-id` and a `corresponding` mark.
+    pub fn from_value(value: &Value) -> Result<Metadata> {
+        let string = |key: &str| {
+            value
+                .get(key)
+                .and_then(|value| value.as_str())
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+
+        let authors = match value.get("authors").or(value.get("author")) {
+            Some(Value::Array(authors)) => authors.iter().cloned().map(Author::try_from).collect::<Result<_>>()?,
+            Some(Value::Null) | None => Vec::new(),
+            Some(author) => vec![Author::try_from(author.clone())?],
+        };
+
+        let date = match string("date") {
+            Some(date) => Some(date.parse::<Date>()?),
+            None => None,
+        };
+
+        let keywords = match value.get("keywords") {
+            Some(Value::Array(keywords)) => keywords
+                .iter()
+                .filter_map(|keyword| keyword.as_str())
+                .map(|keyword| keyword.trim().to_string())
+                .collect(),
+            Some(Value::String(keywords)) => keywords
+                .split(',')
+                .map(|keyword| keyword.trim().to_string())
+                .filter(|keyword| !keyword.is_empty())
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let (lang, mut region) = match string("lang") {
+            Some(lang) => {
+                let (lang, region) = parse_lang(&lang)?;
+                (Some(lang), region)
+            }
+            None => (None, None),
+        };
+        if let Some(value) = string("region") {
+            region = Some(parse_region(&value)?);
+        }
+
+        let vars = match value.get("vars") {
+            Some(Value::Object(vars)) => vars.clone().into_iter().collect(),
+            Some(Value::Null) | None => BTreeMap::new(),
+            Some(_) => return Err(NbconvertError::InvalidArgument("vars must be a table".to_string())),
+        };
+        for name in vars.keys() {
+            check_var_name(name)?;
+        }
+
+        Ok(Metadata {
+            title: string("title"),
+            authors,
+            date,
+            r#abstract: string("abstract"),
+            keywords,
+            lang,
+            region,
+            vars,
+        })
+    }
+
+    /// Fill the fields not set yet from `other`, the variables one by one.
+    pub fn merge(&mut self, other: Metadata) {
+        self.title = self.title.take().or(other.title);
+        if self.authors.is_empty() {
+            self.authors = other.authors;
+        }
+        self.date = self.date.or(other.date);
+        self.r#abstract = self.r#abstract.take().or(other.r#abstract);
+        if self.keywords.is_empty() {
+            self.keywords = other.keywords;
+        }
+        if self.lang.is_none() {
+            self.lang = other.lang;
+            self.region = self.region.take().or(other.region);
+        }
+        for (name, value) in other.vars {
+            self.vars.entry(name).or_insert(value);
+        }
+    }
+}
+
+impl Document {
+    /// Call `f` on every block, including the ones nested in quotes and lists.
+    pub fn visit_blocks_mut(&mut self, f: &mut impl FnMut(&mut Block)) {
+        for cell in &mut self.cells {
+            visit_blocks_mut(&mut cell.input, f);
+            visit_blocks_mut(&mut cell.outputs, f);
+        }
+    }
+
+    /// Apply the actions of the cells: drop the removed inputs and outputs, replace the
+    /// hidden ones with a placeholder. The removed cells are already left out by the
+    /// front-end, so that their front matter, downloads and diagnostics don't count.
+    pub fn apply_tag_actions(&mut self) {
+        for cell in &mut self.cells {
+            if cell.actions.contains(&TagAction::RemoveInput) {
+                cell.input.clear();
+                cell.input_lines.clear();
+            } else if cell.actions.contains(&TagAction::HideInput) {
+                cell.input = vec![Block::Placeholder("[input hidden]".to_string())];
+                cell.input_lines = vec![1];
+            }
+            if cell.actions.contains(&TagAction::RemoveOutput) {
+                cell.outputs.clear();
+            } else if cell.actions.contains(&TagAction::HideOutput) && !cell.outputs.is_empty() {
+                cell.outputs = vec![Block::Placeholder("[output hidden]".to_string())];
+            }
+        }
+    }
+
+    /// Shift the level of every heading down by `offset`, e.g. under a chapter heading.
+    pub fn shift_headings(&mut self, offset: u8) {
+        self.visit_blocks_mut(&mut |block| {
+            if let Block::Heading { level, .. } = block {
+                *level += offset;
+            }
+        });
+    }
+}
+
+fn visit_blocks_mut(blocks: &mut [Block], f: &mut impl FnMut(&mut Block)) {
+    for block in blocks {
+        f(block);
+        match block {
+            Block::BlockQuote(children) => visit_blocks_mut(children, f),
+            Block::List { items, .. } => {
+                for item in items {
+                    visit_blocks_mut(item, f);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code_cell(actions: Vec<TagAction>) -> Cell {
+        Cell {
+            index: 0,
+            id: None,
+            kind: CellKind::Code,
+            actions,
+            input: vec![Block::Output("input".to_string())],
+            input_lines: vec![1],
+            outputs: vec![Block::Output("output".to_string())],
+            source: "input".to_string(),
+        }
+    }
+
+    fn apply(actions: Vec<TagAction>) -> Cell {
+        let mut document = Document { cells: vec![code_cell(actions)], ..Document::default() };
+        document.apply_tag_actions();
+        document.cells.remove(0)
+    }
+
+    #[test]
+    fn remove_parts() {
+        let cell = apply(vec![TagAction::RemoveInput]);
+        assert!(cell.input.is_empty() && cell.input_lines.is_empty());
+        assert_eq!(cell.outputs.len(), 1);
+
+        let cell = apply(vec![TagAction::RemoveOutput]);
+        assert!(cell.outputs.is_empty());
+        assert_eq!(cell.input.len(), 1);
+    }
+
+    #[test]
+    fn hide_parts() {
+        let cell = apply(vec![TagAction::HideInput, TagAction::HideOutput]);
+        assert!(matches!(cell.input.as_slice(), [Block::Placeholder(label)] if label == "[input hidden]"));
+        assert!(matches!(cell.outputs.as_slice(), [Block::Placeholder(label)] if label == "[output hidden]"));
+
+        // Removing wins over hiding, and there is nothing to hide without outputs.
+        let mut removed = code_cell(vec![TagAction::RemoveInput, TagAction::HideInput, TagAction::HideOutput]);
+        removed.outputs.clear();
+        let mut document = Document { cells: vec![removed], ..Document::default() };
+        document.apply_tag_actions();
+        assert!(document.cells[0].input.is_empty());
+        assert!(document.cells[0].outputs.is_empty());
+    }
+
+    #[test]
+    fn no_actions() {
+        let cell = apply(Vec::new());
+        assert!(matches!(cell.input.as_slice(), [Block::Output(_)]));
+        assert!(matches!(cell.outputs.as_slice(), [Block::Output(_)]));
+    }
+}
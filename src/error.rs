@@ -1,19 +1,227 @@
+use std::fmt;
+use std::sync::Arc;
+
 use thiserror::Error;
 
+use crate::compile::CompileDiagnostic;
+use crate::fonts::MissingFont;
+use crate::packages::PackageRef;
+
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum NbconvertError {
   #[error("Failed to read notebook file: {0}")]
   IOError(#[from] std::io::Error),
-    
+
   #[error("Failed to parse notebook: {0}")]
   ParseError(#[from] nbformat::NotebookError),
 
   #[error("Image processing error: {0}")]
   ImageError(#[from] image::ImageError),
-  
-  #[error("OnceLock already initialized: {0}")]
-  OnceLockError(String),
+
+  #[error("Failed to parse config file: {0}")]
+  ConfigError(#[from] toml::de::Error),
+
+  #[error("Invalid argument: {0}")]
+  InvalidArgument(String),
+
+  #[error("{location}: failed to parse markdown: {message}")]
+  MarkdownError { location: CellLocation, message: String },
+
+  #[error("{location}: invalid front matter: {message}")]
+  FrontMatterError { location: CellLocation, message: String },
+
+  #[error("Invalid notebook metadata: {0}")]
+  MetadataError(String),
+
+  #[error("{location}: unresolved reference [{label}]")]
+  ReferenceError { location: CellLocation, label: String },
+
+  #[error("{location}: unmatched HTML tag {tag}")]
+  UnmatchedTagError { location: CellLocation, tag: String },
+
+  #[error("{location}: unsupported HTML {tag}, skipped")]
+  UnsupportedHtmlError { location: CellLocation, tag: String },
+
+  #[error("{location}: unsupported output of type {mime}, {action}")]
+  UnsupportedOutputError { location: CellLocation, mime: String, action: String },
+
+  #[error("{location}: no representation of the output ({mimes}) allowed by mime-priority, {action}")]
+  ExcludedOutputError { location: CellLocation, mimes: String, action: String },
+
+  #[error("{location}: raw cell of format {format} can't be rendered, dropped")]
+  UnsupportedRawError { location: CellLocation, format: String },
+
+  #[error("{location}: raw cell without a format, dropped; set its raw_mimetype to text/typst to keep it")]
+  UntypedRawError { location: CellLocation },
+
+  #[error("{location}: raw HTML skipped, only its tables are converted")]
+  RawHtmlError { location: CellLocation },
+
+  #[error("{location}: invalid media data: {message}")]
+  MediaError { location: CellLocation, message: String },
+
+  #[error("{location}: the cell raised {ename}: {evalue}, tag it raises-exception if expected")]
+  ExecutionError { location: CellLocation, ename: String, evalue: String },
+
+  #[error("{location}: failed to download {url}: {message}")]
+  DownloadError { location: CellLocation, url: String, message: String },
+
+  #[error("{0}")]
+  MissingFontError(MissingFont),
+
+  #[error("Failed to run the typst compiler: {0}")]
+  CompileError(String),
+
+  #[error("typst failed with {} error(s)", .0.iter().filter(|d| d.severity == Severity::Error).count())]
+  TypstError(Vec<CompileDiagnostic>),
+
+  #[error("packages not installed, and not downloaded offline: {}; install them with `nbconvert-rust packages install ARCHIVE`", .0.iter().map(|package| package.to_string()).collect::<Vec<_>>().join(", "))]
+  OfflineError(Vec<PackageRef>),
+
+  #[error("{0} diagnostic(s) reported in strict mode")]
+  StrictError(usize),
+
+  #[error("{0} notebook(s) failed to convert")]
+  BatchError(usize),
 }
 
 pub type Result<T> = std::result::Result<T, NbconvertError>;
+
+
+/// A span in the source of a cell. Lines and columns are 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: u32,
+    pub column: u32,
+    pub end_line: u32,
+    pub end_column: u32,
+}
+
+/// The cell (and optionally the span in its source) a diagnostic comes from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CellLocation {
+    /// The notebook, only set when several notebooks are converted together.
+    pub notebook: Option<Arc<str>>,
+    /// The 0-based index of the cell in the notebook.
+    pub index: usize,
+    /// The `id` of the cell, only in nbformat >= 4.5.
+    pub id: Option<Arc<str>>,
+    pub span: Option<Span>,
+}
+
+impl fmt::Display for CellLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(notebook) = &self.notebook {
+            write!(f, "{}: ", notebook)?;
+        }
+        write!(f, "cell {}", self.index)?;
+        if let Some(id) = &self.id {
+            write!(f, " ({})", id)?;
+        }
+        if let Some(span) = &self.span {
+            write!(f, ", line {}:{}", span.line, span.column)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// An error or warning collected during the conversion.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub error: NbconvertError,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.error),
+            Severity::Error => write!(f, "error: {}", self.error),
+        }
+    }
+}
+
+/// All the diagnostics of a conversion.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn warn(&mut self, error: NbconvertError) {
+        self.items.push(Diagnostic { severity: Severity::Warning, error });
+    }
+
+    pub fn error(&mut self, error: NbconvertError) {
+        self.items.push(Diagnostic { severity: Severity::Error, error });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.items.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Move the diagnostics of `other` to the end of these.
+    pub fn append(&mut self, other: Diagnostics) {
+        self.items.extend(other.items);
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.items.iter().any(|d| d.severity == Severity::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(notebook: Option<&str>, id: Option<&str>, span: Option<Span>) -> CellLocation {
+        CellLocation { notebook: notebook.map(Arc::from), index: 3, id: id.map(Arc::from), span }
+    }
+
+    #[test]
+    fn cell_locations() {
+        let span = Span { line: 2, column: 5, end_line: 2, end_column: 9 };
+        assert_eq!(location(None, None, None).to_string(), "cell 3");
+        assert_eq!(location(None, Some("a1b2"), Some(span)).to_string(), "cell 3 (a1b2), line 2:5");
+        assert_eq!(location(Some("ch1.ipynb"), Some("a1b2"), None).to_string(), "ch1.ipynb: cell 3 (a1b2)");
+    }
+
+    #[test]
+    fn severities() {
+        let mut diagnostics = Diagnostics::default();
+        assert!(diagnostics.is_empty());
+        diagnostics.warn(NbconvertError::InvalidArgument("unmatched tag".to_string()));
+        assert!(!diagnostics.has_errors());
+
+        let mut other = Diagnostics::default();
+        other.error(NbconvertError::MarkdownError { location: location(None, Some("a1b2"), None), message: "bad".to_string() });
+        diagnostics.append(other);
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics.has_errors());
+
+        let lines: Vec<String> = diagnostics.iter().map(Diagnostic::to_string).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "warning: Invalid argument: unmatched tag",
+                "error: cell 3 (a1b2): failed to parse markdown: bad",
+            ]
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::fmt;
+
+use crate::typst_content::Fonts;
+
+/// The fonts of `default-fonts` in `nbconvert.typ`, per role.
+pub const DEFAULT_FONTS: [(&str, &[&str]); 4] = [
+    ("body", &["New Computer Modern"]),
+    ("cjk", &["SimSun"]),
+    ("emphasis", &["KaiTi"]),
+    ("mono", &["Maple Mono NF"]),
+];
+
+/// Common families per role, the alternatives to a missing font when no name is close.
+const ALTERNATIVES: [(&str, &[&str]); 4] = [
+    ("body", &["New Computer Modern", "Libertinus Serif", "Latin Modern Roman", "Times New Roman", "DejaVu Serif"]),
+    (
+        "cjk",
+        &[
+            "SimSun", "Noto Serif CJK SC", "Source Han Serif SC", "Songti SC", "STSong", "Noto Sans CJK SC",
+            "Source Han Sans SC", "Microsoft YaHei", "PingFang SC", "WenQuanYi Zen Hei",
+        ],
+    ),
+    ("emphasis", &["KaiTi", "STKaiti", "Kaiti SC", "LXGW WenKai", "AR PL UKai CN", "FangSong", "Noto Serif CJK SC"]),
+    (
+        "mono",
+        &[
+            "Maple Mono NF", "Maple Mono", "Fira Code", "JetBrains Mono", "Cascadia Code", "Source Code Pro", "Consolas",
+            "Menlo", "DejaVu Sans Mono",
+        ],
+    ),
+];
+
+/// A requested font family the compiler doesn't have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingFont {
+    /// `body`, `cjk`, `emphasis` or `mono`.
+    pub role: &'static str,
+    pub family: String,
+    /// The closest available family: the next one of the role, or one with a close name,
+    /// or else a common one of the same role.
+    pub closest: Option<String>,
+}
+
+impl fmt::Display for MissingFont {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "font \"{}\" ({}) is not available to typst", self.family, self.role)?;
+        match &self.closest {
+            Some(closest) => write!(f, ", the closest is \"{}\"", closest),
+            None => write!(f, ", and no close family is available"),
+        }
+    }
+}
+
+impl Fonts {
+    /// The families per role, the unset roles have the default ones.
+    pub fn families(&self) -> Vec<(&'static str, Vec<String>)> {
+        let set = [&self.body, &self.cjk, &self.emphasis, &self.mono];
+        DEFAULT_FONTS
+            .iter()
+            .zip(set)
+            .map(|((role, defaults), families)| {
+                let families = families
+                    .clone()
+                    .unwrap_or_else(|| defaults.iter().map(|family| family.to_string()).collect());
+                (*role, families)
+            })
+            .collect()
+    }
+}
+
+/// The families of `fonts` not in `available`, compared regardless of case as Typst does.
+pub fn missing_fonts(fonts: &Fonts, available: &[String]) -> Vec<MissingFont> {
+    let find = |family: &str| available.iter().find(|name| name.to_lowercase() == family.to_lowercase());
+
+    let mut missing = Vec::new();
+    for (role, families) in fonts.families() {
+        // Typst falls back to the next family of the list, if any.
+        let fallback = families.iter().find_map(|family| find(family)).cloned();
+        for family in families {
+            if find(&family).is_none() {
+                let closest = fallback.clone().or_else(|| closest_family(role, &family, available));
+                missing.push(MissingFont { role, family, closest });
+            }
+        }
+    }
+    missing
+}
+
+/// The available family with the closest name, e.g. `Maple Mono` for `Maple Mono NF`,
+/// or else the first available common family of the role.
+fn closest_family(role: &str, family: &str, available: &[String]) -> Option<String> {
+    let wanted = normalize(family);
+    let by_name = available
+        .iter()
+        .map(|name| (name, normalize(name)))
+        .filter(|(_, name)| !name.is_empty())
+        .map(|(name, normalized)| {
+            let distance = if normalized.contains(&wanted) || wanted.contains(&normalized) {
+                wanted.len().abs_diff(normalized.len())
+            } else {
+                levenshtein(&wanted, &normalized)
+            };
+            (distance, name)
+        })
+        .filter(|(distance, _)| *distance * 3 <= wanted.len())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name.clone());
+
+    by_name.or_else(|| {
+        let (_, alternatives) = ALTERNATIVES.iter().find(|(name, _)| *name == role)?;
+        alternatives.iter().find_map(|alternative| {
+            available.iter().find(|name| normalize(name) == normalize(alternative)).cloned()
+        })
+    })
+}
+
+/// The family in lowercase without spaces, to compare the names loosely.
+fn normalize(family: &str) -> String {
+    family.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).collect()
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb { previous } else { 1 + previous.min(row[j]).min(row[j + 1]) };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn missing_default_fonts() {
+        let available = names(&["new computer modern", "Maple Mono", "Noto Serif CJK SC", "DejaVu Sans"]);
+        let missing = missing_fonts(&Fonts::default(), &available);
+        let found: Vec<(&str, &str, Option<&str>)> = missing
+            .iter()
+            .map(|font| (font.role, font.family.as_str(), font.closest.as_deref()))
+            .collect();
+        // The body font is found regardless of case.
+        assert_eq!(
+            found,
+            vec![
+                ("cjk", "SimSun", Some("Noto Serif CJK SC")),
+                ("emphasis", "KaiTi", Some("Noto Serif CJK SC")),
+                ("mono", "Maple Mono NF", Some("Maple Mono")),
+            ]
+        );
+    }
+
+    #[test]
+    fn fallback_of_the_list() {
+        let fonts = Fonts { body: Some(names(&["Libertinus Serif", "DejaVu Serif"])), ..Fonts::default() };
+        let available = names(&["DejaVu Serif", "New Computer Modern", "SimSun", "KaiTi", "Maple Mono NF"]);
+        let missing = missing_fonts(&fonts, &available);
+        // Typst uses the next family of the list.
+        assert_eq!(missing.len(), 1);
+        assert_eq!((missing[0].family.as_str(), missing[0].closest.as_deref()), ("Libertinus Serif", Some("DejaVu Serif")));
+        assert!(missing[0].to_string().contains("the closest is \"DejaVu Serif\""));
+    }
+
+    #[test]
+    fn closest_families() {
+        let available = names(&["Fira Code", "JetBrains Mono", "Source Han Serif SC"]);
+        // A close name, a typo, then a common family of the role.
+        assert_eq!(closest_family("mono", "Fira Code Retina", &available).as_deref(), Some("Fira Code"));
+        assert_eq!(closest_family("mono", "JetBrain Mono", &available).as_deref(), Some("JetBrains Mono"));
+        assert_eq!(closest_family("mono", "Inconsolata", &available).as_deref(), Some("Fira Code"));
+        assert_eq!(closest_family("cjk", "SimSun", &available).as_deref(), Some("Source Han Serif SC"));
+        assert_eq!(closest_family("body", "Garamond", &available), None);
+        let missing = MissingFont { role: "body", family: "Garamond".to_string(), closest: None };
+        assert!(missing.to_string().ends_with("and no close family is available"));
+    }
+}
@@ -0,0 +1,42 @@
+//! Convert Jupyter notebooks to Typst, to be compiled to PDF.
+//!
+//! The entry point is [`Converter`], which takes a notebook (or its path)
+//! and returns the Typst source along with the assets it refers to.
+//!
+//! Internally, the notebook is first converted to a document model of cells,
+//! blocks and inlines, which is then written as Typst. The model is not part
+//! of the API.
+
+mod book;
+pub mod compile;
+mod config;
+mod context;
+mod converter;
+mod document;
+mod error;
+pub mod fonts;
+mod locale;
+mod markdown;
+mod media;
+mod notebook;
+pub mod packages;
+mod source_map;
+mod tags;
+pub mod template;
+mod typst_content;
+mod typst_writer;
+mod vars;
+#[cfg(feature = "embed-typst")]
+mod world;
+
+pub use book::{Chapter, Manifest};
+pub use compile::{CompileDiagnostic, CompileOptions, ExportOptions, OutputFormat};
+pub use config::Config;
+pub use converter::{Conversion, Converter};
+pub use error::{CellLocation, Diagnostic, Diagnostics, NbconvertError, Result, Severity, Span};
+pub use media::{parse_fallback, FallbackAction};
+pub use notebook::read_notebook;
+pub use source_map::{CellPosition, CellSource, SourceMap, SourceRange};
+pub use tags::{parse_tag_action, TagAction};
+pub use typst_content::{Author, Date, Fonts};
+pub use vars::{parse_var, parse_var_json};
@@ -0,0 +1,162 @@
+use crate::error::{NbconvertError, Result};
+
+/// The language when none is given and the text has too few CJK characters.
+pub const DEFAULT_LANG: &str = "en";
+
+/// Check an ISO 639 language code, e.g. `zh`, and return it in lowercase.
+/// A region may follow it, as in `zh-CN` or `en_GB`: it is returned too.
+pub fn parse_lang(lang: &str) -> Result<(String, Option<String>)> {
+    let lang = lang.trim();
+    let (code, region) = match lang.split_once(['-', '_']) {
+        Some((code, region)) => (code, Some(parse_region(region)?)),
+        None => (lang, None),
+    };
+    if !(2..=3).contains(&code.len()) || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(NbconvertError::InvalidArgument(format!(
+            "invalid language \"{}\", expected an ISO 639 code such as en or zh",
+            lang
+        )));
+    }
+    Ok((code.to_ascii_lowercase(), region))
+}
+
+/// Check an ISO 3166-1 alpha-2 region code, e.g. `CN`, and return it in lowercase.
+pub fn parse_region(region: &str) -> Result<String> {
+    let region = region.trim();
+    if region.len() != 2 || !region.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(NbconvertError::InvalidArgument(format!(
+            "invalid region \"{}\", expected an ISO 3166 code such as us or cn",
+            region
+        )));
+    }
+    Ok(region.to_ascii_lowercase())
+}
+
+/// The number of characters per CJK script in some text, and of the other words,
+/// to guess its language.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ScriptCounts {
+    pub han: usize,
+    pub kana: usize,
+    pub hangul: usize,
+    /// The words in other scripts, e.g. Latin.
+    pub words: usize,
+}
+
+impl ScriptCounts {
+    /// Count the characters of markdown text, the code (`` `...` ``) and the math (`$...$`)
+    /// are skipped.
+    pub fn add_text(&mut self, text: &str) {
+        let (mut code, mut math, mut in_word) = (false, false, false);
+        for c in text.chars() {
+            let word_char = match c {
+                '`' => {
+                    code = !code;
+                    false
+                }
+                '$' if !code => {
+                    math = !math;
+                    false
+                }
+                _ if code || math => false,
+                _ => match c as u32 {
+                    0x3040..=0x30FF | 0x31F0..=0x31FF => {
+                        self.kana += 1;
+                        false
+                    }
+                    0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => {
+                        self.hangul += 1;
+                        false
+                    }
+                    0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0x20000..=0x2FA1F => {
+                        self.han += 1;
+                        false
+                    }
+                    _ => c.is_alphabetic(),
+                },
+            };
+            if word_char && !in_word {
+                self.words += 1;
+            }
+            in_word = word_char;
+        }
+    }
+
+    /// `zh`, `ja` or `ko` if there are at least as many CJK characters as other words,
+    /// a CJK character being about a word.
+    pub fn detect(&self) -> Option<&'static str> {
+        let cjk = self.han + self.kana + self.hangul;
+        if cjk == 0 || cjk < self.words {
+            return None;
+        }
+        // Japanese mixes kana with the kanji, Chinese only rarely quotes some.
+        Some(if self.kana * 10 > cjk {
+            "ja"
+        } else if self.hangul > self.han {
+            "ko"
+        } else {
+            "zh"
+        })
+    }
+}
+
+/// The Typst `display` format of the date in the preface for a language and a region.
+/// Typst only names the months in English, so the other languages use numbers.
+pub fn date_format(lang: &str, region: Option<&str>) -> &'static str {
+    match (lang, region) {
+        ("zh" | "ja", _) => "[year]年[month padding:space]月[day padding:space]日",
+        ("ko", _) => "[year]년 [month padding:none]월 [day padding:none]일",
+        ("en", Some("gb" | "au" | "nz" | "ie" | "in" | "za")) => "[day padding:none] [month repr:long] [year]",
+        ("en", _) => "[month repr:long] [day padding:none], [year]",
+        ("de" | "ru" | "pl" | "cs" | "fi" | "nb" | "da" | "tr" | "uk", _) => "[day].[month].[year]",
+        ("fr" | "es" | "it" | "pt" | "el" | "vi", _) => "[day]/[month]/[year]",
+        ("nl", _) => "[day]-[month]-[year]",
+        _ => "[year]-[month]-[day]",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lang(lang: &str) -> (String, Option<String>) {
+        parse_lang(lang).unwrap()
+    }
+
+    fn detect(text: &str) -> Option<&'static str> {
+        let mut counts = ScriptCounts::default();
+        counts.add_text(text);
+        counts.detect()
+    }
+
+    #[test]
+    fn languages_and_regions() {
+        assert_eq!(lang("en"), ("en".to_string(), None));
+        assert_eq!(lang(" ZH "), ("zh".to_string(), None));
+        assert_eq!(lang("zh-TW"), ("zh".to_string(), Some("tw".to_string())));
+        assert_eq!(lang("en_GB"), ("en".to_string(), Some("gb".to_string())));
+        assert_eq!(lang("fil"), ("fil".to_string(), None));
+        for invalid in ["", "e", "engl", "e1", "zh-TWN", "zh-", "zh-1a"] {
+            assert!(parse_lang(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(parse_region(" CN").unwrap(), "cn");
+        assert!(parse_region("usa").is_err());
+    }
+
+    #[test]
+    fn cjk_detection() {
+        assert_eq!(detect("# 实验报告\n本实验测量了摆的周期。"), Some("zh"));
+        assert_eq!(detect("# 実験レポート\nこの実験では振り子の周期を測定した。"), Some("ja"));
+        assert_eq!(detect("# 실험 보고서\n이 실험에서는 진자의 주기를 측정했다."), Some("ko"));
+        assert_eq!(detect("# Lab report\nWe measured the period of a pendulum."), None);
+        assert_eq!(detect(""), None);
+    }
+
+    #[test]
+    fn cjk_detection_skips_code_and_math() {
+        // A few CJK words in an English text.
+        assert_eq!(detect("The period (周期) of a pendulum, measured twice in the lab."), None);
+        // The English of the code and the math doesn't count.
+        assert_eq!(detect("摆的周期 `period = measure(pendulum, times=10)` $T = 2 \\pi \\sqrt{l / g}$"), Some("zh"));
+    }
+}
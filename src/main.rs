@@ -1,126 +1,945 @@
-mod error;
-mod notebook;
-mod media;
-mod typst_content;
-mod markdown;
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs, path::{Component, Path, PathBuf}, process::ExitCode, sync::Mutex};
 
-use std::{fs::{self, File}, io::Write, path::{Path, PathBuf}, process::Command};
+use nbconvert_rust::{compile::{compile, font_families, has_page_pattern, CompileOptions, ExportOptions, OutputFormat, PageRange}, fonts::missing_fonts, packages::{export_packages, find_package, install_archive, package_cache_dir, template_packages}, template::{builtin_template, template_source, write_support_files, BUILTIN_TEMPLATES}, parse_fallback, parse_tag_action, parse_var, parse_var_json, read_notebook, Chapter, Config, Converter, Diagnostics, Fonts, Manifest, NbconvertError, Result, SourceMap};
 
-use notebook::convert_notebook;
-
-use crate::{error::Result, typst_content::Author};
-
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use nbformat::Notebook;
+use rayon::prelude::*;
 
 /// CLI Arguments
 #[derive(Parser, Debug)]
-#[command(author, version, about = "Convert a Jupyter Notebook to Typst, and compile it to PDF.", long_about = "This tool reads a Jupyter Notebook (.ipynb) file, converts it to Typst format, and compiles it to a PDF document. You can specify the title, authors, emails, affiliations, and date of the document. The output will be saved as a Typst file (.typ) and a PDF file.
+#[command(author, version, about = "Convert a Jupyter Notebook to Typst, and compile it to PDF.", long_about = "This tool reads a Jupyter Notebook (.ipynb) file, converts it to Typst format, and compiles it to a PDF document, or with --to to PNG or SVG images of the pages, or HTML. You can specify the title, authors and date of the document. The output will be saved as a Typst file (.typ) and a PDF file, or only as the Typst file with --to typst.
 
-Tips: You need to install the typst command line tool. You also need to install several fonts, including: \"New Computer Modern\", \"SimSun\", \"KaiTi\", \"Maple Mono NF\".
-")]
+Tips: Unless built with the embed-typst feature, you need to install the typst command line tool, or give its path with --typst-bin. The default fonts are \"New Computer Modern\", \"SimSun\", \"KaiTi\" and \"Maple Mono NF\": install them, or choose others with --font-body, --font-cjk, --font-emphasis and --font-mono. `nbconvert-rust fonts` shows the ones typst can't find.
+", subcommand_negates_reqs = true)]
 struct Args {
-    /// Input notebook path (.ipynb)
-    #[arg(short, long)]
-    input: PathBuf,
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// Input notebook paths (.ipynb), directories or glob patterns
+    #[arg(short, long, num_args = 1.., required_unless_present = "manifest")]
+    input: Vec<String>,
+
+    /// Merge the input notebooks, in order, into one book with a chapter per notebook
+    #[arg(long)]
+    book: bool,
 
-    /// Output pdf file path (.pdf)
+    /// Book manifest (.toml or .yaml) listing the chapters, implies --book
+    #[arg(long, conflicts_with = "input")]
+    manifest: Option<PathBuf>,
+
+    /// Output file path, only for a single notebook or a book. Its extension gives the format
+    /// unless --to is given; for png and svg, {p} in the name is the page number, {0p} the
+    /// zero-padded one and {t} the number of pages [default: -{p} before the extension]
     #[arg(short, long)]
     output: Option<PathBuf>,
 
-    /// Title of the document
-    #[arg(long, default_value = "Untitled Notebook")]
-    title: String,
+    /// Output format: typst (the source and its assets only), pdf, png or svg (an image per
+    /// page), or html (with a typst binary 0.13 or later) [default: from the --output
+    /// extension, else pdf]
+    #[arg(long, value_name = "FORMAT")]
+    to: Option<String>,
+
+    /// Pages to export, e.g. "1-3,5,8-" [default: all]
+    #[arg(long)]
+    pages: Option<String>,
+
+    /// Resolution of the png images, in pixels per inch [default: 144]
+    #[arg(long)]
+    dpi: Option<f32>,
+
+    /// Output directory, defaults to the directory of each notebook
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
 
-    /// Author list, e.g. "Zhang San, Si Li"
-    #[arg(long, default_value = "Anonymous")]
-    authors: String,
+    /// Directory of the figures and downloaded files, a relative path under the output directory
+    /// [default: downloads]
+    #[arg(long)]
+    downloads_dir: Option<PathBuf>,
 
-    /// Emails for authors, split by ',', or empty
-    #[arg(long, default_value = "")]
-    emails: String,
+    /// Title of the document, defaults to the title in the front matter or the notebook
+    /// metadata, the first heading or the file name
+    #[arg(long)]
+    title: Option<String>,
 
-    /// Affiliations for authors, split by ',', or empty
-    #[arg(long, default_value = "")]
-    affiliations: String,
+    /// Author as "Name <email> (Affiliation) [orcid: ..., student-id: ..., corresponding]",
+    /// repeatable, defaults to the authors in the front matter or the notebook metadata, or "Anonymous"
+    #[arg(long = "author", value_name = "AUTHOR")]
+    authors: Vec<String>,
 
-    /// Date in format YYYY-MM-DD
+    /// Authors file (.toml with [[authors]] tables, or .json), read before --author
+    #[arg(long)]
+    authors_file: Option<PathBuf>,
+
+    /// Date as YYYY-MM-DD, today, file-mtime (the last change of the notebook) or
+    /// SOURCE_DATE_EPOCH (the environment variable), defaults to the date in the front matter
+    /// or the day of compilation
     #[arg(long)]
     date: Option<String>,
+
+    /// Language of the document for the date format, the hyphenation and the labels, e.g. en
+    /// or zh-CN, defaults to the lang of the front matter, or to zh, ja or ko for a notebook
+    /// mostly written in CJK characters, or else en
+    #[arg(long)]
+    lang: Option<String>,
+
+    /// Region of the document, e.g. us or gb
+    #[arg(long)]
+    region: Option<String>,
+
+    /// Template: article (default), report, homework, minimal, or a .typ file defining `project`
+    #[arg(long)]
+    template: Option<String>,
+
+    /// Paper size, e.g. "a4" or "us-letter" [default: a4]
+    #[arg(long)]
+    paper: Option<String>,
+
+    /// Turn the pages to landscape
+    #[arg(long, overrides_with = "no_landscape")]
+    landscape: bool,
+
+    /// Keep the pages in portrait, over a landscape set in a config or the notebook
+    #[arg(long, overrides_with = "landscape")]
+    no_landscape: bool,
+
+    /// Body font families, split by ',' [default: New Computer Modern]
+    #[arg(long, value_name = "FAMILIES")]
+    font_body: Option<String>,
+
+    /// CJK font families, after the body ones, split by ',' [default: SimSun]
+    #[arg(long, value_name = "FAMILIES")]
+    font_cjk: Option<String>,
+
+    /// Emphasized CJK font families, split by ',' [default: KaiTi]
+    #[arg(long, value_name = "FAMILIES")]
+    font_emphasis: Option<String>,
+
+    /// Code and output font families, split by ',' [default: Maple Mono NF]
+    #[arg(long, value_name = "FAMILIES")]
+    font_mono: Option<String>,
+
+    /// Directory of fonts given to typst, e.g. fonts bundled with the project, repeatable
+    #[arg(long)]
+    font_path: Vec<PathBuf>,
+
+    /// Compile with this typst binary instead of the embedded compiler
+    /// [default: the embedded compiler if built with the embed-typst feature, else typst]
+    #[arg(long, value_name = "PATH")]
+    typst_bin: Option<PathBuf>,
+
+    /// Directory of the Typst package cache, where the packages are installed and downloaded
+    /// [default: typst/packages in the user cache directory]
+    #[arg(long, value_name = "DIR")]
+    package_cache: Option<PathBuf>,
+
+    /// Never download a Typst package, fail if one the document imports isn't installed
+    #[arg(long, overrides_with = "no_offline")]
+    offline: bool,
+
+    /// Download the missing Typst packages, over an offline set in a config
+    #[arg(long, overrides_with = "offline")]
+    no_offline: bool,
+
+    /// MIME display priority, split by ',', e.g. "svg,png,latex,html,plain"
+    #[arg(long)]
+    mime_priority: Option<String>,
+
+    /// Fallback for an unsupported MIME type as mime=action (code, placeholder or drop), repeatable
+    #[arg(long)]
+    mime_fallback: Vec<String>,
+
+    /// Action on the cells with a tag as tag=action (remove-cell, remove-input, remove-output,
+    /// hide-input, hide-output or raises-exception), repeatable
+    #[arg(long)]
+    tag_action: Vec<String>,
+
+    /// Template variable as key=value, passed to `project` as a named argument, repeatable.
+    /// The value is a bool, a number or a string, or typed as key:TYPE=value with TYPE one of
+    /// str, int, float, bool or path (a file copied next to the output)
+    #[arg(long, value_name = "KEY=VALUE")]
+    var: Vec<String>,
+
+    /// Template variable as key=JSON, e.g. 'tags=["a", "b"]', repeatable
+    #[arg(long, value_name = "KEY=JSON")]
+    var_json: Vec<String>,
+
+    /// Render only what is shown in Jupyter: hide the collapsed sources and outputs,
+    /// cut the scrolled outputs
+    #[arg(long, overrides_with = "no_respect_hidden")]
+    respect_hidden: bool,
+
+    /// Render the collapsed and scrolled parts in full, over a respect-hidden set in a config
+    /// or the notebook
+    #[arg(long, overrides_with = "respect_hidden")]
+    no_respect_hidden: bool,
+
+    /// Config file path (.toml), defaults to the nearest nbconvert-rust.toml found from the
+    /// notebook directory up, the user config is merged under it
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Fail with a non-zero exit code if any warning or error is reported, including an
+    /// error output of a cell not tagged raises-exception
+    #[arg(long, overrides_with = "no_strict")]
+    strict: bool,
+
+    /// Only fail on an error, over a strict set in a config
+    #[arg(long, overrides_with = "strict")]
+    no_strict: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// List the fonts typst can use, and check the ones of the document
+    Fonts {
+        /// Notebook, or directory from which the project config is searched
+        #[arg(default_value = ".")]
+        path: PathBuf,
+    },
+    /// Install, list and export the Typst packages of the template, for offline use
+    Packages {
+        #[command(subcommand)]
+        command: PackagesCommand,
+    },
+    /// Write a built-in template to a file, to customize it and use it with --template
+    InitTemplate {
+        /// Built-in template: article, report, homework or minimal
+        #[arg(default_value = "article")]
+        name: String,
+
+        /// Template file to write
+        #[arg(default_value = "template.typ")]
+        path: PathBuf,
+
+        /// Replace the file if it exists
+        #[arg(long)]
+        force: bool,
+
+        /// List the built-in templates and exit
+        #[arg(long)]
+        list: bool,
+    },
+}
 
-fn main() -> Result<()> {
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the effective configuration: the CLI flags over the notebook settings over the
+    /// project config over the user config
+    Show {
+        /// Notebook, or directory from which the project config is searched
+        #[arg(default_value = ".")]
+        path: PathBuf,
+    },
+}
 
-    let args = Args::parse();
+#[derive(Subcommand, Debug)]
+enum PackagesCommand {
+    /// List the packages of the template, and where they are installed
+    List,
+    /// Install packages from .tar.gz archives into the package cache
+    Install {
+        /// Package archives, from the Typst registry or made by `packages export`
+        #[arg(required = true)]
+        archives: Vec<PathBuf>,
+    },
+    /// Write the installed packages of the template to a .tar.gz archive
+    Export {
+        #[arg(default_value = "nbconvert-packages.tar.gz")]
+        archive: PathBuf,
+    },
+}
 
 
-    let title = args.title;
-    
-    let mut authors: Vec<Author> = Vec::new();
-    for ((author, email), affiliation) in args.authors.split(',').zip(args.emails.split(',')).zip(args.affiliations.split(',')) {
-        authors.push(Author {
-            name: author.trim().to_string(),
-            email: if email.trim().is_empty() { None } else { Some(email.trim().to_string()) },
-            affiliation: if affiliation.trim().is_empty() { None } else { Some(affiliation.trim().to_string()) },
-        });
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            ExitCode::FAILURE
+        }
     }
+}
 
-    let date = args.date.as_ref().map(|d| {
-        let parts: Vec<&str> = d.split('-').collect();
-        if parts.len() == 3 {
-            Some(typst_content::Date {
-                year: parts[0].parse().unwrap_or(2023),
-                month: parts[1].parse().unwrap_or(1),
-                day: parts[2].parse().unwrap_or(1),
-            })
+/// A notebook to convert, and where its outputs go.
+struct Job {
+    input: PathBuf,
+    /// The output, a pattern for a paged format, see [`output_paths`].
+    output: PathBuf,
+    typ_output: PathBuf,
+    download_dir: PathBuf,
+    /// How the Typst source refers to `download_dir`.
+    asset_prefix: String,
+    /// The config files of the notebook, from the highest to the lowest precedence.
+    config_files: Vec<PathBuf>,
+}
+
+fn run(args: Args) -> Result<()> {
+    match &args.command {
+        Some(Commands::Config { command: ConfigCommand::Show { path } }) => return show_config(&args, path),
+        Some(Commands::Fonts { path }) => return list_fonts(&args, path),
+        Some(Commands::Packages { command }) => return manage_packages(&args, command),
+        Some(Commands::InitTemplate { name, path, force, list }) => return init_template(name, path, *force, *list),
+        None => {}
+    }
+
+    if args.book || args.manifest.is_some() {
+        return run_book(&args);
+    }
+
+    let inputs = collect_inputs(&args.input)?;
+
+    if inputs.len() > 1 && args.output.is_some() {
+        return Err(NbconvertError::InvalidArgument(
+            "--output only accepts a single notebook, use --output-dir instead".to_string(),
+        ));
+    }
+
+    // In batch mode every notebook has its own downloads directory.
+    let batch = inputs.len() > 1;
+    let mut jobs: Vec<Job> = Vec::new();
+    for input in inputs {
+        let config_files = find_config(&args, input.parent().unwrap_or(Path::new(".")));
+        jobs.push(plan_job(&args, input, config_files, batch)?);
+    }
+
+    if !batch {
+        return convert_job(&args, &jobs[0], false).map(|_| ());
+    }
+
+    check_collisions(&jobs)?;
+    write_shared_files(&args, &jobs)?;
+
+    let results: Vec<Result<PathBuf>> = jobs
+        .par_iter()
+        .map(|job| convert_job(&args, job, true))
+        .collect();
+
+    print_summary(&jobs, &results);
+
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    if failed > 0 {
+        return Err(NbconvertError::BatchError(failed));
+    }
+    Ok(())
+}
+
+/// Fail if two notebooks of a batch would be written to the same files, e.g. `a/x.ipynb` and
+/// `b/x.ipynb` with `--output-dir`.
+fn check_collisions(jobs: &[Job]) -> Result<()> {
+    let normalize = |path: &Path| -> PathBuf { path.components().filter(|c| *c != Component::CurDir).collect() };
+    let mut outputs: HashMap<PathBuf, &Path> = HashMap::new();
+    for job in jobs {
+        if let Some(other) = outputs.insert(normalize(&job.typ_output), &job.input) {
+            return Err(NbconvertError::InvalidArgument(format!(
+                "{} and {} would both be written to {}, rename one or convert them separately",
+                other.display(),
+                job.input.display(),
+                job.typ_output.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Write `nbconvert.typ` and the template once per output directory, before the notebooks
+/// of a batch are converted in parallel. The notebooks of a directory must use the same
+/// template. A notebook that can't be read is left to fail in its job.
+fn write_shared_files(args: &Args, jobs: &[Job]) -> Result<()> {
+    let mut templates: BTreeMap<PathBuf, (Option<String>, &Path)> = BTreeMap::new();
+    for job in jobs {
+        let Ok(notebook) = read_notebook(&job.input) else {
+            continue;
+        };
+        let Ok(config) = notebook_layers(args, &notebook, &job.input, &job.config_files) else {
+            continue;
+        };
+        let Ok(template) = config.template.as_deref().map(template_source).transpose() else {
+            continue;
+        };
+        let dir = job.typ_output.parent().unwrap_or(Path::new(".")).to_path_buf();
+        match templates.get(&dir) {
+            Some((other, input)) if *other != template => {
+                return Err(NbconvertError::InvalidArgument(format!(
+                    "{} and {} use different templates but share the output directory {}, give them different output directories",
+                    input.display(),
+                    job.input.display(),
+                    dir.display()
+                )));
+            }
+            Some(_) => {}
+            None => {
+                templates.insert(dir, (template, &job.input));
+            }
+        }
+    }
+    for (dir, (template, _)) in &templates {
+        fs::create_dir_all(dir)?;
+        write_support_files(dir, template.as_deref())?;
+    }
+    Ok(())
+}
+
+/// Expand the inputs: a directory gives the notebooks in it, a glob pattern the matching files.
+fn collect_inputs(patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut inputs: Vec<PathBuf> = Vec::new();
+
+    for pattern in patterns {
+        let path = Path::new(pattern);
+        if path.is_dir() {
+            let mut notebooks: Vec<PathBuf> = fs::read_dir(path)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "ipynb"))
+                .collect();
+            notebooks.sort();
+            inputs.extend(notebooks);
+        } else if path.exists() {
+            inputs.push(path.to_path_buf());
+        } else if pattern.contains(['*', '?', '[']) {
+            let paths = glob::glob(pattern)
+                .map_err(|e| NbconvertError::InvalidArgument(format!("invalid glob pattern {}: {}", pattern, e)))?;
+            inputs.extend(paths.filter_map(|path| path.ok()).filter(|path| path.is_file()));
         } else {
-            None
+            return Err(NbconvertError::InvalidArgument(format!("no such notebook: {}", pattern)));
+        }
+    }
+
+    // The same notebook may be given twice, e.g. by a directory and a glob.
+    let mut seen = HashSet::new();
+    inputs.retain(|path| {
+        let normalized: PathBuf = path.components().filter(|c| *c != Component::CurDir).collect();
+        seen.insert(normalized)
+    });
+    if inputs.is_empty() {
+        return Err(NbconvertError::InvalidArgument("no notebook found in the inputs".to_string()));
+    }
+    Ok(inputs)
+}
+
+/// Where the outputs of a notebook go, from the CLI arguments and the config files.
+fn plan_job(args: &Args, input: PathBuf, config_files: Vec<PathBuf>, batch: bool) -> Result<Job> {
+    let config = cli_config(args)?.load(&config_files)?;
+    let stem = input
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "notebook".to_string());
+
+    let export = config.export_options()?;
+    let output = if let Some(output) = &args.output {
+        output.clone()
+    } else if let Some(output_dir) = &config.output_dir {
+        output_dir.join(&stem).with_extension(export.format.extension())
+    } else {
+        // Default output path is the same as input, with the extension of the format
+        input.with_extension(export.format.extension())
+    };
+    let (output, typ_output) = output_paths(&output, &export)?;
+
+    let parent = output.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let (download_dir, asset_prefix) = downloads_dir(&config, &parent)?;
+    let (download_dir, asset_prefix) = if batch {
+        (download_dir.join(&stem), format!("{}/{}", asset_prefix, stem))
+    } else {
+        (download_dir, asset_prefix)
+    };
+
+    Ok(Job { input, output, typ_output, download_dir, asset_prefix, config_files })
+}
+
+/// The path of the output and of the Typst source. A path without extension gets the one
+/// of the format. The path of a paged format is a pattern of the page files, with `-{p}`
+/// added before the extension unless it has a placeholder or a single page is exported.
+/// The extension of another format is an error, e.g. `out.typ` with `--to pdf` would be
+/// both the source and the PDF.
+fn output_paths(path: &Path, export: &ExportOptions) -> Result<(PathBuf, PathBuf)> {
+    let mut output = path.to_path_buf();
+    match output.extension() {
+        None => {
+            output.set_extension(export.format.extension());
+        }
+        Some(extension) => {
+            if let Some(format) = OutputFormat::from_extension(&extension.to_string_lossy()) {
+                if format != export.format {
+                    return Err(NbconvertError::InvalidArgument(format!(
+                        "the output {} has the extension of another format, expected .{}, or change --to",
+                        output.display(),
+                        export.format.extension()
+                    )));
+                }
+            }
+        }
+    }
+    if export.format == OutputFormat::Typst {
+        return Ok((output.clone(), output));
+    }
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let typ_stem = ["{0p}", "{p}", "{n}", "{t}"].iter().fold(stem.clone(), |stem, pattern| stem.replace(pattern, ""));
+    let typ_stem = typ_stem.trim_end_matches(['-', '_', ' ']);
+    let typ_output = output.with_file_name(if typ_stem.is_empty() { "document" } else { typ_stem }).with_extension("typ");
+
+    let single_page = matches!(export.pages.as_slice(), [PageRange { start: Some(start), end: Some(end) }] if start == end);
+    if export.format.is_paged() && !has_page_pattern(&output) && !single_page {
+        output.set_file_name(format!("{}-{{p}}.{}", stem, output.extension().unwrap_or_default().to_string_lossy()));
+    }
+    Ok((output, typ_output))
+}
+
+/// The downloads directory next to the output, and how the Typst source refers to it.
+/// The directory must be under the output directory: typst can't read the files out of the
+/// directory of the source.
+fn downloads_dir(config: &Config, output_parent: &Path) -> Result<(PathBuf, String)> {
+    let dir = config.downloads_dir.clone().unwrap_or_else(|| PathBuf::from("downloads"));
+    let mut parts = Vec::new();
+    for component in dir.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+            Component::CurDir => {}
+            _ => {
+                return Err(NbconvertError::InvalidArgument(format!(
+                    "invalid downloads directory {}, expected a directory under the output directory, as typst can't read the files out of it",
+                    dir.display()
+                )))
+            }
         }
-    }).flatten();
+    }
+    if parts.is_empty() {
+        return Ok((output_parent.to_path_buf(), ".".to_string()));
+    }
+    Ok((output_parent.join(&dir), format!("./{}", parts.join("/"))))
+}
+
+/// Convert and compile one notebook, return the path of the output.
+/// In batch mode the diagnostics are prefixed with the notebook path.
+fn convert_job(args: &Args, job: &Job, batch: bool) -> Result<PathBuf> {
+    let notebook = read_notebook(&job.input)?;
+    let config = notebook_layers(args, &notebook, &job.input, &job.config_files)?;
+
+    if let Some(parent) = job.output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    create_require_dir(&job.download_dir)?;
+
+    let converter = build_converter(
+        args,
+        &job.config_files,
+        std::slice::from_ref(&job.input),
+        &job.download_dir,
+        &job.asset_prefix,
+    )?;
+    let conversion = converter.convert_file(&notebook, &job.input)?;
+
+    let notebook = if batch { Some(job.input.as_path()) } else { None };
+    report_diagnostics(&config, &conversion.diagnostics, notebook)?;
+
+    // In batch mode the files next to the source are written once, see write_shared_files.
+    if batch {
+        conversion.write_source(&job.typ_output)?;
+    } else {
+        conversion.write(&job.typ_output)?;
+    }
+    compile_output(&job.typ_output, &job.output, &config, &conversion.source_map, notebook)?;
+
+    Ok(job.output.clone())
+}
+
+/// Convert the notebooks (or the manifest chapters) into one book and compile it.
+fn run_book(args: &Args) -> Result<()> {
+    let manifest = match &args.manifest {
+        Some(path) => Some(Manifest::from_file(path)?),
+        None => None,
+    };
+
+    let chapters: Vec<Chapter> = match &manifest {
+        Some(manifest) => manifest.chapters(),
+        None => collect_inputs(&args.input)?.into_iter().map(Chapter::new).collect(),
+    };
+    if chapters.is_empty() {
+        return Err(NbconvertError::InvalidArgument("the book has no chapter".to_string()));
+    }
 
-    let pdf_output = if let Some(output) = &args.output {
-        output.with_extension("pdf")
+    let config_dir = match &args.manifest {
+        Some(path) => path.parent(),
+        None => chapters[0].path.parent(),
+    };
+    let config_files = find_config(args, config_dir.unwrap_or(Path::new(".")));
+    let config = cli_config(args)?.load(&config_files)?;
+
+    let export = config.export_options()?;
+    let output = if let Some(output) = &args.output {
+        output.clone()
+    } else if let Some(output) = manifest.as_ref().and_then(|manifest| manifest.output.clone()) {
+        output
+    } else if let Some(path) = &args.manifest {
+        path.with_extension(export.format.extension())
     } else {
-        // Default output path is the same as input, but with .typ extension
-        args.input.with_extension("pdf")
+        let dir = config
+            .output_dir
+            .clone()
+            .unwrap_or_else(|| chapters[0].path.parent().unwrap_or(Path::new(".")).to_path_buf());
+        dir.join("book").with_extension(export.format.extension())
     };
+    let (output, typ_output) = output_paths(&output, &export)?;
 
-    let parent = pdf_output.parent().expect("No parent dir");
-    let download_dir = parent.join("downloads");
+    let parent = output.parent().unwrap_or(Path::new(".")).to_path_buf();
+    fs::create_dir_all(&parent)?;
+    let (download_dir, asset_prefix) = downloads_dir(&config, &parent)?;
     create_require_dir(&download_dir)?;
 
-    let nb = notebook::read_notebook(&args.input)?;
+    let sources: Vec<PathBuf> = chapters.iter().map(|chapter| chapter.path.clone()).collect();
+    let mut converter = build_converter(args, &config_files, &sources, &download_dir, &asset_prefix)?;
+    // The title of the manifest comes before the ones of the chapters and config files.
+    if let Some(title) = manifest.as_ref().and_then(|manifest| manifest.title.clone()) {
+        if args.title.is_none() {
+            converter = converter.title(title);
+        }
+    }
 
-    let mut typst_content = convert_notebook(&nb, &download_dir)?;
+    let conversion = converter.convert_book(&chapters)?;
 
-    // Add preface to the typst content
-    typst_content.add_preface(&title, &authors, date.as_ref());
+    report_diagnostics(&config, &conversion.diagnostics, None)?;
 
-    
+    conversion.write(&typ_output)?;
+    compile_output(&typ_output, &output, &config, &conversion.source_map, None)
+}
+
+/// The CLI arguments as a config, of the highest precedence.
+fn cli_config(args: &Args) -> Result<Config> {
+    let mut config = Config {
+        output_dir: args.output_dir.clone(),
+        downloads_dir: args.downloads_dir.clone(),
+        title: args.title.clone(),
+        authors_file: args.authors_file.clone(),
+        date: args.date.clone(),
+        lang: args.lang.clone(),
+        region: args.region.clone(),
+        template: args.template.clone(),
+        paper: args.paper.clone(),
+        landscape: flag(args.landscape, args.no_landscape),
+        fonts: Fonts {
+            body: split_list(args.font_body.as_deref()),
+            cjk: split_list(args.font_cjk.as_deref()),
+            emphasis: split_list(args.font_emphasis.as_deref()),
+            mono: split_list(args.font_mono.as_deref()),
+        },
+        font_paths: (!args.font_path.is_empty()).then(|| args.font_path.clone()),
+        typst_bin: args.typst_bin.clone(),
+        package_cache: args.package_cache.clone(),
+        to: match &args.to {
+            Some(to) => Some(to.parse()?),
+            None => args
+                .output
+                .as_ref()
+                .and_then(|output| output.extension())
+                .and_then(|extension| OutputFormat::from_extension(&extension.to_string_lossy())),
+        },
+        pages: args.pages.clone(),
+        dpi: args.dpi,
+        mime_priority: args
+            .mime_priority
+            .as_ref()
+            .map(|priority| priority.split(',').map(|mime| mime.trim().to_string()).collect()),
+        respect_hidden: flag(args.respect_hidden, args.no_respect_hidden),
+        strict: flag(args.strict, args.no_strict),
+        offline: flag(args.offline, args.no_offline),
+        ..Config::default()
+    };
+    for author in &args.authors {
+        config.authors.push(author.parse()?);
+    }
+    for pair in &args.mime_fallback {
+        let (mime, action) = parse_fallback(pair)?;
+        config.mime_fallback.insert(mime, action);
+    }
+    for pair in &args.tag_action {
+        let (tag, action) = parse_tag_action(pair)?;
+        config.tag_actions.insert(tag, action);
+    }
+    for pair in &args.var {
+        let (name, value) = parse_var(pair)?;
+        config.vars.insert(name, value);
+    }
+    for pair in &args.var_json {
+        let (name, value) = parse_var_json(pair)?;
+        config.vars.insert(name, value);
+    }
+    Ok(config)
+}
 
-    create_template(&pdf_output);
+/// A flag and its `--no-` form, `None` when neither is given.
+fn flag(on: bool, off: bool) -> Option<bool> {
+    match (on, off) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
 
-    let typ_output = pdf_output.with_extension("typ");
-    let mut file = File::create(&typ_output)?;
-    file.write_all(typst_content.content.as_bytes())?;
+/// The config files found from `dir`, from the highest to the lowest precedence.
+fn find_config(args: &Args, dir: &Path) -> Vec<PathBuf> {
+    let paths = Config::find(args.config.as_deref(), dir);
+    for path in &paths {
+        println!("Using config: {}", path.display());
+    }
+    paths
+}
 
-    // Compile the typst file to PDF
-    let status = Command::new("typst")
-        .arg("compile")
-        .arg(&typ_output)
-        .arg(&pdf_output)
-        .status()
-        .expect("Failed to execute typst compiler");
+/// The CLI arguments over the settings in the notebook metadata over the config files.
+fn notebook_layers(args: &Args, notebook: &Notebook, path: &Path, config_files: &[PathBuf]) -> Result<Config> {
+    let notebook_config = Config::default()
+        .load(config_files)?
+        .with_notebook(notebook, path.parent().unwrap_or(Path::new(".")))?;
+    let mut config = cli_config(args)?;
+    config.merge(notebook_config);
+    Ok(config)
+}
 
-    if status.success() {
-        println!("PDF successfully compiled: {}", pdf_output.display());
+fn show_config(args: &Args, path: &Path) -> Result<()> {
+    let notebook = path.is_file();
+    let dir = if notebook { path.parent().unwrap_or(Path::new(".")) } else { path };
+    let paths = Config::find(args.config.as_deref(), dir);
+    let config = if notebook {
+        println!("# Merged from the metadata of {}", path.display());
+        notebook_layers(args, &read_notebook(path)?, path, &paths)?
     } else {
-        eprintln!("PDF compilation failed");
+        cli_config(args)?.load(&paths)?
+    };
+
+    for path in &paths {
+        println!("# Merged from {}", path.display());
+    }
+    if !notebook && paths.is_empty() {
+        println!("# No config file found.");
+    }
+    print!("{}", config.to_toml()?);
+    Ok(())
+}
+
+/// List, install or export the packages of the template, in the package cache of the config.
+fn manage_packages(args: &Args, command: &PackagesCommand) -> Result<()> {
+    let config = cli_config(args)?.load(&Config::find(args.config.as_deref(), Path::new(".")))?;
+    let cache = config.package_cache.as_deref();
+
+    match command {
+        PackagesCommand::List => {
+            if let Some(dir) = package_cache_dir(cache) {
+                println!("Package cache: {}", dir.display());
+            }
+            for package in template_packages() {
+                match find_package(&package, cache) {
+                    Some(dir) => println!("  {:<30}{}", package.to_string(), dir.display()),
+                    None => println!("  {:<30}missing", package.to_string()),
+                }
+            }
+        }
+        PackagesCommand::Install { archives } => {
+            let dir = package_cache_dir(cache).ok_or_else(|| {
+                NbconvertError::InvalidArgument("no package cache directory, give one with --package-cache".to_string())
+            })?;
+            for archive in archives {
+                for package in install_archive(archive, &dir)? {
+                    println!("Installed {} into {}", package, dir.join(package.subdir()).display());
+                }
+            }
+        }
+        PackagesCommand::Export { archive } => {
+            export_packages(&template_packages(), archive, cache)?;
+            println!("Exported the packages to: {}", archive.display());
+            println!("Install them offline with `nbconvert-rust packages install {}`.", archive.display());
+        }
     }
+    Ok(())
+}
 
+/// Eject a built-in template.
+fn init_template(name: &str, path: &Path, force: bool, list: bool) -> Result<()> {
+    if list {
+        for template in BUILTIN_TEMPLATES {
+            println!("{:<10}{}", template.name, template.description);
+        }
+        return Ok(());
+    }
+
+    let template = builtin_template(name).ok_or_else(|| {
+        let names: Vec<&str> = BUILTIN_TEMPLATES.iter().map(|template| template.name).collect();
+        NbconvertError::InvalidArgument(format!("unknown template \"{}\", expected one of {}", name, names.join(", ")))
+    })?;
+    if path.exists() && !force {
+        return Err(NbconvertError::InvalidArgument(format!(
+            "{} already exists, use --force to replace it",
+            path.display()
+        )));
+    }
+
+    fs::write(path, template.source)?;
+    println!("Created template at: {}", path.display());
+    println!("Use it with --template {}, or template = \"{}\" in nbconvert-rust.toml.", path.display(), path.display());
     Ok(())
 }
 
+/// The converter of the CLI arguments over the config files, `sources` are the notebooks
+/// to convert. The settings in the metadata of the notebooks come between them.
+fn build_converter(
+    args: &Args,
+    config_files: &[PathBuf],
+    sources: &[PathBuf],
+    download_dir: &Path,
+    asset_prefix: &str,
+) -> Result<Converter> {
+    Ok(Converter::new()
+        .apply_config(&cli_config(args)?, sources)?
+        .base_config(Config::default().load(config_files)?)
+        .asset_dir(download_dir, asset_prefix))
+}
+
+/// Print the diagnostics, prefixed with the notebook path if given.
+/// Fail in strict mode if there is any.
+fn report_diagnostics(config: &Config, diagnostics: &Diagnostics, notebook: Option<&Path>) -> Result<()> {
+    for diagnostic in diagnostics.iter() {
+        match notebook {
+            Some(notebook) => eprintln!("{}: {}", notebook.display(), diagnostic),
+            None => eprintln!("{}", diagnostic),
+        }
+    }
+    if config.strict.unwrap_or(false) && !diagnostics.is_empty() {
+        return Err(NbconvertError::StrictError(diagnostics.len()));
+    }
+    if diagnostics.has_errors() {
+        eprintln!("Some cells failed to convert, they are shown as error boxes in the output.");
+    }
+    Ok(())
+}
+
+/// The font families typst finds, in the system and the font paths, `None` if it can't run.
+/// The list is cached per compile options, for the notebooks of a batch.
+fn available_fonts(options: &CompileOptions) -> Option<Vec<String>> {
+    static CACHE: Mutex<BTreeMap<CompileOptions, Option<Vec<String>>>> = Mutex::new(BTreeMap::new());
+
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache.entry(options.clone()).or_insert_with(|| font_families(options)).clone()
+}
+
+/// Warn about the font families of the document typst doesn't have, before compiling.
+/// In strict mode they fail the conversion.
+fn check_fonts(config: &Config, notebook: Option<&Path>) -> Result<()> {
+    let Some(available) = available_fonts(&config.compile_options()) else {
+        return Ok(());
+    };
+    let mut diagnostics = Diagnostics::default();
+    for missing in missing_fonts(&config.fonts, &available) {
+        diagnostics.warn(NbconvertError::MissingFontError(missing));
+    }
+    let result = report_diagnostics(config, &diagnostics, notebook);
+    if !diagnostics.is_empty() {
+        eprintln!("Typst falls back to other fonts: install the missing ones, add their directory with --font-path, or choose others with --font-body, --font-cjk, --font-emphasis and --font-mono.");
+    }
+    result
+}
+
+/// Print the fonts typst can use, and the families of the document with the missing ones.
+fn list_fonts(args: &Args, path: &Path) -> Result<()> {
+    let notebook = path.is_file();
+    let dir = if notebook { path.parent().unwrap_or(Path::new(".")) } else { path };
+    let paths = Config::find(args.config.as_deref(), dir);
+    let config = if notebook {
+        notebook_layers(args, &read_notebook(path)?, path, &paths)?
+    } else {
+        cli_config(args)?.load(&paths)?
+    };
+    let font_paths = config.font_paths.clone().unwrap_or_default();
+    let available = available_fonts(&config.compile_options())
+        .ok_or_else(|| NbconvertError::CompileError("can't list the fonts with `typst fonts`".to_string()))?;
+
+    println!("Fonts available to typst{}:", if font_paths.is_empty() { String::new() } else {
+        format!(" (with {})", font_paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", "))
+    });
+    for family in &available {
+        println!("  {}", family);
+    }
+
+    let missing = missing_fonts(&config.fonts, &available);
+    println!();
+    println!("Fonts of the document:");
+    for (role, families) in config.fonts.families() {
+        for family in families {
+            match missing.iter().find(|font| font.role == role && font.family == family) {
+                Some(font) => println!("  {:<10}{:<30}missing, closest: {}", role, family, font.closest.as_deref().unwrap_or("none")),
+                None => println!("  {:<10}{:<30}ok", role, family),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Compile the typst file to the output format, after checking the fonts, and print the
+/// diagnostics of the compiler, the ones in the typst file at the cells they come from.
+/// The typst format only writes the typst file.
+fn compile_output(typ_output: &Path, output: &Path, config: &Config, source_map: &SourceMap, notebook: Option<&Path>) -> Result<()> {
+    let export = config.export_options()?;
+    if export.format == OutputFormat::Typst {
+        println!("Typst source written: {}", typ_output.display());
+        return Ok(());
+    }
+    check_fonts(config, notebook)?;
+
+    let result = compile(typ_output, output, &config.compile_options(), &export);
+    let diagnostics = match &result {
+        Ok(warnings) => warnings.as_slice(),
+        Err(NbconvertError::TypstError(diagnostics)) => diagnostics.as_slice(),
+        Err(_) => &[],
+    };
+    let typ_name = typ_output.file_name().unwrap_or_default().to_string_lossy();
+    for diagnostic in diagnostics {
+        let message = source_map.map_diagnostic(diagnostic, &typ_name).unwrap_or_else(|| diagnostic.to_string());
+        match notebook {
+            Some(notebook) => eprintln!("{}: {}", notebook.display(), message),
+            None => eprintln!("{}", message),
+        }
+    }
+    result?;
+
+    println!("{} successfully compiled: {}", export.format.extension().to_uppercase(), output.display());
+    Ok(())
+}
+
+/// A comma-separated list, `None` if not given.
+fn split_list(list: Option<&str>) -> Option<Vec<String>> {
+    list.map(|list| list.split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect())
+}
+
+/// Print a table of the successes and failures of a batch.
+fn print_summary(jobs: &[Job], results: &[Result<PathBuf>]) {
+    let width = jobs
+        .iter()
+        .map(|job| job.input.display().to_string().chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("Notebook".len());
+
+    println!();
+    println!("{:<8}{:<width$}  Output", "Status", "Notebook", width = width);
+    for (job, result) in jobs.iter().zip(results) {
+        let (status, detail) = match result {
+            Ok(output) => ("ok", output.display().to_string()),
+            Err(error) => ("failed", error.to_string()),
+        };
+        println!(
+            "{:<8}{:<width$}  {}",
+            status,
+            job.input.display().to_string(),
+            detail,
+            width = width
+        );
+    }
+
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    println!("{} succeeded, {} failed.", results.len() - failed, failed);
+}
+
 fn create_require_dir(path: &Path) -> Result<()> {
     if !path.exists() {
         fs::create_dir_all(path)?;
@@ -131,123 +950,51 @@ fn create_require_dir(path: &Path) -> Result<()> {
     Ok(())
 }
 
-/// Ensure template.typ exists in the output directory.
-/// Return the full path to the created or existing template.
-fn create_template(output_path: &Path) -> PathBuf {
-    let dir = output_path
-        .parent()
-        .expect("Output path must have a parent directory");
-
-    let template_path = dir.join("template.typ");
-
-    if !template_path.exists() {
-        fs::write(&template_path, r#"#import "@preview/ansi-render:0.8.0": * // Render a terminal-like output.
-
-#import "@preview/mitex:0.2.5": * // LaTex Support for Typst.
-
-#import "@preview/cuti:0.3.0": show-cn-fakebold // Fake bold for CJK
-
-#let radius = 3pt
-#let inset = 8pt
-
-// Form a code block, with execution count to its left.
-#let code-block(body, lang: "python", count: none) = context {
-  block(
-    raw(body, lang: lang),
-    fill: luma(230),
-    inset: inset,
-    radius: radius,
-    width: 100%
-  )
-  v(0pt, weak: true)
-    let c = if count == none { raw("[ ]:") } else { raw("[" + str(count) + "]:") }
-  let size = measure(c)
-  box(height: 0pt, move(dx: -size.width, dy: -size.height - inset, c))
-}
-
-#let output-block(body) = {
-  v(0pt, weak: true)
-  ansi-render(
-    body,
-    radius: radius,
-    inset: inset,
-    width: 100%,
-    font: ("Maple Mono NF")
-  )
-}
-
-#let block-quote(body) = context {
-  let size = measure(body)
-  grid(
-    columns: (4pt, auto),
-    rows: auto,
-    gutter: 0pt,
-    rect(
-      fill: luma(180),
-      height: size.height + 2 * inset,
-      radius: (left: radius),
-    ),
-    block(
-      fill: luma(240),
-      height: size.height + 2 * inset,
-      inset: inset,
-      radius: (right: radius),
-      width: 100%,
-      body,
-    ),
-  )
-}
-
-// The project function defines how your document looks.
-// It takes your content and some metadata and formats it.
-// Go ahead and customize it to your liking!
-#let project(title: "", authors: (), date: none, body) = {
-  // Set the document's basic properties.
-  set document(author: authors.map(a => a.name), title: title)
-  set page(numbering: "1", number-align: center)
-  set text(font: ("New Computer Modern", "SimSun"), lang: "en")
-  show raw: set text(font: ("Maple Mono NF"))
-  show: show-cn-fakebold
-  show emph: set text(font: ("New Computer Modern", "KaiTi"))
-
-
-  // Title row.
-  align(center)[
-    #block(text(weight: 700, 1.75em, title))
-  ]
-
-  // Author information.
-  pad(
-    top: 0.5em,
-    bottom: 0.5em,
-    x: 2em,
-    grid(
-      columns: (1fr,) * calc.min(3, authors.len()),
-      gutter: 1em,
-      ..authors.map(author => align(center)[
-        *#author.name*
-        #if author.email != none { linebreak(); author.email }
-        #if author.affiliation != none { linebreak(); author.affiliation }
-      ]),
-    ),
-  )
-
-  align(center)[
-    #v(1em, weak: true)
-    #date
-  ]
-
-  // Main body.
-  set par(justify: true)
-
-  body
-}
-"#)
-            .expect("Failed to create template.typ");
-        println!("Created template at: {}", template_path.display());
-    } else {
-        println!("Using existing template: {}", template_path.display());
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cli(args: &[&str]) -> Config {
+        let args = Args::try_parse_from(["nbconvert-rust", "-i", "a.ipynb"].iter().chain(args)).unwrap();
+        cli_config(&args).unwrap()
+    }
+
+    #[test]
+    fn flags_and_their_no_forms() {
+        let config = cli(&[]);
+        assert_eq!((config.landscape, config.respect_hidden, config.strict, config.offline), (None, None, None, None));
+
+        let config = cli(&["--landscape", "--respect-hidden", "--strict", "--offline"]);
+        let on = Some(true);
+        assert_eq!((config.landscape, config.respect_hidden, config.strict, config.offline), (on, on, on, on));
+
+        let config = cli(&["--no-landscape", "--no-respect-hidden", "--no-strict", "--no-offline"]);
+        let off = Some(false);
+        assert_eq!((config.landscape, config.respect_hidden, config.strict, config.offline), (off, off, off, off));
+
+        // The last one wins.
+        assert_eq!(cli(&["--landscape", "--no-landscape"]).landscape, Some(false));
+        assert_eq!(cli(&["--no-strict", "--strict"]).strict, Some(true));
+    }
+
+    #[test]
+    fn flags_over_the_config() {
+        let mut config = cli(&["--no-landscape", "--no-strict"]);
+        config.merge(Config { landscape: Some(true), strict: Some(true), offline: Some(true), ..Config::default() });
+        assert_eq!((config.landscape, config.strict, config.offline), (Some(false), Some(false), Some(true)));
     }
 
-    template_path
-}
\ No newline at end of file
+    #[test]
+    fn strict_exit() {
+        let mut warnings = Diagnostics::default();
+        warnings.warn(NbconvertError::InvalidArgument("unmatched tag".to_string()));
+        let strict = Config { strict: Some(true), ..Config::default() };
+
+        assert!(report_diagnostics(&Config::default(), &warnings, None).is_ok());
+        assert!(matches!(report_diagnostics(&strict, &warnings, None), Err(NbconvertError::StrictError(1))));
+        assert!(report_diagnostics(&strict, &Diagnostics::default(), None).is_ok());
+        // `main` prints it, and exits with a failure.
+        let error = report_diagnostics(&strict, &warnings, None).unwrap_err();
+        assert_eq!(error.to_string(), "1 diagnostic(s) reported in strict mode");
+    }
+}
@@ -1,7 +1,6 @@
-use core::panic;
-use std::{collections::{HashMap, VecDeque}, fs::{self, File}, io, path::Path, sync::{LazyLock, RwLock}};
+use std::{fs::File, io};
 
-use markdown::{mdast::{self, Node}, to_mdast, Constructs, ParseOptions};
+use markdown::{mdast::{self, Node}, to_mdast, unist::Position, Constructs, ParseOptions};
 use reqwest::blocking;
 use serde_json::Value;
 
@@ -9,406 +8,447 @@ use serde_json::Value;
 use url::Url;
 use base64::prelude::*;
 
-/// The markdown definition.
-static DEFINITION: LazyLock<RwLock<HashMap<String, String>>> = LazyLock::new(|| {
-    RwLock::new(HashMap::new())
-});
-/// The markdown footnote definition.
-static FOOTNOTE_DEFINITION: LazyLock<RwLock<HashMap<String, String>>> = LazyLock::new(|| {
-    RwLock::new(HashMap::new())
-});
-
-
-/// Name - Path in String.
-static ATTACHMENTS: LazyLock<RwLock<HashMap<String, String>>> =
-	LazyLock::new(|| RwLock::new(HashMap::new()));
-
-
-/// Parse a given markdown to Typst contents.
-pub fn parse_markdown(source: &Vec<String>, attachments: &Option<Value>, download_dir: &Path) -> String {
-    let mut result = String::new();
-
-    let ast = to_mdast(
-        source.join("").as_str(),
-        &ParseOptions {
-            constructs: Constructs {
-                math_flow: true,
-                math_text: true,
-                ..Constructs::gfm() // GitHub Flavored Markdown.
-            },
-            ..Default::default()
-        },
-    )
-    .unwrap();
+use crate::context::{unique_file_name, Context};
+use crate::document::{Align, Block, Inline, Metadata, Table, TableCell};
+use crate::error::{CellLocation, NbconvertError, Result};
+
+/// Parse a given markdown to blocks.
+/// Problems are reported to `ctx`, a failing part is replaced by an error box.
+pub fn parse_markdown(source: &[String], attachments: &Option<Value>, ctx: &mut Context) -> Vec<Block> {
+    parse_markdown_lines(source, attachments, false, ctx).0
+}
+
+/// Parse a given markdown to blocks, along with the 1-based line of the source where
+/// each block starts. A front matter is only read with `front_matter`, in the first
+/// markdown cell: elsewhere `---` lines are thematic breaks.
+pub fn parse_markdown_lines(
+    source: &[String],
+    attachments: &Option<Value>,
+    front_matter: bool,
+    ctx: &mut Context,
+) -> (Vec<Block>, Vec<usize>) {
+    let mut result = Vec::new();
+
+    // Definitions and attachments are local to the markdown source.
+    ctx.definitions.clear();
+    ctx.footnotes.clear();
+    ctx.attachments.clear();
+
+    let ast = match to_mdast(source.join("").as_str(), &parse_options(front_matter)) {
+        Ok(ast) => ast,
+        Err(message) => {
+            let error = ctx.error_block(NbconvertError::MarkdownError {
+                location: ctx.location(None),
+                message: message.to_string(),
+            });
+            return (vec![error], vec![1]);
+        }
+    };
+
+    if let Err(error) = insert_attachments(attachments, ctx) {
+        result.push((ctx.error_block(error), 1));
+    }
+
+    parse_definition(&ast, ctx);
 
-    insert_attachments(attachments, download_dir);
+    // The blocks of a node start at its line.
+    for node in ast.children().map_or(&[][..], |children| children) {
+        let line = node.position().map_or(1, |position| position.start.line);
+        result.extend(parse_blocks(std::slice::from_ref(node), ctx).into_iter().map(|block| (block, line)));
+    }
 
-    let mut html_queue: VecDeque<&str> = VecDeque::new();
+    result.into_iter().unzip()
+}
 
-    parse_definition(&ast, &mut html_queue, download_dir);
 
-    result += parse_ast(&ast, &mut html_queue, download_dir).as_str();
+/// The markdown syntax of notebooks: GitHub Flavored Markdown, math, and the front matter if
+/// `front_matter`.
+pub fn parse_options(front_matter: bool) -> ParseOptions {
+    ParseOptions {
+        constructs: Constructs {
+            math_flow: true,
+            math_text: true,
+            frontmatter: front_matter,
+            ..Constructs::gfm() // GitHub Flavored Markdown.
+        },
+        ..Default::default()
+    }
+}
+
+/// Parse the block nodes of the markdown ast.
+fn parse_blocks(nodes: &[Node], ctx: &mut Context) -> Vec<Block> {
+    let mut result = Vec::new();
+
+    for node in nodes {
+        match node {
+            Node::Blockquote(node) => {
+                // > a.
+                result.push(Block::BlockQuote(parse_blocks(&node.children, ctx)));
+            }
+            Node::Code(node) => {
+                // The code is kept verbatim, its special characters are not markup.
+                result.push(Block::CodeBlock {
+                    lang: Some(node.lang.clone().unwrap_or("text".to_string())),
+                    code: node.value.clone(),
+                });
+            }
+            Node::Definition(_) | Node::FootnoteDefinition(_) => {
+                // [x]: y and [^x]: y, pre-processed in parse_definition.
+                // We will use [x] to create a link and [^x] a footnote later.
+            }
+            Node::Heading(node) => {
+                result.push(Block::Heading {
+                    level: node.depth,
+                    content: parse_inlines(&node.children, ctx),
+                });
+            }
+            Node::List(node) => {
+                // 判断是 enum 还是 list.
+                let items = node
+                    .children
+                    .iter()
+                    .map(|item| parse_blocks(item.children().map_or(&[][..], |children| children), ctx))
+                    .collect();
+                result.push(Block::List { ordered: node.ordered, items });
+            }
+            Node::Math(node) => {
+                result.push(Block::Math(format!("$$\n{}\n$$", node.value)));
+            }
+            Node::Paragraph(node) => {
+                result.push(Block::Paragraph(parse_inlines(&node.children, ctx)));
+            }
+            Node::Table(node) => {
+                result.push(Block::Table(parse_table(node, ctx)));
+            }
+            Node::ThematicBreak(_) => {
+                // The long long line. --------
+                result.push(Block::ThematicBreak);
+            }
+            Node::Yaml(node) => {
+                // ---\ntitle: ...\n---, not rendered.
+                let value = serde_yaml::from_str::<Value>(&node.value).map_err(|e| e.to_string());
+                parse_front_matter(value, node.position.as_ref(), ctx);
+            }
+            Node::Toml(node) => {
+                // +++\ntitle = ...\n+++, not rendered.
+                let value = toml::from_str::<Value>(&node.value).map_err(|e| e.to_string());
+                parse_front_matter(value, node.position.as_ref(), ctx);
+            }
+            Node::MdxFlowExpression(_) | Node::MdxJsxFlowElement(_) | Node::MdxjsEsm(_) => {
+                // {a}, MDX is not enabled.
+            }
+            _ => {
+                // HTML on its own lines, or an inline node out of a paragraph.
+                let inlines = parse_inlines(std::slice::from_ref(node), ctx);
+                if !inlines.is_empty() {
+                    result.push(Block::Paragraph(inlines));
+                }
+            }
+        }
+    }
 
     result
 }
 
+/// Read the title, authors... of the front matter into `ctx.metadata`.
+fn parse_front_matter(value: std::result::Result<Value, String>, position: Option<&Position>, ctx: &mut Context) {
+    let metadata = value.and_then(|value| Metadata::from_value(&value).map_err(error_message));
+    match metadata {
+        Ok(metadata) => ctx.metadata.merge(metadata),
+        Err(message) => ctx.warn(NbconvertError::FrontMatterError {
+            location: ctx.location(position),
+            message,
+        }),
+    }
+}
 
-/// Recursively parse the markdown ast.
-fn parse_ast(node: &Node, html_queue: &mut VecDeque<&str>, download_dir: &Path) -> String {
-    let mut result = String::new();
-    
+/// The message of an error, without the "Invalid argument" prefix.
+fn error_message(error: NbconvertError) -> String {
+    match error {
+        NbconvertError::InvalidArgument(message) => message,
+        error => error.to_string(),
+    }
+}
 
-    match node {
-        Node::Blockquote(node) => {
-            // > a.
-            let mut children_result = String::new();
-            for child in &node.children {
-                children_result += parse_ast(child, html_queue, download_dir).as_str();
+/// Parse the inline nodes of the markdown ast.
+/// The simple HTML tags (`<b>`, `<i>`, `<u>`) wrap the nodes up to their closing tag,
+/// the tags left open at the end are closed with a warning.
+fn parse_inlines(nodes: &[Node], ctx: &mut Context) -> Vec<Inline> {
+    let mut result = Vec::new();
+    // The open HTML tags and their content so far, the innermost last.
+    let mut open_tags: Vec<(HtmlTag, Vec<Inline>)> = Vec::new();
+
+    for node in nodes {
+        let inlines = match node {
+            Node::Html(node) => {
+                let location = ctx.location(node.position.as_ref());
+                match parse_html(&node.value, location.clone(), ctx) {
+                    HtmlNode::Open(tag) => {
+                        open_tags.push((tag, Vec::new()));
+                        continue;
+                    }
+                    HtmlNode::Close(tag) => {
+                        if open_tags.last().map(|(open, _)| *open) == Some(tag) {
+                            let (tag, content) = open_tags.pop().unwrap();
+                            vec![tag.wrap(content)]
+                        } else {
+                            ctx.warn(NbconvertError::UnmatchedTagError {
+                                location,
+                                tag: node.value.trim().to_string(),
+                            });
+                            continue;
+                        }
+                    }
+                    HtmlNode::Break => vec![Inline::LineBreak],
+                    HtmlNode::Skip => continue,
+                }
             }
-            result += format!(
-                "#block-quote[{}]\n\n",
-                children_result
-            ).as_str();
+            _ => parse_inline(node, ctx),
+        };
+
+        match open_tags.last_mut() {
+            Some((_, content)) => content.extend(inlines),
+            None => result.extend(inlines),
+        }
+    }
+
+    // Close the HTML tags left open, so that the markup stays balanced.
+    while let Some((tag, content)) = open_tags.pop() {
+        ctx.warn(NbconvertError::UnmatchedTagError {
+            location: ctx.location(None),
+            tag: tag.name().to_string(),
+        });
+        let inline = tag.wrap(content);
+        match open_tags.last_mut() {
+            Some((_, content)) => content.push(inline),
+            None => result.push(inline),
         }
+    }
+
+    result
+}
+
+/// Parse an inline node, an unresolved reference gives its text.
+fn parse_inline(node: &Node, ctx: &mut Context) -> Vec<Inline> {
+    match node {
         Node::Break(_) => {
             // Breakline.
-            result += "\\ \n";
-        }
-        Node::Code(node) => {
-            // We use the code block in Typst.
-            // So its special characters should not be escaped 
-            result += format!(
-                "```{}\n{}\n```\n\n",
-                node.lang.clone().unwrap_or("text".to_string()),
-                node.value
-            ).as_str();
-        }
-        Node::Definition(_) => {
-            // Defintion, like the Link in Typst.
-            // [x]: y, we will use [x] to create a link later.
-            // Pre-processed before in parse_definition.
+            vec![Inline::LineBreak]
         }
         Node::Delete(node) => {
             // Delete Line.
-            let mut children_result = String::new();
-            for child in &node.children {
-                children_result += parse_ast(child, html_queue, download_dir).as_str();
-            }
-            result += format!(
-                "#strike[{}]",
-                children_result
-            ).as_str();
+            vec![Inline::Strike(parse_inlines(&node.children, ctx))]
         }
         Node::Emphasis(node) => {
-            // Enphasis.
-            let mut children_result = String::new();
-            for child in &node.children {
-                children_result += parse_ast(child, html_queue, download_dir).as_str();
-            }
-            result += format!(
-                "#emph[{}]",
-                children_result
-            ).as_str();
-        }
-        Node::FootnoteDefinition(_) => {
-            // Like the definition.
-            // Pre-processed in parse_definition.
-            // We will use [^x] to create a footnote later.
+            vec![Inline::Emphasis(parse_inlines(&node.children, ctx))]
         }
         Node::FootnoteReference(node) => {
-            if let Some(link) = FOOTNOTE_DEFINITION.read().unwrap().to_owned().get(node.identifier.as_str()) {
-                result += format!(
-                    "#footnote(\"{}\")[{}]",
-                    link,
-                    node.identifier.clone()
-                ).as_str();
-            }
-        }
-        Node::Heading(node) => {
-            let mut children_result = String::new();
-            for child in &node.children {
-                children_result += parse_ast(child, html_queue, download_dir).as_str();
-            }
-            result += format!(
-                "\n\n{} {}\n\n",
-                "=".repeat(node.depth.into()),
-                children_result
-            ).as_str();
-        }
-        Node::Html(node) => {
-            // We need to record all the HTML nodes.
-            // That means, record the first <br>
-            // When meeting </br>, apply the effect.
-            if node.value == "<br>" {
-                // This is a break line.
-                result += "\\ \n";
-                return result;
-            } else {
-                result += &parse_html(node.value.as_str(), html_queue);
+            match ctx.footnotes.get(node.identifier.as_str()).cloned() {
+                Some(content) => vec![Inline::Footnote(content)],
+                None => {
+                    ctx.warn(NbconvertError::ReferenceError {
+                        location: ctx.location(node.position.as_ref()),
+                        label: format!("^{}", node.identifier),
+                    });
+                    Vec::new()
+                }
             }
         }
         Node::Image(node) => {
             // ![alpha](https://example.com/favicon.ico "bravo")
-            match Url::parse(&node.url) {
-                Ok(url) => {
-                    match url.scheme() {
-                        "http" | "https" => result += format!(
-                            "#figure(align(center, image(\"{}\", width: 100%)))",
-                            download(&url, download_dir)
-                        ).as_str(),
-                        _ => {
-                            // In attachments with base64.
-                            let filename = node.url.strip_prefix("attachment:").unwrap();
-
-                            if let Some(filepath) = ATTACHMENTS.read().unwrap().to_owned().get(filename) {
-                                result += format!(
-                                    "#figure(align(center, image(\"{}\", width: 100%)))",
-                                    filepath
-                                ).as_str();
-                            }
-                        }
-                    }
-                }
-                _ => {
-                    // Baisc file.
-                    result += format!(
-                        "#figure(align(center, image(\"{}\", width: 50%)))",
-                        node.url
-                    ).as_str();
+            let location = ctx.location(node.position.as_ref());
+            parse_image(&node.url, location, ctx).into_iter().collect()
+        }
+        Node::ImageReference(node) => {
+            // ![alpha][bravo], [bravo] is defined elsewhere.
+            let location = ctx.location(node.position.as_ref());
+            let url = ctx.definitions.get(node.identifier.as_str()).cloned();
+            match url {
+                Some(url) => parse_image(&url, location, ctx).into_iter().collect(),
+                None => {
+                    ctx.warn(NbconvertError::ReferenceError {
+                        location,
+                        label: node.identifier.clone(),
+                    });
+                    vec![Inline::Text(node.alt.clone())]
                 }
             }
         }
-        Node::ImageReference(_node) => {
-            panic!("Image reference encountered, directly specify it in Jupyter notebook.");
-            // !DO not use image reference.
-        }
         Node::InlineCode(node) => {
-            result += format!(
-                "`{}`",
-                node.value
-            ).as_str();
+            vec![Inline::Code(node.value.clone())]
         }
         Node::InlineMath(node) => {
-            result += format!(
-                "#mi(`{}`)",
-                node.value
-            ).as_str();
+            vec![Inline::Math(node.value.clone())]
         }
         Node::Link(node) => {
             // [a](b)
-            let mut children_result = String::new();
-            for child in &node.children {
-                children_result += parse_ast(child, html_queue, download_dir).as_str();
-            }
-            result += format!(
-                "#link(\"{}\")[{}]",
-                node.url,
-                children_result
-            ).as_str();
-        }
-        Node::LinkReference(_node) => {
-            // [a] which is defined before.
-            panic!("Link reference encountered, directly specify it in Jupyter notebook.");
-        }
-        Node::List(node) => {
-            for child in &node.children {
-                // 判断是 enum 还是 list.
-                result += if node.ordered { "+ " } else { "- " };
-                // 难点在于如何处理嵌套的 List.
-                let mut list_item = parse_ast(child, html_queue, download_dir);
-                list_item = list_item.trim_end_matches("\n").replace("\n", "\n  ");
-                list_item += "\n";
-                result += list_item.as_str();
-            }
-            
-            // result += "\n";
-        }
-        Node::ListItem(node) => {
-            // Node 是有一些Markdown content组成的.
-            for child in &node.children {
-                result += parse_ast(child, html_queue, download_dir).as_str();
-            }
-        }
-        Node::Math(node) => {
-            result += format!(
-                "#mimath(`$$\n{}\n$$`)",
-                node.value
-            ).as_str();
-        }
-        Node::MdxFlowExpression(_) => {
-            // {a}
-        }
-        Node::MdxJsxFlowElement(_) => {
-
-        }
-        Node::MdxJsxTextElement(_) => {
-
-        }
-        Node::MdxTextExpression(_) => {
-
-        }
-        Node::MdxjsEsm(_) => {
-
-        }
-        Node::Paragraph(node) => {
-            let mut children_result = String::new();
-            for child in &node.children {
-                children_result += parse_ast(child, html_queue, download_dir).as_str();
-            }
-            result += children_result.as_str();
-            result += "\n";
-        }
-        Node::Root(node) => {
-            // This is the root node representing a doc.
-            for child in &node.children {
-                result += parse_ast(child, html_queue, download_dir).as_str();
-                result += "\n"; // Separating the paragraph.
+            vec![Inline::Link {
+                url: node.url.clone(),
+                content: parse_inlines(&node.children, ctx),
+            }]
+        }
+        Node::LinkReference(node) => {
+            // [a] which is defined elsewhere.
+            let content = parse_inlines(&node.children, ctx);
+            let url = ctx.definitions.get(node.identifier.as_str()).cloned();
+            match url {
+                Some(url) => vec![Inline::Link { url, content }],
+                None => {
+                    ctx.warn(NbconvertError::ReferenceError {
+                        location: ctx.location(node.position.as_ref()),
+                        label: node.identifier.clone(),
+                    });
+                    content
+                }
             }
         }
         Node::Strong(node) => {
             // **a**
-            result += "*";
-            for child in &node.children {
-                result += parse_ast(child, html_queue, download_dir).as_str();
-            }
-            result += "*";
-        }
-        Node::Table(node) => {
-            // Typst 里表格的语法：
-            /* #table(
-                columns: 3,
-                align: (left, center, auto, ),
-                table.header([ggg], [sss], [sss]), // header cells.
-                [x], [y], [z]
-                ) */
-            result += format!(
-                "#table(
-  columns: {},
-  align: ({}),\n",
-                node.align.len(),
-                node.align.iter()
-                          .map(|a| {
-                            match a {
-                                mdast::AlignKind::Center => "center".to_string(),
-                                mdast::AlignKind::Left => "left".to_string(),
-                                mdast::AlignKind::Right => "right".to_string(),
-                                mdast::AlignKind::None => "auto".to_string(),
-                            }
-                          })
-                          .collect::<Vec<String>>()
-                          .join(", ")
-            ).as_str();
-            let mut children = node.children.clone();
-            // The first row is title.
-            let mut table_header = parse_ast(&children.remove(0), html_queue, download_dir); 
-            table_header.pop(); // Delete the newline char.
-            result += format!(
-                "  table.header(
-  {}
-  ),\n",
-                table_header
-            ).as_ref();
-            // The following rows are contents.
-            let mut children_result = String::new();
-            for child in &children {
-                children_result += parse_ast(child, html_queue, download_dir).as_str();
-            }
-            result += children_result.as_str();
-            result += ")\n\n";
-        }
-        Node::TableCell(node) => {
-            // 处理Table Cell.
-            result += "[";
-            let mut children_result = String::new();
-            for child in &node.children {
-                children_result += parse_ast(child, html_queue, download_dir).as_str();
-            }
-            result += children_result.as_str();
-            result += "], ";
-        }
-        Node::TableRow(node) => {
-            result += "  ";
-            // Child of row: Cell.
-            let mut children_result = String::new();
-            for child in &node.children {
-                children_result += parse_ast(child, html_queue, download_dir).as_str();
-            }
-            result += children_result.as_str();
-            result += "\n";
+            vec![Inline::Strong(parse_inlines(&node.children, ctx))]
         }
         Node::Text(node) => {
-            result += node.value.as_str();
+            vec![Inline::Text(node.value.clone())]
         }
-        Node::ThematicBreak(_) => {
-            // The long long line. --------
-            result += "#line(length: 100%)\n";
+        Node::Html(_) => {
+            // Out of a list of nodes, e.g. alone in a table cell.
+            parse_inlines(std::slice::from_ref(node), ctx)
         }
-        Node::Toml(_) => {
-            // TODO!
+        Node::MdxJsxTextElement(_) | Node::MdxTextExpression(_) => {
+            // MDX is not enabled.
+            Vec::new()
         }
-        Node::Yaml(_) => {
-            // TODO!
+        _ => {
+            // A block node, e.g. the paragraph of a list item in a table.
+            node.children()
+                .map(|children| parse_inlines(children, ctx))
+                .unwrap_or_default()
         }
     }
+}
 
-    result
+/// Parse a GFM table, its first row is the header.
+fn parse_table(node: &mdast::Table, ctx: &mut Context) -> Table {
+    let mut rows: Vec<Vec<TableCell>> = node
+        .children
+        .iter()
+        .map(|row| {
+            row.children()
+                .map_or(&[][..], |children| children)
+                .iter()
+                .map(|cell| {
+                    let content = parse_inlines(cell.children().map_or(&[][..], |children| children), ctx);
+                    TableCell::new(content)
+                })
+                .collect()
+        })
+        .collect();
+
+    let header = if rows.is_empty() { Vec::new() } else { vec![rows.remove(0)] };
+
+    Table {
+        columns: node.align.len(),
+        align: node
+            .align
+            .iter()
+            .map(|align| match align {
+                mdast::AlignKind::Center => Align::Center,
+                mdast::AlignKind::Left => Align::Left,
+                mdast::AlignKind::Right => Align::Right,
+                mdast::AlignKind::None => Align::Auto,
+            })
+            .collect(),
+        header,
+        rows,
+        caption: None,
+    }
 }
 
 
-pub fn parse_definition(node: &Node, html_queue: &mut VecDeque<&str>, download_dir: &Path) {
+pub fn parse_definition(node: &Node, ctx: &mut Context) {
     match node {
         Node::Definition(node) => {
-            DEFINITION.write().unwrap().insert(node.identifier.clone(), node.url.clone());
+            ctx.definitions.insert(node.identifier.clone(), node.url.clone());
         }
         Node::FootnoteDefinition(node) => {
-            let mut children_result = String::new();
-            for child in &node.children {
-                children_result += parse_ast(child, html_queue, download_dir).as_str();
+            let content = parse_blocks(&node.children, ctx);
+            ctx.footnotes.insert(node.identifier.clone(), content);
+        }
+        _ => {
+            // Definitions may be nested, e.g. in a block quote.
+            for child in node.children().into_iter().flatten() {
+                parse_definition(child, ctx);
             }
-            FOOTNOTE_DEFINITION.write().unwrap().insert(node.identifier.clone(), children_result);
         }
-        _ => {}
+    }
+}
+
+/// Parse an image given by its url: a web image, an attachment or a local file.
+fn parse_image(url: &str, location: CellLocation, ctx: &mut Context) -> Option<Inline> {
+    match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {
+            match download(&parsed, location, ctx) {
+                Ok(path) => Some(Inline::Image { path, width: Some(100) }),
+                Err(error) => Some(ctx.error_inline(error)),
+            }
+        }
+        Ok(_) if url.starts_with("attachment:") => {
+            // In attachments with base64.
+            let filename = url.trim_start_matches("attachment:");
+
+            if let Some(filepath) = ctx.attachments.get(filename) {
+                Some(Inline::Image { path: filepath.clone(), width: Some(100) })
+            } else {
+                ctx.warn(NbconvertError::ReferenceError {
+                    location,
+                    label: url.to_string(),
+                });
+                None
+            }
+        }
+        _ => {
+            // Baisc file.
+            Some(Inline::Image { path: url.to_string(), width: Some(50) })
+        }
     }
 }
 
 /// Download the file and return the file path.
-pub fn download(url: &Url, download_dir: &Path) -> String {
+pub fn download(url: &Url, location: CellLocation, ctx: &mut Context) -> Result<String> {
 
-    let mut resp = blocking::get(url.clone()).unwrap();
+    let download_error = |message: String| NbconvertError::DownloadError {
+        location: location.clone(),
+        url: url.to_string(),
+        message,
+    };
 
+    let mut resp = blocking::get(url.clone())
+        .and_then(|resp| resp.error_for_status())
+        .map_err(|e| download_error(e.to_string()))?;
+
+    // Every download gets its own file, the last segments of two URLs are often the same.
     let filename = url
         .path_segments()
-        .and_then(|segments| segments.last())
+        .and_then(|mut segments| segments.next_back())
+        .filter(|segment| !segment.is_empty())
         .unwrap_or("downloaded");
+    let local_name = unique_file_name("download", filename);
+    let local_path = ctx.download_dir.join(&local_name);
 
-    let ext_opt = Path::new(filename)
-        .extension()
-        .and_then(|os| os.to_str());
-
-    let local_name = if let Some(ext) = ext_opt {
-        format!("downloaded.{}", ext)       // With extension: use it.
-    } else {
-        filename.to_string()                // Without extension: use the name.
-    };
-
-    let local_path = download_dir.join(local_name.clone());
+    let mut out = File::create(&local_path)?;
 
-    let mut out = File::create(&local_path).unwrap();
+    io::copy(&mut resp, &mut out).map_err(|e| download_error(e.to_string()))?;
+    ctx.assets.push(local_path);
 
-    io::copy(&mut resp, &mut out).unwrap();
-
-    format!("./downloads/{}", local_name)
+    Ok(ctx.asset_path(&local_name))
 
 }
 
-fn insert_attachments(attachments: &Option<Value>, download_dir: &Path) {
+fn insert_attachments(attachments: &Option<Value>, ctx: &mut Context) -> Result<()> {
     let obj = match attachments {
         Some(Value::Object(map)) => map,
-        _ => return,
+        _ => return Ok(()),
     };
 
-    let mut guard = ATTACHMENTS.write().unwrap();
-
     for (filename, bundle) in obj {
         // bundle is a JSON object with MIME type as key and Base64 data as value.
         // E.g. {"image/png": "iVBORw0KGgoAAAANSUhEUgAA..."}
@@ -416,61 +456,82 @@ fn insert_attachments(attachments: &Option<Value>, download_dir: &Path) {
             // We only handle the first value in the object.
             // This is a simplification, as the notebook may have multiple MIME types.
             if let Some(Value::String(data_b64)) = inner.values().next() {
-                let bytes = BASE64_STANDARD.decode(data_b64).unwrap();
-                let local_path = download_dir.join(filename);
-                fs::write(&local_path, &bytes).unwrap();
-                guard.insert(filename.clone(), local_path.display().to_string());
+                let bytes = BASE64_STANDARD.decode(data_b64).map_err(|e| NbconvertError::MediaError {
+                    location: ctx.location(None),
+                    message: format!("failed to decode attachment {}: {}", filename, e),
+                })?;
+                // The names are the notebook's, two cells may both have an `image.png`.
+                let path = ctx.write_named_asset("attachment", filename, &bytes)?;
+                ctx.attachments.insert(filename.clone(), path);
             }
         }
     }
+
+    Ok(())
 }
 
 
 
-/// This function is to parse several simple HTML tags.
-fn parse_html(html: &str, queue: &mut VecDeque<&str>) -> String {
-    match html {
-        "<b>" => {
-            // Bold text.
-            queue.push_back("<b>");
-            "*".to_string()
-        }
-        "</b>" => {
-            // End of bold text.
-            if let Some(_) = queue.pop_front() {
-                "*".to_string()
-            } else {
-                panic!("Unmatched </b> tag");
-            }
-        }
-        "<i>" => {
-            // Italic text.
-            queue.push_back("<i>");
-            "_".to_string()
-        }
-        "</i>" => {
-            // End of italic text.
-            if let Some(_) = queue.pop_front() {
-                "_".to_string()
-            } else {
-                panic!("Unmatched </i> tag");
-            }
+/// The simple HTML tags supported in markdown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HtmlTag {
+    Bold,
+    Italic,
+    Underline,
+}
+
+impl HtmlTag {
+    fn name(&self) -> &'static str {
+        match self {
+            HtmlTag::Bold => "<b>",
+            HtmlTag::Italic => "<i>",
+            HtmlTag::Underline => "<u>",
         }
-        "<u>" => {
-            // Underline text.
-            queue.push_back("<u>");
-            "#underline[".to_string()
+    }
+
+    /// The inline of the content between the tags.
+    fn wrap(&self, content: Vec<Inline>) -> Inline {
+        match self {
+            HtmlTag::Bold => Inline::Strong(content),
+            HtmlTag::Italic => Inline::Emphasis(content),
+            HtmlTag::Underline => Inline::Underline(content),
         }
-        "</u>" => {
-            // End of underline text.
-            if let Some(_) = queue.pop_front() {
-                "]".to_string()
-            } else {
-                panic!("Unmatched </u> tag");
-            }
+    }
+}
+
+/// An HTML node in markdown.
+enum HtmlNode {
+    Open(HtmlTag),
+    Close(HtmlTag),
+    Break,
+    /// A comment, or an unsupported tag.
+    Skip,
+}
+
+/// This function is to parse several simple HTML tags.
+/// Unsupported tags are reported to `ctx` and skipped.
+fn parse_html(html: &str, location: CellLocation, ctx: &mut Context) -> HtmlNode {
+    let html = html.trim();
+    match html {
+        // Bold, italic or underline text.
+        "<b>" => HtmlNode::Open(HtmlTag::Bold),
+        "<i>" => HtmlNode::Open(HtmlTag::Italic),
+        "<u>" => HtmlNode::Open(HtmlTag::Underline),
+        "</b>" => HtmlNode::Close(HtmlTag::Bold),
+        "</i>" => HtmlNode::Close(HtmlTag::Italic),
+        "</u>" => HtmlNode::Close(HtmlTag::Underline),
+        // This is a break line.
+        "<br>" | "<br/>" | "<br />" => HtmlNode::Break,
+        _ if html.starts_with("<!--") => {
+            // Comments are not rendered.
+            HtmlNode::Skip
         }
         _ => {
-            unimplemented!("HTML tag not implemented: {}", html);
+            ctx.warn(NbconvertError::UnsupportedHtmlError {
+                location,
+                tag: html.to_string(),
+            });
+            HtmlNode::Skip
         }
     }
-}
\ No newline at end of file
+}
@@ -1,97 +1,911 @@
-use std::{fs, path::Path};
+use std::{collections::HashMap, str::FromStr};
 
 use base64::{prelude::BASE64_STANDARD, Engine};
 use jupyter_protocol::{Media, MediaType};
 use uuid::Uuid;
 
-/// Process the given media.
-pub fn process_media(media: &Media, download_dir: &Path) -> String {
-    // 如果没有内容，返回空字符串
-    if media.content.is_empty() {
-        return String::new();
+use serde::{Deserialize, Serialize};
+
+use crate::context::Context;
+use crate::document::{Align, Block, Inline, Table, TableCell};
+use crate::error::{NbconvertError, Result};
+use crate::markdown::parse_markdown;
+
+/// The default display priority, from the most to the least preferred MIME type.
+/// Like the `display_data_priority` of nbconvert.
+pub const DEFAULT_MIME_PRIORITY: &[&str] = &[
+    "image/svg+xml",
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "text/latex",
+    "text/markdown",
+    "text/html",
+    "text/plain",
+];
+
+/// The default display priority as owned strings.
+pub fn default_mime_priority() -> Vec<String> {
+    DEFAULT_MIME_PRIORITY.iter().map(|mime| mime.to_string()).collect()
+}
+
+/// Expand the short names in a priority list (e.g. `png`, `latex`) to full MIME types.
+pub fn normalize_mime_priority(priority: &[String]) -> Vec<String> {
+    priority
+        .iter()
+        .filter(|mime| !mime.trim().is_empty())
+        .map(|mime| normalize_mime(mime))
+        .collect()
+}
+
+/// Expand a short name (e.g. `png`, `latex`) to the full MIME type.
+pub fn normalize_mime(mime: &str) -> String {
+    match mime.trim() {
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "latex" => "text/latex",
+        "markdown" => "text/markdown",
+        "html" => "text/html",
+        "plain" | "text" => "text/plain",
+        "json" => "application/json",
+        "typst" => "text/typst",
+        mime => mime,
+    }.to_string()
+}
+
+/// What to do with an output whose representations are all unsupported.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FallbackAction {
+    /// Pretty-print the data as a code block.
+    Code,
+    /// Render a labelled box naming the MIME type.
+    Placeholder,
+    /// Drop the output.
+    Drop,
+}
+
+impl FromStr for FallbackAction {
+    type Err = NbconvertError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "code" => Ok(FallbackAction::Code),
+            "placeholder" => Ok(FallbackAction::Placeholder),
+            "drop" => Ok(FallbackAction::Drop),
+            other => Err(NbconvertError::InvalidArgument(format!(
+                "unknown fallback action \"{}\", expected code, placeholder or drop",
+                other
+            ))),
+        }
     }
+}
 
-    let mut result = String::new();
+/// The built-in fallback action of a MIME type.
+fn default_fallback(mime: &str) -> FallbackAction {
+    match mime {
+        "application/json" | "application/geo+json" => FallbackAction::Code,
+        "application/javascript" | "application/vnd.jupyter.widget-state+json" => FallbackAction::Drop,
+        _ => FallbackAction::Placeholder,
+    }
+}
+
+/// How the outputs of code cells are rendered.
+#[derive(Debug, Clone)]
+pub struct MediaOptions {
+    /// The MIME types to render, from the most to the least preferred.
+    pub priority: Vec<String>,
+    /// The fallback action per MIME type, `*` matches every type.
+    pub fallbacks: HashMap<String, FallbackAction>,
+}
 
-    for data in &media.content {
-        result += parse_media(data, download_dir).as_str();
+impl Default for MediaOptions {
+    fn default() -> Self {
+        MediaOptions {
+            priority: default_mime_priority(),
+            fallbacks: HashMap::new(),
+        }
     }
+}
 
-    result
+impl MediaOptions {
+    /// The fallback action of the MIME type: the configured one, or the built-in default.
+    pub fn fallback(&self, mime: &str) -> FallbackAction {
+        self.fallbacks
+            .get(mime)
+            .or(self.fallbacks.get("*"))
+            .copied()
+            .unwrap_or_else(|| default_fallback(mime))
+    }
 }
 
-/// Parse given type of media. 
-fn parse_media(data: &MediaType, download_dir: &Path) -> String {
+/// Parse a `mime=action` pair, e.g. `application/json=code`.
+pub fn parse_fallback(pair: &str) -> Result<(String, FallbackAction)> {
+    let (mime, action) = pair.split_once('=').ok_or_else(|| {
+        NbconvertError::InvalidArgument(format!("expected mime=action, got \"{}\"", pair))
+    })?;
+    Ok((normalize_mime(mime), action.parse()?))
+}
 
-    let mut result = String::new();
+/// The MIME type of the media data.
+pub fn mime_type(data: &MediaType) -> &str {
     match data {
-        MediaType::DataTable(_data) => {
-            // TODO!
+        MediaType::Plain(_) => "text/plain",
+        MediaType::Html(_) => "text/html",
+        MediaType::Latex(_) => "text/latex",
+        MediaType::Javascript(_) => "application/javascript",
+        MediaType::Markdown(_) => "text/markdown",
+        MediaType::Svg(_) => "image/svg+xml",
+        MediaType::Png(_) => "image/png",
+        MediaType::Jpeg(_) => "image/jpeg",
+        MediaType::Gif(_) => "image/gif",
+        MediaType::Json(_) => "application/json",
+        MediaType::GeoJson(_) => "application/geo+json",
+        MediaType::DataTable(_) => "application/vnd.dataresource+json",
+        MediaType::Plotly(_) => "application/vnd.plotly.v1+json",
+        MediaType::WidgetView(_) => "application/vnd.jupyter.widget-view+json",
+        MediaType::WidgetState(_) => "application/vnd.jupyter.widget-state+json",
+        MediaType::VegaLiteV2(_) => "application/vnd.vegalite.v2+json",
+        MediaType::VegaLiteV3(_) => "application/vnd.vegalite.v3+json",
+        MediaType::VegaLiteV4(_) => "application/vnd.vegalite.v4+json",
+        MediaType::VegaLiteV5(_) => "application/vnd.vegalite.v5+json",
+        MediaType::VegaLiteV6(_) => "application/vnd.vegalite.v6+json",
+        MediaType::VegaV3(_) => "application/vnd.vega.v3+json",
+        MediaType::VegaV4(_) => "application/vnd.vega.v4+json",
+        MediaType::VegaV5(_) => "application/vnd.vega.v5+json",
+        MediaType::Vdom(_) => "application/vdom.v1+json",
+        MediaType::Other((mime, _)) => mime.as_str(),
+    }
+}
+
+/// The text of SVG data. jupyter-protocol reads `image/svg+xml` as `Other`, as it takes every
+/// `image/` type but PNG, JPEG and GIF for binary data.
+fn svg_text(data: &MediaType) -> Option<String> {
+    match data {
+        MediaType::Svg(text) => Some(text.clone()),
+        MediaType::Other((mime, value)) if mime == "image/svg+xml" => match value {
+            serde_json::Value::String(text) => Some(text.clone()),
+            serde_json::Value::Array(lines) => lines.iter().map(|line| line.as_str()).collect(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether the media data can be rendered to Typst.
+fn is_supported(data: &MediaType) -> bool {
+    if svg_text(data).is_some() {
+        return true;
+    }
+    match data {
+        MediaType::Html(html) => html.contains("<table"),
+        MediaType::Plain(_)
+        | MediaType::Latex(_)
+        | MediaType::Markdown(_)
+        | MediaType::Svg(_)
+        | MediaType::Png(_)
+        | MediaType::Jpeg(_)
+        | MediaType::Gif(_) => true,
+        _ => false,
+    }
+}
+
+/// Pick the single best representation in the MIME bundle,
+/// i.e. the first supported one in the priority list.
+pub fn select_media<'a>(media: &'a Media, priority: &[String]) -> Option<&'a MediaType> {
+    priority.iter().find_map(|mime| {
+        media
+            .content
+            .iter()
+            .find(|data| mime_type(data) == mime && is_supported(data))
+    })
+}
+
+/// Process the given media of an output in the current cell.
+pub fn process_media(media: &Media, ctx: &mut Context) -> Vec<Block> {
+    // 如果没有内容，返回空
+    if media.content.is_empty() {
+        return Vec::new();
+    }
+
+    if let Some(data) = select_media(media, &ctx.media.priority) {
+        return match parse_media(data, ctx) {
+            Ok(result) => result,
+            Err(error) => vec![ctx.error_block(error)],
+        };
+    }
+
+    // Nothing can be rendered, fall back on the first representation that is not dropped.
+    let fallback = media
+        .content
+        .iter()
+        .map(|data| (data, ctx.media.fallback(mime_type(data))))
+        .find(|(_, action)| *action != FallbackAction::Drop);
+    // A supported representation was only left out of the priority list.
+    let excluded = media.content.iter().any(is_supported);
+    let mimes: Vec<&str> = media.content.iter().map(mime_type).collect();
+
+    let (mime, action, blocks) = match fallback {
+        Some((data, action)) => (
+            mime_type(data).to_string(),
+            format!("rendered as {:?}", action).to_lowercase(),
+            parse_fallback_media(data, action).into_iter().collect(),
+        ),
+        None => (mimes.join(", "), "dropped".to_string(), Vec::new()),
+    };
+    let location = ctx.location(None);
+    if excluded {
+        ctx.warn(NbconvertError::ExcludedOutputError { location, mimes: mimes.join(", "), action });
+    } else {
+        ctx.warn(NbconvertError::UnsupportedOutputError { location, mime, action });
+    }
+    blocks
+}
+
+/// Render unsupported media data with the fallback action.
+fn parse_fallback_media(data: &MediaType, action: FallbackAction) -> Option<Block> {
+    let mime = mime_type(data);
+    match action {
+        FallbackAction::Code => {
+            let (code, lang) = match data {
+                MediaType::Plain(text)
+                | MediaType::Html(text)
+                | MediaType::Latex(text)
+                | MediaType::Markdown(text)
+                | MediaType::Svg(text) => (text.clone(), "text"),
+                MediaType::Javascript(text) => (text.clone(), "js"),
+                other => {
+                    // The JSON based types serialize to {"type": .., "data": ..}.
+                    let value = serde_json::to_value(other).unwrap_or_default();
+                    let data = match &value {
+                        serde_json::Value::Object(map) => match map.get("data") {
+                            // Other((mime, data)) is a [mime, data] pair.
+                            Some(serde_json::Value::Array(pair)) if pair.len() == 2 => pair[1].clone(),
+                            Some(data) => data.clone(),
+                            None => value.clone(),
+                        },
+                        _ => value.clone(),
+                    };
+                    match data {
+                        serde_json::Value::String(text) => (text, "text"),
+                        data => (serde_json::to_string_pretty(&data).unwrap_or_default(), "json"),
+                    }
+                }
+            };
+            Some(Block::CodeBlock { lang: Some(lang.to_string()), code })
         }
-        MediaType::Latex(data) => {
-            result += format!(
-                "#mimath(`{}`)",
-                data
-            ).as_str();
+        FallbackAction::Placeholder => {
+            let label = if mime.contains("widget") {
+                "interactive widget"
+            } else {
+                "unsupported output"
+            };
+            Some(Block::Placeholder(format!("[{}: {}]", label, mime)))
         }
+        FallbackAction::Drop => None,
+    }
+}
+
+/// Parse given type of media.
+fn parse_media(data: &MediaType, ctx: &mut Context) -> Result<Vec<Block>> {
+
+    let blocks = match data {
+        MediaType::Latex(data) => vec![Block::Math(data.clone())],
         // Image data is all base64 encoded. These variants could all accept <Vec<u8>> as the
         // data. However, not all users of this library will need immediate decoding of the data.
-        MediaType::Png(data) => {
-            result += format!(
-                "#image(\"{}\")",
-                write_figure(data, "png", download_dir)
-            ).as_str();
-        }
-        MediaType::Jpeg(data) => {
-            result += format!(
-                "#image(\"{}\")",
-                write_figure(data, "jpeg", download_dir)
-            ).as_str();
-        }
-        MediaType::Svg(data) => {
-            result += format!(
-                "#image(\"{}\")",
-                write_figure(data, "svg", download_dir)
-            ).as_str();
-        }
-        MediaType::Gif(data) => {
-            result += format!(
-                "#image(\"{}\")",
-                write_figure(data, "gif", download_dir)
-            ).as_str();
+        MediaType::Png(data) => vec![Block::Figure { path: write_figure(data, "png", ctx)? }],
+        MediaType::Jpeg(data) => vec![Block::Figure { path: write_figure(data, "jpeg", ctx)? }],
+        MediaType::Svg(_) | MediaType::Other(_) if svg_text(data).is_some() => {
+            // SVG is stored as text, not Base64.
+            let svg = svg_text(data).unwrap_or_default();
+            vec![Block::Figure { path: write_text_figure(&svg, "svg", ctx)? }]
         }
+        MediaType::Gif(data) => vec![Block::Figure { path: write_figure(data, "gif", ctx)? }],
         MediaType::Plain(data) => {
-            result += format!(
-                "#```{}```",
-                data
-            ).as_str();
             // It's a plain text, so we can just use the code block.
+            vec![Block::CodeBlock { lang: None, code: data.clone() }]
         }
-        MediaType::Html(_) => {
-            println!("Html is not supported yet, skipping.");
+        MediaType::Markdown(data) => parse_markdown(std::slice::from_ref(data), &None, ctx),
+        MediaType::Html(data) => parse_html_tables(data),
+        _ => {
+            // The other types are not supported, see the fallback in process_media.
+            Vec::new()
         }
-        _ => unimplemented!()
-    }
+    };
 
-    result
+    Ok(blocks)
 
 }
 
 
 /// Write the Base64 encoded figure data to a file and return the file path.
 /// The file will be saved in the media directory.
-fn write_figure(data: &String, ext: &str, download_dir: &Path) -> String {
+fn write_figure(data: &str, ext: &str, ctx: &mut Context) -> Result<String> {
 
     // Generate a unique file name based on the content hash or timestamp
     let file_name = format!("figure_{}.{}", Uuid::new_v4(), ext);
-    let file_path = download_dir.join(&file_name);
 
-    // Write the Base64 data to the file
-    if let Ok(decoded_data) = BASE64_STANDARD.decode(data) {
-        fs::write(&file_path, decoded_data).expect("Failed to write media file");
-    } else {
-        panic!("Failed to decode Base64 data for media");
+    // The Base64 data may be split into several lines.
+    let data: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+    let decoded_data = BASE64_STANDARD.decode(data).map_err(|e| NbconvertError::MediaError {
+        location: ctx.location(None),
+        message: format!("failed to decode Base64 {} data: {}", ext, e),
+    })?;
+    ctx.write_asset(&file_name, decoded_data)
+}
+
+/// Write a textual figure (e.g. SVG) to a file and return the file path.
+fn write_text_figure(data: &str, ext: &str, ctx: &mut Context) -> Result<String> {
+    let file_name = format!("figure_{}.{}", Uuid::new_v4(), ext);
+
+    ctx.write_asset(&file_name, data)
+}
+
+/// A token of an HTML fragment.
+#[derive(Debug)]
+enum HtmlToken {
+    /// `<name attr="value">`, the tag name is lowercased.
+    Open { name: String, attrs: Vec<(String, String)> },
+    /// `</name>`.
+    Close(String),
+    /// The text between tags, with entities decoded.
+    Text(String),
+}
+
+/// A cell of a HTML table.
+struct HtmlCell {
+    content: String,
+    header: bool,
+    rowspan: usize,
+    colspan: usize,
+    align: Option<Align>,
+}
+
+/// A row of a HTML table.
+struct HtmlRow {
+    cells: Vec<HtmlCell>,
+    in_head: bool,
+}
+
+/// Convert every `<table>` in the HTML (e.g. a pandas DataFrame) to a table block.
+/// The "N rows × M columns" footer of pandas is kept as the caption of the table.
+pub fn parse_html_tables(html: &str) -> Vec<Block> {
+    let tokens = tokenize_html(html);
+    let mut result = Vec::new();
+
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i] {
+            HtmlToken::Open { name, .. } if name == "table" => {
+                let (rows, next) = collect_table_rows(&tokens, i + 1);
+                let mut table = html_table(&rows);
+                i = next;
+                // The footer directly follows the table, e.g. <p>5 rows × 3 columns</p>.
+                if let Some(HtmlToken::Open { name, .. }) = tokens.get(i) {
+                    if name == "p" {
+                        let mut footer = String::new();
+                        i += 1;
+                        while let Some(HtmlToken::Text(text)) = tokens.get(i) {
+                            footer += text;
+                            i += 1;
+                        }
+                        if !footer.trim().is_empty() {
+                            table.caption = Some(footer.trim().to_string());
+                        }
+                    }
+                }
+                if !rows.is_empty() {
+                    result.push(Block::Table(table));
+                }
+            }
+            _ => i += 1,
+        }
+    }
+
+    result
+}
+
+/// Collect the rows of a table starting right after its `<table>` tag.
+/// Return the rows and the index after the closing `</table>`.
+fn collect_table_rows(tokens: &[HtmlToken], start: usize) -> (Vec<HtmlRow>, usize) {
+    let mut rows: Vec<HtmlRow> = Vec::new();
+    let mut in_head = false;
+    // The `text-align` of the current row, applied to cells without their own.
+    let mut row_align: Option<Align> = None;
+    let mut cell: Option<HtmlCell> = None;
+
+    let mut i = start;
+    while i < tokens.len() {
+        match &tokens[i] {
+            HtmlToken::Open { name, attrs } => match name.as_str() {
+                "thead" => in_head = true,
+                "tbody" | "tfoot" => in_head = false,
+                "tr" => {
+                    row_align = html_align(attrs);
+                    rows.push(HtmlRow { cells: Vec::new(), in_head });
+                }
+                "th" | "td" => {
+                    cell = Some(HtmlCell {
+                        content: String::new(),
+                        header: name == "th",
+                        rowspan: html_span(attrs, "rowspan"),
+                        colspan: html_span(attrs, "colspan"),
+                        align: html_align(attrs).or(row_align),
+                    });
+                }
+                "br" => {
+                    if let Some(cell) = cell.as_mut() {
+                        cell.content += "\n";
+                    }
+                }
+                "table" => {
+                    // Nested tables are flattened into the text of the cell, a line per row.
+                    let (nested, next) = collect_table_rows(tokens, i + 1);
+                    if let Some(cell) = cell.as_mut() {
+                        for row in &nested {
+                            let texts: Vec<&str> = row.cells.iter().map(|cell| cell.content.trim()).collect();
+                            cell.content += "\n";
+                            cell.content += &texts.join(" ");
+                        }
+                    }
+                    i = next;
+                    continue;
+                }
+                _ => {}
+            },
+            HtmlToken::Close(name) => match name.as_str() {
+                "th" | "td" => {
+                    if let Some(cell) = cell.take() {
+                        if rows.is_empty() {
+                            rows.push(HtmlRow { cells: Vec::new(), in_head });
+                        }
+                        rows.last_mut().unwrap().cells.push(cell);
+                    }
+                }
+                "thead" => in_head = false,
+                "table" => return (rows, i + 1),
+                _ => {}
+            },
+            HtmlToken::Text(text) => {
+                if let Some(cell) = cell.as_mut() {
+                    cell.content += text;
+                }
+            }
+        }
+        i += 1;
+    }
+
+    (rows, i)
+}
+
+/// The table of the rows, header rows go into the header.
+fn html_table(rows: &[HtmlRow]) -> Table {
+    let cells = |row: &HtmlRow| -> Vec<TableCell> {
+        row.cells
+            .iter()
+            .map(|cell| {
+                let text = cell.content.trim();
+                let content = if text.is_empty() {
+                    Vec::new()
+                } else if cell.header {
+                    vec![Inline::Strong(vec![Inline::Text(text.to_string())])]
+                } else {
+                    vec![Inline::Text(text.to_string())]
+                };
+                TableCell {
+                    content,
+                    rowspan: cell.rowspan,
+                    colspan: cell.colspan,
+                    align: cell.align,
+                }
+            })
+            .collect()
+    };
+
+    Table {
+        columns: table_columns(rows),
+        header: rows.iter().filter(|row| row.in_head).map(cells).collect(),
+        rows: rows.iter().filter(|row| !row.in_head).map(cells).collect(),
+        ..Table::default()
+    }
+}
+
+/// Count the columns of the table, taking `rowspan` and `colspan` into account.
+fn table_columns(rows: &[HtmlRow]) -> usize {
+    // How many more rows each column is still occupied by a rowspan above.
+    let mut occupied: Vec<usize> = Vec::new();
+    let mut columns = 0;
+
+    for row in rows {
+        let mut col = 0;
+        for cell in &row.cells {
+            while col < occupied.len() && occupied[col] > 0 {
+                col += 1;
+            }
+            if occupied.len() < col + cell.colspan {
+                occupied.resize(col + cell.colspan, 0);
+            }
+            for slot in &mut occupied[col..col + cell.colspan] {
+                // The current row is counted as well, it is released below.
+                *slot = cell.rowspan;
+            }
+            col += cell.colspan;
+        }
+        columns = columns.max(col).max(occupied.len());
+        for slot in &mut occupied {
+            *slot = slot.saturating_sub(1);
+        }
+    }
+
+    columns.max(1)
+}
+
+fn html_span(attrs: &[(String, String)], key: &str) -> usize {
+    attrs
+        .iter()
+        .find(|(name, _)| name == key)
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .filter(|span| *span > 0)
+        .unwrap_or(1)
+}
+
+/// Read the alignment from `style="text-align: right;"` or `align="right"`.
+fn html_align(attrs: &[(String, String)]) -> Option<Align> {
+    let mut align = None;
+    for (name, value) in attrs {
+        match name.as_str() {
+            "align" => align = Some(value.trim().to_lowercase()),
+            "style" => {
+                for decl in value.split(';') {
+                    if let Some((prop, val)) = decl.split_once(':') {
+                        if prop.trim().eq_ignore_ascii_case("text-align") {
+                            align = Some(val.trim().to_lowercase());
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
     }
 
-    format!("./downloads/{}", file_name)
-}
\ No newline at end of file
+    match align.as_deref() {
+        Some("left") | Some("start") => Some(Align::Left),
+        Some("right") | Some("end") => Some(Align::Right),
+        Some("center") => Some(Align::Center),
+        _ => None,
+    }
+}
+
+/// Split the HTML into tags and text. Comments and `<style>`/`<script>` bodies are dropped.
+fn tokenize_html(html: &str) -> Vec<HtmlToken> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = after.find("-->").map_or("", |end| &after[end + 3..]);
+            continue;
+        }
+
+        if rest.starts_with('<') {
+            let end = match rest.find('>') {
+                Some(end) => end,
+                None => break,
+            };
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+
+            if let Some(name) = tag.strip_prefix('/') {
+                tokens.push(HtmlToken::Close(name.trim().to_lowercase()));
+                continue;
+            }
+            if tag.starts_with('!') || tag.starts_with('?') {
+                // <!DOCTYPE ...> or <?xml ...>
+                continue;
+            }
+
+            let tag = tag.trim_end_matches('/');
+            let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+            let name = tag[..name_end].to_lowercase();
+            let attrs = parse_html_attrs(&tag[name_end..]);
+
+            if name == "style" || name == "script" {
+                let close = format!("</{}", name);
+                rest = rest
+                    .to_ascii_lowercase()
+                    .find(&close)
+                    .and_then(|start| rest[start..].find('>').map(|end| &rest[start + end + 1..]))
+                    .unwrap_or("");
+                continue;
+            }
+
+            tokens.push(HtmlToken::Open { name, attrs });
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = decode_html_entities(&rest[..end]);
+            if !text.trim().is_empty() {
+                tokens.push(HtmlToken::Text(text));
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    tokens
+}
+
+/// Parse `key="value" key='value' key=value key` into pairs.
+fn parse_html_attrs(source: &str) -> Vec<(String, String)> {
+    let mut attrs = Vec::new();
+    let mut rest = source.trim_start();
+
+    while !rest.is_empty() {
+        let key_end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let key = rest[..key_end].to_lowercase();
+        rest = rest[key_end..].trim_start();
+
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            if let Some(quote) = after.chars().next().filter(|c| *c == '"' || *c == '\'') {
+                let inner = &after[1..];
+                let end = inner.find(quote).unwrap_or(inner.len());
+                value = inner[..end].to_string();
+                rest = inner.get(end + 1..).unwrap_or("");
+            } else {
+                let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                value = after[..end].to_string();
+                rest = &after[end..];
+            }
+        }
+
+        if !key.is_empty() {
+            attrs.push((key, decode_html_entities(&value)));
+        }
+        rest = rest.trim_start();
+    }
+
+    attrs
+}
+
+/// Decode the common named and numeric HTML entities.
+fn decode_html_entities(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        result += &rest[..start];
+        rest = &rest[start..];
+
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                "times" => Some('×'),
+                _ => {
+                    if let Some(hex) = entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                        u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+                    } else if let Some(dec) = entity.strip_prefix('#') {
+                        dec.parse().ok().and_then(char::from_u32)
+                    } else {
+                        None
+                    }
+                }
+            };
+            c.map(|c| (c, end))
+        });
+
+        match decoded {
+            Some((c, end)) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    result + rest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn media(json: serde_json::Value) -> Media {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn priority(mimes: &[&str]) -> Vec<String> {
+        normalize_mime_priority(&mimes.iter().map(|mime| mime.to_string()).collect::<Vec<_>>())
+    }
+
+    fn selected(media: &Media, priority: &[String]) -> Option<String> {
+        select_media(media, priority).map(|data| mime_type(data).to_string())
+    }
+
+    fn context(options: MediaOptions) -> Context {
+        Context::new(PathBuf::from("downloads"), "./downloads".to_string(), options)
+    }
+
+    #[test]
+    fn select_by_priority() {
+        let figure = media(serde_json::json!({
+            "text/plain": "<Figure size 640x480>",
+            "image/png": "iVBORw0KGgo=",
+            "image/svg+xml": "<svg></svg>",
+        }));
+        assert_eq!(selected(&figure, &default_mime_priority()).as_deref(), Some("image/svg+xml"));
+        assert_eq!(selected(&figure, &priority(&["png", "svg", "plain"])).as_deref(), Some("image/png"));
+        assert_eq!(selected(&figure, &priority(&["latex", "plain"])).as_deref(), Some("text/plain"));
+        // HTML is only rendered for its tables.
+        let html = media(serde_json::json!({"text/html": "<b>x</b>", "text/plain": "x"}));
+        assert_eq!(selected(&html, &default_mime_priority()).as_deref(), Some("text/plain"));
+    }
+
+    #[test]
+    fn render_svg() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = Context::new(dir.path().to_path_buf(), "./downloads".to_string(), MediaOptions::default());
+        let figure = media(serde_json::json!({"image/svg+xml": ["<svg>", "</svg>"], "text/plain": "<Figure>"}));
+        match process_media(&figure, &mut ctx).as_slice() {
+            [Block::Figure { path }] => {
+                assert!(path.starts_with("./downloads/") && path.ends_with(".svg"), "{}", path);
+                assert_eq!(fs::read_to_string(&ctx.assets[0]).unwrap(), "<svg></svg>");
+            }
+            blocks => panic!("expected a figure, got {:?}", blocks),
+        }
+        assert!(ctx.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn select_excluded_by_priority() {
+        let figure = media(serde_json::json!({"text/plain": "<Figure>", "image/png": "iVBORw0KGgo="}));
+        assert_eq!(selected(&figure, &priority(&["svg", "jpeg"])), None);
+        assert_eq!(selected(&figure, &[]), None);
+
+        // Rendered with the fallback action of the first representation.
+        let mut ctx = context(MediaOptions { priority: priority(&["svg"]), ..MediaOptions::default() });
+        assert!(matches!(process_media(&figure, &mut ctx).as_slice(), [Block::Placeholder(_)]));
+        let warnings: Vec<String> = ctx.diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("allowed by mime-priority"), "{}", warnings[0]);
+    }
+
+    #[test]
+    fn fallback_actions() {
+        let options = MediaOptions::default();
+        assert_eq!(options.fallback("application/json"), FallbackAction::Code);
+        assert_eq!(options.fallback("application/javascript"), FallbackAction::Drop);
+        assert_eq!(options.fallback("application/vnd.jupyter.widget-view+json"), FallbackAction::Placeholder);
+
+        let options = MediaOptions {
+            fallbacks: HashMap::from([
+                ("application/json".to_string(), FallbackAction::Drop),
+                ("*".to_string(), FallbackAction::Code),
+            ]),
+            ..MediaOptions::default()
+        };
+        assert_eq!(options.fallback("application/json"), FallbackAction::Drop);
+        assert_eq!(options.fallback("application/vnd.jupyter.widget-view+json"), FallbackAction::Code);
+        assert_eq!(parse_fallback("json=placeholder").unwrap(), ("application/json".to_string(), FallbackAction::Placeholder));
+        assert!(parse_fallback("json").is_err());
+        assert!(parse_fallback("json=hide").is_err());
+    }
+
+    #[test]
+    fn render_fallbacks() {
+        let json = media(serde_json::json!({"application/json": {"a": 1}}));
+        let mut ctx = context(MediaOptions::default());
+        match process_media(&json, &mut ctx).as_slice() {
+            [Block::CodeBlock { lang, code }] => {
+                assert_eq!(lang.as_deref(), Some("json"));
+                assert!(code.contains("\"a\": 1"), "{}", code);
+            }
+            blocks => panic!("expected a code block, got {:?}", blocks),
+        }
+        assert_eq!(ctx.diagnostics.len(), 1);
+
+        let script = media(serde_json::json!({"application/javascript": "alert(1)"}));
+        let mut ctx = context(MediaOptions::default());
+        assert!(process_media(&script, &mut ctx).is_empty());
+        assert!(ctx.diagnostics.iter().next().unwrap().to_string().contains("dropped"));
+
+        // application/pdf can't be rendered, whatever the priority.
+        let pdf = media(serde_json::json!({"application/pdf": "JVBERi0=", "text/plain": "<PDF>"}));
+        assert_eq!(selected(&pdf, &priority(&["pdf", "plain"])).as_deref(), Some("text/plain"));
+    }
+
+    fn text(cell: &TableCell) -> String {
+        cell.content
+            .iter()
+            .map(|inline| match inline {
+                Inline::Text(text) => text.clone(),
+                Inline::Strong(content) => format!("*{}*", text(&TableCell::new(content.clone()))),
+                other => panic!("unexpected inline {:?}", other),
+            })
+            .collect()
+    }
+
+    fn table(html: &str) -> Table {
+        match parse_html_tables(html).as_slice() {
+            [Block::Table(table)] => table.clone(),
+            blocks => panic!("expected a table, got {:?}", blocks),
+        }
+    }
+
+    const DATAFRAME: &str = r#"<div>
+<table border="1" class="dataframe">
+  <thead>
+    <tr style="text-align: right;">
+      <th></th>
+      <th colspan="2">A</th>
+    </tr>
+    <tr>
+      <th>idx</th>
+      <th>x</th>
+      <th>y</th>
+    </tr>
+  </thead>
+  <tbody>
+    <tr><th>0</th><td>1</td><td>2</td></tr>
+    <tr><th rowspan="2">1</th><td>3</td><td>4</td></tr>
+    <tr><td>5</td><td>6</td></tr>
+  </tbody>
+</table>
+<p>3 rows &times; 2 columns</p>
+</div>"#;
+
+    #[test]
+    fn multi_row_thead() {
+        let table = table(DATAFRAME);
+        assert_eq!(table.header.len(), 2);
+        assert_eq!(table.rows.len(), 3);
+        assert_eq!(table.header[1].iter().map(text).collect::<Vec<_>>(), ["*idx*", "*x*", "*y*"]);
+    }
+
+    #[test]
+    fn colspan_and_rowspan() {
+        let table = table(DATAFRAME);
+        assert_eq!(table.columns, 3);
+        assert_eq!(table.header[0][1].colspan, 2);
+        assert_eq!(table.header[0][1].align, Some(Align::Right));
+        assert_eq!(table.rows[1][0].rowspan, 2);
+        // The row below the rowspan only has the two other cells.
+        assert_eq!(table.rows[2].iter().map(text).collect::<Vec<_>>(), ["5", "6"]);
+    }
+
+    #[test]
+    fn pandas_index_column() {
+        let table = table(DATAFRAME);
+        assert_eq!(text(&table.rows[0][0]), "*0*");
+        assert_eq!(text(&table.rows[0][1]), "1");
+        assert!(table.header[0][0].content.is_empty());
+    }
+
+    #[test]
+    fn rows_and_columns_note() {
+        assert_eq!(table(DATAFRAME).caption.as_deref(), Some("3 rows × 2 columns"));
+        assert_eq!(table("<table><tr><td>1</td></tr></table>").caption, None);
+    }
+
+    #[test]
+    fn nested_table_flattened() {
+        let table = table("<table><tr><td>a<table><tr><td>b</td><td>c</td></tr><tr><td>d</td></tr></table></td></tr></table>");
+        assert_eq!(table.rows.len(), 1);
+        assert_eq!(text(&table.rows[0][0]), "a\nb c\nd");
+    }
+}
@@ -1,18 +1,21 @@
+use crate::config::NotebookConfig;
+use crate::context::Context;
+use crate::document::{Block, Cell, CellKind, Document, Metadata};
 use crate::error::{NbconvertError, Result};
-use crate::markdown::parse_markdown;
-use crate::typst_content::{escape_code, escape_vec_code, TypstContent};
-use crate::media::process_media;
+use crate::locale::ScriptCounts;
+use crate::markdown::{parse_markdown, parse_markdown_lines, parse_options};
+use crate::media::{normalize_mime, parse_html_tables, process_media};
+use crate::tags::TagAction;
 
-use nbformat::v4::Output;
+use markdown::{mdast::Node, to_mdast};
+use nbformat::v4::{CellMetadata, Output};
+use serde::Deserialize;
+use serde_json::Value;
 /// Parse Jupyter Notebook.
 use nbformat::{parse_notebook, v4, legacy, Notebook};
 
 use std::fs;
 use std::path::Path;
-use std::sync::OnceLock;
-
-/// The Jupyter Notebook's language.
-static LANGUAGE: OnceLock<String> = OnceLock::new();
 
 /// Read a Jupyter Notebook from the given path.
 pub fn read_notebook<P: AsRef<Path>>(path: P) -> Result<Notebook> {
@@ -21,134 +24,569 @@ pub fn read_notebook<P: AsRef<Path>>(path: P) -> Result<Notebook> {
     Ok(notebook)
 }
 
-/// Convert a notebook to Typst.
-pub fn convert_notebook(notebook: &Notebook, download_dir: &Path) -> Result<TypstContent> {
+/// The conversion settings in `metadata.nbconvert_rust`, `None` if there are none.
+/// An unknown key is an error.
+pub fn notebook_config(notebook: &Notebook) -> Result<Option<NotebookConfig>> {
+    let metadata = match notebook {
+        Notebook::V4(notebook) => &notebook.metadata,
+        Notebook::Legacy(notebook) => &notebook.metadata,
+    };
+    match metadata.additional.get("nbconvert_rust") {
+        Some(value) => NotebookConfig::deserialize(value)
+            .map(Some)
+            .map_err(|e| NbconvertError::MetadataError(format!("nbconvert_rust: {}", e))),
+        None => Ok(None),
+    }
+}
 
+/// The sources of the markdown cells.
+fn markdown_sources(notebook: &Notebook) -> Vec<String> {
     match notebook {
-        Notebook::V4(notebook) => convert_v4_notebook(notebook, download_dir),
-        Notebook::Legacy(notebook) => convert_legacy_notebook(notebook, download_dir),
+        Notebook::V4(notebook) => notebook.cells.iter().filter_map(|cell| match cell {
+            v4::Cell::Markdown { source, .. } => Some(source.join("")),
+            _ => None,
+        }).collect(),
+        Notebook::Legacy(notebook) => notebook.cells.iter().filter_map(|cell| match cell {
+            legacy::Cell::Markdown { source, .. } => Some(source.join("")),
+            _ => None,
+        }).collect(),
+    }
+}
+
+/// The text of the first heading in the markdown cells, a sensible default title.
+pub fn first_heading(notebook: &Notebook) -> Option<String> {
+    markdown_sources(notebook).iter().enumerate().find_map(|(index, source)| {
+        let ast = to_mdast(source, &parse_options(index == 0)).ok()?;
+        ast.children()?.iter().find_map(|node| match node {
+            Node::Heading(_) => Some(node.to_string().trim().to_string()),
+            _ => None,
+        })
+    }).filter(|title| !title.is_empty())
+}
+
+/// Count the letters per script in the markdown cells, see [`ScriptCounts::detect`].
+pub fn script_counts(notebook: &Notebook, counts: &mut ScriptCounts) {
+    for source in markdown_sources(notebook) {
+        counts.add_text(&source);
+    }
+}
+
+/// Convert a notebook to a document.
+/// A malformed cell doesn't stop the conversion, it is reported to `ctx.diagnostics`
+/// and shown as an error box in its place.
+pub fn convert_notebook(notebook: &Notebook, ctx: &mut Context) -> Result<Document> {
+
+    match notebook {
+        Notebook::V4(notebook) => convert_v4_notebook(notebook, ctx),
+        Notebook::Legacy(notebook) => convert_legacy_notebook(notebook, ctx),
     }
 
 }
 
 /// Parse a V4 Version notebook.
-pub fn convert_v4_notebook(notebook: &v4::Notebook, download_dir: &Path) -> Result<TypstContent> {
-    let langugae = match &notebook.metadata.language_info {
+pub fn convert_v4_notebook(notebook: &v4::Notebook, ctx: &mut Context) -> Result<Document> {
+    ctx.language = match &notebook.metadata.language_info {
         Some(info) => info.name.clone(),
         None => "text".to_owned()
     };
 
-    LANGUAGE.set(langugae)
-            .map_err(|v| NbconvertError::OnceLockError(v))?;
-
 
-    let mut result = String::new();
+    let mut document = Document::default();
 
-    for cell in &notebook.cells {
-        match cell {
-            v4::Cell::Code { id: _, metadata: _, execution_count, source, outputs } => {
-                result += &parse_code(source, execution_count);
-                result += &parse_output(outputs, download_dir);
+    for (index, cell) in notebook.cells.iter().enumerate() {
+        let view = match cell {
+            v4::Cell::Code { id, metadata, execution_count, source, outputs } => CellView {
+                execution_count: *execution_count,
+                outputs,
+                ..CellView::new(index, Some(id.to_string()), CellKind::Code, metadata, source)
+            },
+            v4::Cell::Markdown { id, metadata, source, attachments } => CellView {
+                attachments,
+                ..CellView::new(index, Some(id.to_string()), CellKind::Markdown, metadata, source)
+            },
+            v4::Cell::Raw { id, metadata, source } => {
+                CellView::new(index, Some(id.to_string()), CellKind::Raw, metadata, source)
             }
-            v4::Cell::Markdown { id:_ , metadata: _, source, attachments } => {
-                result += &parse_markdown(source, attachments, download_dir);
-            }
-            v4::Cell::Raw { id: _, metadata: _, source } => {
-                result += source.join("\n").as_str();
-            }
-        }
+        };
+        document.cells.extend(convert_cell(view, ctx));
     }
 
-    Ok(TypstContent { content: result })
+    document.apply_tag_actions();
+    document.metadata = read_metadata(&notebook.metadata, ctx);
+
+    Ok(document)
 }
 
 
 /// Parse a legacy version notebook.
-pub fn convert_legacy_notebook(notebook: &legacy::Notebook, download_dir: &Path) -> Result<TypstContent> {
-    let langugae = match &notebook.metadata.language_info {
+pub fn convert_legacy_notebook(notebook: &legacy::Notebook, ctx: &mut Context) -> Result<Document> {
+    ctx.language = match &notebook.metadata.language_info {
         Some(info) => info.name.clone(),
         None => "text".to_owned()
     };
 
-    LANGUAGE.set(langugae)
-            .map_err(|v| NbconvertError::OnceLockError(v))?;
+    let mut document = Document::default();
 
-    let mut result = String::new();
-
-    for cell in &notebook.cells {
-        match cell {
-            legacy::Cell::Code { id: _, metadata: _, execution_count, source, outputs } => {
-                result += &parse_code(source, execution_count);
-                result += &parse_output(outputs, download_dir);
-            }
-            legacy::Cell::Markdown { id: _, metadata: _, source, attachments } => {
-                result += &parse_markdown(source, attachments, download_dir);
+    for (index, cell) in notebook.cells.iter().enumerate() {
+        let view = match cell {
+            legacy::Cell::Code { id, metadata, execution_count, source, outputs } => CellView {
+                execution_count: *execution_count,
+                outputs,
+                ..CellView::new(index, id.as_ref().map(|id| id.to_string()), CellKind::Code, metadata, source)
+            },
+            legacy::Cell::Markdown { id, metadata, source, attachments } => CellView {
+                attachments,
+                ..CellView::new(index, id.as_ref().map(|id| id.to_string()), CellKind::Markdown, metadata, source)
+            },
+            legacy::Cell::Raw { id, metadata, source } => {
+                CellView::new(index, id.as_ref().map(|id| id.to_string()), CellKind::Raw, metadata, source)
             }
-            legacy::Cell::Raw { id: _, metadata: _, source } => {
-                result += source.join("\n").as_str();
+        };
+        document.cells.extend(convert_cell(view, ctx));
+    }
+
+    document.apply_tag_actions();
+    document.metadata = read_metadata(&notebook.metadata, ctx);
+
+
+    Ok(document)
+}
+
+/// The metadata of the document: the front matter read in `ctx`,
+/// completed by the `title` and `authors` of the notebook metadata.
+fn read_metadata(metadata: &v4::Metadata, ctx: &mut Context) -> Metadata {
+    let mut result = std::mem::take(&mut ctx.metadata);
+    let notebook_metadata = serde_json::to_value(metadata)
+        .map_err(|e| e.to_string())
+        .and_then(|value| Metadata::from_value(&value).map_err(|e| e.to_string()));
+    match notebook_metadata {
+        Ok(metadata) => result.merge(metadata),
+        Err(message) => ctx.warn(NbconvertError::MetadataError(message)),
+    }
+    result
+}
+
+/// A cell of a v4 or a legacy notebook, so that both are converted the same way.
+struct CellView<'a> {
+    index: usize,
+    id: Option<String>,
+    kind: CellKind,
+    metadata: &'a CellMetadata,
+    source: &'a [String],
+    attachments: &'a Option<Value>,
+    execution_count: Option<i32>,
+    outputs: &'a [Output],
+}
+
+impl<'a> CellView<'a> {
+    fn new(index: usize, id: Option<String>, kind: CellKind, metadata: &'a CellMetadata, source: &'a [String]) -> Self {
+        CellView {
+            index,
+            id,
+            kind,
+            metadata,
+            source,
+            attachments: &None,
+            execution_count: None,
+            outputs: &[],
+        }
+    }
+}
+
+/// Convert a cell, with the actions of its tags, see [`Document::apply_tag_actions`].
+/// `None` if the cell is removed: it isn't converted at all.
+fn convert_cell(view: CellView, ctx: &mut Context) -> Option<Cell> {
+    let tags = view.metadata.tags.as_deref().unwrap_or_default();
+    let mut actions = ctx.tags.actions(tags);
+    if ctx.respect_hidden {
+        actions.extend(hidden_actions(view.metadata));
+    }
+    if actions.contains(&TagAction::RemoveCell) {
+        return None;
+    }
+
+    ctx.enter_cell(view.index, view.id.clone());
+    if view.kind == CellKind::Markdown {
+        ctx.markdown_cells += 1;
+    }
+
+    let (input, input_lines) = match view.kind {
+        CellKind::Code => (vec![parse_code(view.source, &view.execution_count, ctx)], vec![1]),
+        CellKind::Markdown => parse_markdown_lines(view.source, view.attachments, ctx.markdown_cells == 1, ctx),
+        CellKind::Raw => {
+            let blocks = parse_raw(view.source, view.metadata, ctx);
+            let lines = vec![1; blocks.len()];
+            (blocks, lines)
+        }
+    };
+
+    // An error is expected, or not shown.
+    let errors_reported = ![TagAction::RaisesException, TagAction::RemoveOutput, TagAction::HideOutput]
+        .iter()
+        .any(|action| actions.contains(action));
+    let mut outputs = parse_output(view.outputs, errors_reported, ctx);
+    if ctx.respect_hidden && view.metadata.scrolled == Some(true) {
+        for output in &mut outputs {
+            if let Block::Output(text) = output {
+                *text = truncate_lines(text, SCROLLED_OUTPUT_LINES);
             }
         }
     }
 
+    Some(Cell {
+        index: view.index,
+        id: view.id,
+        kind: view.kind,
+        actions,
+        input,
+        input_lines,
+        outputs,
+        source: view.source.join(""),
+    })
+}
+
 
-    Ok(TypstContent { content: result })
+/// The number of lines of a scrolled output, i.e. what the output area shows.
+pub const SCROLLED_OUTPUT_LINES: usize = 20;
+
+/// What the cell looks like in Jupyter: the source or the outputs collapsed by the author,
+/// with `jupyter.source_hidden` and `jupyter.outputs_hidden` (JupyterLab) or `collapsed` (classic).
+fn hidden_actions(metadata: &CellMetadata) -> Vec<TagAction> {
+    let mut actions = Vec::new();
+    let jupyter = metadata.jupyter.as_ref();
+    if jupyter.and_then(|jupyter| jupyter.source_hidden) == Some(true) {
+        actions.push(TagAction::HideInput);
+    }
+    if jupyter.and_then(|jupyter| jupyter.outputs_hidden) == Some(true) || metadata.collapsed == Some(true) {
+        actions.push(TagAction::HideOutput);
+    }
+    actions
 }
 
+/// Keep the first `max` lines of the text, followed by a note of how many are left out.
+fn truncate_lines(text: &str, max: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.len() <= max {
+        return text.to_string();
+    }
+    format!("{}\n... ({} more lines)\n", lines[..max].join("\n"), lines.len() - max)
+}
 
-/// Parse the given code. Place it in the style of code blocks.
-fn parse_code(code: &Vec<String>, count: &Option<i32>) -> String {
-    let mut result = String::new();
-
-    // Refer to the [template.typ] to see the def. of code-block.
-    result += "#code-block(";
-    result += format!(
-        "\"{}\", ", escape_vec_code(code)
-    ).as_str();
-    match count {
-        Some(count) => {
-            result += format!(
-                "lang: \"{}\", count: {})\n\n",
-                LANGUAGE.get().unwrap(),
-                count
-            ).as_str();
-        }
+/// Parse a raw cell according to its `raw_mimetype` (or `format`) metadata.
+/// A raw cell without a format is dropped with a warning, it is often meant for another
+/// output format of nbconvert.
+fn parse_raw(source: &[String], metadata: &CellMetadata, ctx: &mut Context) -> Vec<Block> {
+    let format = metadata
+        .additional
+        .get("raw_mimetype")
+        .and_then(|mime| mime.as_str())
+        .or(metadata.format.as_deref())
+        .filter(|mime| !mime.trim().is_empty())
+        .map(normalize_mime);
+
+    let source = source.join("");
+    match format.as_deref() {
         None => {
-            result += format!(
-                "lang: \"{}\", count: none)\n\n",
-                LANGUAGE.get().unwrap(),
-            ).as_str();
+            if !source.trim().is_empty() {
+                ctx.warn(NbconvertError::UntypedRawError { location: ctx.location(None) });
+            }
+            Vec::new()
+        }
+        Some("text/typst") => vec![Block::Raw(source)],
+        Some("text/latex") => vec![Block::Latex(source)],
+        Some("text/markdown") => parse_markdown(std::slice::from_ref(&source), &None, ctx),
+        Some("text/html") => {
+            let tables = parse_html_tables(&source);
+            if tables.is_empty() && !source.trim().is_empty() {
+                ctx.warn(NbconvertError::RawHtmlError { location: ctx.location(None) });
+            }
+            tables
+        }
+        Some(format) => {
+            ctx.warn(NbconvertError::UnsupportedRawError {
+                location: ctx.location(None),
+                format: format.to_string(),
+            });
+            Vec::new()
         }
     }
-    result
+}
+
+/// Parse the given code. Place it in the style of code blocks.
+fn parse_code(code: &[String], count: &Option<i32>, ctx: &Context) -> Block {
+    Block::Code {
+        lang: ctx.language.clone(),
+        source: code.join(""),
+        execution_count: *count,
+    }
 }
 
 /// Parse an ouput of a given code block.
-fn parse_output(outputs: &Vec<Output>, download_dir: &Path) -> String {
-    let mut result = String::new();
+/// An error is reported as a warning if `errors_reported`.
+fn parse_output(outputs: &[Output], errors_reported: bool, ctx: &mut Context) -> Vec<Block> {
+    let mut result = Vec::new();
 
     for output in outputs {
         match output {
             v4::Output::DisplayData(data) => {
-                result += &process_media(&data.data, download_dir);
+                result.extend(process_media(&data.data, ctx));
             }
             v4::Output::ExecuteResult(data) => {
-                result += &process_media(&data.data, download_dir);
+                result.extend(process_media(&data.data, ctx));
             }
             v4::Output::Stream { name: _, text } => {
-                result += format!(
-                    "#output-block(\"{}\")\n\n",
-                    escape_code(&text.0)
-                ).as_str();
+                result.push(Block::Output(text.0.clone()));
             }
             v4::Output::Error(error) => {
-                result += format!(
-                    "#output-block(\"{}\")\n\n",
-                    escape_vec_code(&error.traceback)
-                ).as_str();
+                if errors_reported {
+                    ctx.warn(NbconvertError::ExecutionError {
+                        location: ctx.location(None),
+                        ename: error.ename.clone(),
+                        evalue: error.evalue.clone(),
+                    });
+                }
+                result.push(Block::Output(error.traceback.join("\n")));
             }
         }
     }
 
     result
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    use serde_json::json;
+
+    use crate::error::{Diagnostic, Severity};
+    use crate::media::MediaOptions;
+
+    fn context() -> Context {
+        Context::new(PathBuf::from("downloads"), "./downloads".to_string(), MediaOptions::default())
+    }
+
+    /// Convert a v4 notebook of the given cells.
+    fn convert(cells: Vec<Value>, ctx: &mut Context) -> Document {
+        let notebook = json!({"nbformat": 4, "nbformat_minor": 5, "metadata": {}, "cells": cells});
+        convert_notebook(&parse_notebook(&notebook.to_string()).unwrap(), ctx).unwrap()
+    }
+
+    /// A code cell with the given tags, printing `out`.
+    fn code_cell(id: &str, tags: &[&str]) -> Value {
+        json!({
+            "cell_type": "code", "id": id, "metadata": {"tags": tags}, "execution_count": 1,
+            "source": ["print('out')"],
+            "outputs": [{"output_type": "stream", "name": "stdout", "text": ["out\n"]}],
+        })
+    }
+
+    /// A code cell with the given tags, raising an error.
+    fn error_cell(tags: &[&str]) -> Value {
+        json!({
+            "cell_type": "code", "id": "error", "metadata": {"tags": tags}, "execution_count": 1,
+            "source": ["1 / 0"],
+            "outputs": [{"output_type": "error", "ename": "ZeroDivisionError", "evalue": "division by zero", "traceback": ["ZeroDivisionError: division by zero"]}],
+        })
+    }
+
+    fn placeholder(blocks: &[Block]) -> Option<&str> {
+        match blocks {
+            [Block::Placeholder(label)] => Some(label),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn tag_actions() {
+        let mut ctx = context();
+        let tags = ["none", "remove-cell", "remove-input", "remove-output", "hide-input", "hide-output"];
+        let cells = tags.iter().map(|tag| code_cell(tag, &[tag])).collect();
+        let document = convert(cells, &mut ctx);
+
+        let ids: Vec<&str> = document.cells.iter().filter_map(|cell| cell.id.as_deref()).collect();
+        assert_eq!(ids, vec!["none", "remove-input", "remove-output", "hide-input", "hide-output"]);
+        let [none, remove_input, remove_output, hide_input, hide_output] = &document.cells[..] else {
+            unreachable!()
+        };
+        assert!(matches!(none.input.as_slice(), [Block::Code { .. }]));
+        assert!(matches!(none.outputs.as_slice(), [Block::Output(_)]));
+        assert!(remove_input.input.is_empty());
+        assert_eq!(remove_input.outputs.len(), 1);
+        assert_eq!(remove_output.input.len(), 1);
+        assert!(remove_output.outputs.is_empty());
+        assert_eq!(placeholder(&hide_input.input), Some("[input hidden]"));
+        assert_eq!(hide_input.outputs.len(), 1);
+        assert_eq!(hide_output.input.len(), 1);
+        assert_eq!(placeholder(&hide_output.outputs), Some("[output hidden]"));
+        assert!(ctx.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn custom_tag_action() {
+        let mut ctx = context();
+        let (tag, action) = crate::tags::parse_tag_action("solution=remove-cell").unwrap();
+        ctx.tags.actions.insert(tag, action);
+        let document = convert(vec![code_cell("kept", &["other"]), code_cell("solution", &["solution"])], &mut ctx);
+        assert_eq!(document.cells.len(), 1);
+        assert_eq!(document.cells[0].id.as_deref(), Some("kept"));
+    }
+
+    /// An untagged error output is a warning, which fails `--strict`.
+    #[test]
+    fn error_output() {
+        let mut ctx = context();
+        let document = convert(vec![error_cell(&[])], &mut ctx);
+        assert!(matches!(document.cells[0].outputs.as_slice(), [Block::Output(text)] if text.contains("ZeroDivisionError")));
+        let diagnostics: Vec<&Diagnostic> = ctx.diagnostics.iter().collect();
+        assert!(matches!(
+            diagnostics.as_slice(),
+            [Diagnostic { severity: Severity::Warning, error: NbconvertError::ExecutionError { ename, .. } }] if ename == "ZeroDivisionError"
+        ));
+        assert!(!ctx.diagnostics.has_errors());
+    }
+
+    #[test]
+    fn expected_error_output() {
+        for tags in [["raises-exception"], ["remove-output"], ["hide-output"]] {
+            let mut ctx = context();
+            convert(vec![error_cell(&tags)], &mut ctx);
+            assert!(ctx.diagnostics.is_empty(), "{:?}", tags);
+        }
+        // Still rendered when it is expected.
+        let document = convert(vec![error_cell(&["raises-exception"])], &mut context());
+        assert_eq!(document.cells[0].outputs.len(), 1);
+    }
+
+    /// A code cell with the given metadata, printing `lines` lines.
+    fn cell_with_metadata(metadata: Value, lines: usize) -> Value {
+        let text: Vec<String> = (1..=lines).map(|line| format!("{}\n", line)).collect();
+        json!({
+            "cell_type": "code", "id": "cell", "metadata": metadata, "execution_count": 1,
+            "source": ["print(1)"],
+            "outputs": [{"output_type": "stream", "name": "stdout", "text": text}],
+        })
+    }
+
+    fn convert_hidden(metadata: Value, lines: usize, respect_hidden: bool) -> Cell {
+        let mut ctx = context();
+        ctx.respect_hidden = respect_hidden;
+        convert(vec![cell_with_metadata(metadata, lines)], &mut ctx).cells.remove(0)
+    }
+
+    #[test]
+    fn collapsed_parts() {
+        let lab = json!({"jupyter": {"source_hidden": true, "outputs_hidden": true}});
+        let cell = convert_hidden(lab.clone(), 1, true);
+        assert_eq!(placeholder(&cell.input), Some("[input hidden]"));
+        assert_eq!(placeholder(&cell.outputs), Some("[output hidden]"));
+
+        let classic = json!({"collapsed": true});
+        let cell = convert_hidden(classic.clone(), 1, true);
+        assert!(matches!(cell.input.as_slice(), [Block::Code { .. }]));
+        assert_eq!(placeholder(&cell.outputs), Some("[output hidden]"));
+
+        // Shown in full without `respect-hidden`.
+        for metadata in [lab, classic] {
+            let cell = convert_hidden(metadata, 1, false);
+            assert!(matches!(cell.input.as_slice(), [Block::Code { .. }]));
+            assert!(matches!(cell.outputs.as_slice(), [Block::Output(_)]));
+        }
+    }
+
+    #[test]
+    fn scrolled_outputs() {
+        let output = |cell: Cell| match cell.outputs.as_slice() {
+            [Block::Output(text)] => text.clone(),
+            blocks => panic!("{:?}", blocks),
+        };
+        let text = output(convert_hidden(json!({"scrolled": true}), 30, true));
+        assert_eq!(text.lines().count(), SCROLLED_OUTPUT_LINES + 1);
+        assert!(text.starts_with("1\n2\n"));
+        assert!(text.ends_with("20\n... (10 more lines)\n"), "{}", text);
+
+        // Short enough, or not scrolled, or without `respect-hidden`.
+        assert_eq!(output(convert_hidden(json!({"scrolled": true}), 20, true)).lines().count(), 20);
+        assert_eq!(output(convert_hidden(json!({"scrolled": false}), 30, true)).lines().count(), 30);
+        assert_eq!(output(convert_hidden(json!({"scrolled": true}), 30, false)).lines().count(), 30);
+    }
+
+    /// Convert a raw cell with the given metadata, and return its blocks and diagnostics.
+    fn convert_raw(metadata: Value, source: &str) -> (Vec<Block>, Vec<String>) {
+        let mut ctx = context();
+        let cell = json!({"cell_type": "raw", "id": "raw", "metadata": metadata, "source": [source]});
+        let mut document = convert(vec![cell], &mut ctx);
+        let diagnostics = ctx.diagnostics.iter().map(Diagnostic::to_string).collect();
+        (document.cells.remove(0).input, diagnostics)
+    }
+
+    #[test]
+    fn raw_formats() {
+        let (blocks, diagnostics) = convert_raw(json!({"raw_mimetype": "text/typst"}), "#lorem(5)");
+        assert!(matches!(blocks.as_slice(), [Block::Raw(source)] if source == "#lorem(5)"));
+        assert!(diagnostics.is_empty());
+
+        // A short name, or the `format` of older notebooks.
+        let (blocks, _) = convert_raw(json!({"raw_mimetype": "latex"}), "\\LaTeX");
+        assert!(matches!(blocks.as_slice(), [Block::Latex(source)] if source == "\\LaTeX"));
+        let (blocks, _) = convert_raw(json!({"format": "text/latex"}), "\\LaTeX");
+        assert!(matches!(blocks.as_slice(), [Block::Latex(_)]));
+
+        let (blocks, _) = convert_raw(json!({"raw_mimetype": "text/markdown"}), "# Title");
+        assert!(matches!(blocks.as_slice(), [Block::Heading { level: 1, .. }]));
+
+        let table = "<table><tr><th>a</th></tr><tr><td>1</td></tr></table>";
+        let (blocks, diagnostics) = convert_raw(json!({"raw_mimetype": "text/html"}), table);
+        assert!(matches!(blocks.as_slice(), [Block::Table(_)]));
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn raw_formats_left_out() {
+        let (blocks, diagnostics) = convert_raw(json!({"raw_mimetype": "text/html"}), "<b>bold</b>");
+        assert!(blocks.is_empty());
+        assert_eq!(diagnostics.len(), 1);
+
+        let (blocks, diagnostics) = convert_raw(json!({"raw_mimetype": "text/restructuredtext"}), "**bold**");
+        assert!(blocks.is_empty());
+        assert!(diagnostics[0].contains("text/restructuredtext"), "{:?}", diagnostics);
+
+        // Without a format, only a cell with some text is reported.
+        let (blocks, diagnostics) = convert_raw(json!({}), "for another output");
+        assert!(blocks.is_empty());
+        assert_eq!(diagnostics.len(), 1);
+        let (_, diagnostics) = convert_raw(json!({"raw_mimetype": ""}), " \n");
+        assert!(diagnostics.is_empty());
+    }
+
+    fn markdown_cell(id: &str, source: &str) -> Value {
+        json!({"cell_type": "markdown", "id": id, "metadata": {}, "source": [source]})
+    }
+
+    #[test]
+    fn front_matter_of_the_first_markdown_cell() {
+        let mut ctx = context();
+        let cells = vec![
+            code_cell("code", &[]),
+            markdown_cell("first", "---\ntitle: First\nkeywords: [a, b]\n---\n# Heading"),
+            markdown_cell("second", "---\ntitle: Second\n---\n"),
+        ];
+        let document = convert(cells, &mut ctx);
+        assert_eq!(document.metadata.title.as_deref(), Some("First"));
+        assert_eq!(document.metadata.keywords, vec!["a", "b"]);
+        // Only the heading is left of the first cell, the other one has thematic breaks.
+        assert!(matches!(document.cells[1].input.as_slice(), [Block::Heading { level: 1, .. }]));
+        assert!(matches!(document.cells[2].input.first(), Some(Block::ThematicBreak)));
+    }
+
+    #[test]
+    fn toml_front_matter() {
+        let mut ctx = context();
+        let document = convert(vec![markdown_cell("first", "+++\ntitle = \"First\"\n+++\nText")], &mut ctx);
+        assert_eq!(document.metadata.title.as_deref(), Some("First"));
+        assert!(matches!(document.cells[0].input.as_slice(), [Block::Paragraph(_)]));
+        assert!(ctx.diagnostics.is_empty());
+    }
+}
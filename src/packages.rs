@@ -0,0 +1,360 @@
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::error::{NbconvertError, Result};
+use crate::template::{BLOCKS, BUILTIN_TEMPLATES};
+
+/// A Typst package as imported, `@namespace/name:version`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PackageRef {
+    pub namespace: String,
+    pub name: String,
+    pub version: String,
+}
+
+impl PackageRef {
+    /// The directory of the package in a packages directory, `namespace/name/version`.
+    pub fn subdir(&self) -> PathBuf {
+        Path::new(&self.namespace).join(&self.name).join(&self.version)
+    }
+}
+
+impl fmt::Display for PackageRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "@{}/{}:{}", self.namespace, self.name, self.version)
+    }
+}
+
+impl FromStr for PackageRef {
+    type Err = NbconvertError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || NbconvertError::InvalidArgument(format!("invalid package \"{}\", expected @namespace/name:version", s));
+        let (namespace, rest) = s.strip_prefix('@').and_then(|s| s.split_once('/')).ok_or_else(invalid)?;
+        let (name, version) = rest.split_once(':').ok_or_else(invalid)?;
+        let valid_name = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+        let valid_version = version.split('.').count() == 3 && version.split('.').all(|part| part.parse::<u32>().is_ok());
+        if !valid_name(namespace) || !valid_name(name) || !valid_version {
+            return Err(invalid());
+        }
+        Ok(PackageRef { namespace: namespace.to_string(), name: name.to_string(), version: version.to_string() })
+    }
+}
+
+/// The packages of the built-in templates and `nbconvert.typ`, at the versions they import.
+pub fn template_packages() -> Vec<PackageRef> {
+    let mut packages = Vec::new();
+    let sources = BUILTIN_TEMPLATES.iter().map(|template| template.source);
+    for source in std::iter::once(BLOCKS).chain(sources) {
+        for package in imports(source).into_iter().filter_map(|target| target.parse::<PackageRef>().ok()) {
+            if !packages.contains(&package) {
+                packages.push(package);
+            }
+        }
+    }
+    packages
+}
+
+/// The directory of the local packages: `$TYPST_PACKAGE_PATH`, by default `typst/packages`
+/// in the data directory, as for the typst binary.
+pub fn data_packages_dir() -> Option<PathBuf> {
+    env::var_os("TYPST_PACKAGE_PATH")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| data_dir().map(|dir| dir.join("typst").join("packages")))
+}
+
+/// The package cache: the given directory, or `$TYPST_PACKAGE_CACHE_PATH`, by default
+/// `typst/packages` in the cache directory, where typst downloads the `@preview` packages.
+pub fn package_cache_dir(cache: Option<&Path>) -> Option<PathBuf> {
+    cache.map(Path::to_path_buf).or_else(|| {
+        env::var_os("TYPST_PACKAGE_CACHE_PATH")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| cache_dir().map(|dir| dir.join("typst").join("packages")))
+    })
+}
+
+/// The directory of an installed package, in the local packages, else in the package cache.
+pub fn find_package(package: &PackageRef, cache: Option<&Path>) -> Option<PathBuf> {
+    [data_packages_dir(), package_cache_dir(cache)]
+        .into_iter()
+        .flatten()
+        .map(|dir| dir.join(package.subdir()))
+        .find(|dir| dir.join("typst.toml").is_file())
+}
+
+/// The packages a Typst file needs that aren't installed: its imports, the ones of the
+/// local files it imports, and the ones of the installed packages it imports.
+pub fn missing_packages(typ: &Path, cache: Option<&Path>) -> Vec<PackageRef> {
+    let mut missing = Vec::new();
+    let mut seen = HashSet::new();
+    let mut files = vec![typ.to_path_buf()];
+    while let Some(file) = files.pop() {
+        if !seen.insert(file.clone()) {
+            continue;
+        }
+        let Ok(text) = fs::read_to_string(&file) else {
+            continue;
+        };
+        let dir = file.parent().unwrap_or(Path::new("."));
+        for target in imports(&text) {
+            match target.parse::<PackageRef>() {
+                Ok(package) => match find_package(&package, cache) {
+                    Some(package_dir) => files.push(package_dir.join(entrypoint(&package_dir))),
+                    None if !missing.contains(&package) => missing.push(package),
+                    None => {}
+                },
+                Err(_) if target.ends_with(".typ") => files.push(dir.join(target)),
+                Err(_) => {}
+            }
+        }
+    }
+    missing
+}
+
+/// The paths of the `#import` and `#include` statements of some Typst source, each at the
+/// start of a line, or after a block comment. The line comments, the text and the
+/// imports in code blocks are skipped.
+fn imports(text: &str) -> Vec<&str> {
+    let mut targets = Vec::new();
+    let mut in_comment = false;
+    for line in text.lines() {
+        let mut line = line.trim_start();
+        if in_comment || line.starts_with("/*") {
+            match line.split_once("*/") {
+                Some((_, rest)) => line = rest.trim_start(),
+                None => {
+                    in_comment = true;
+                    continue;
+                }
+            }
+            in_comment = false;
+        }
+        let statement = line.strip_prefix("#import").or_else(|| line.strip_prefix("#include"));
+        let target = statement
+            .and_then(|rest| rest.trim_start().strip_prefix('"'))
+            .and_then(|rest| rest.split_once('"'));
+        if let Some((target, _)) = target {
+            targets.push(target);
+        }
+    }
+    targets
+}
+
+/// The entry point of a package, from its `typst.toml`.
+fn entrypoint(package_dir: &Path) -> String {
+    manifest_field(&package_dir.join("typst.toml"), "entrypoint").unwrap_or_else(|| "lib.typ".to_string())
+}
+
+/// A field of the `[package]` table of a `typst.toml`.
+fn manifest_field(manifest: &Path, field: &str) -> Option<String> {
+    let manifest: toml::Value = toml::from_str(&fs::read_to_string(manifest).ok()?).ok()?;
+    Some(manifest.get("package")?.get(field)?.as_str()?.to_string())
+}
+
+/// Install the packages of a `.tar.gz` archive into the package cache, and return them.
+/// The archive is a package, as downloaded from the Typst registry (installed in the
+/// `preview` namespace), or packages directories, `namespace/name/version/...`, as made by
+/// [`export_packages`]. An installed package is replaced.
+pub fn install_archive(archive: &Path, cache: &Path) -> Result<Vec<PackageRef>> {
+    fs::create_dir_all(cache)?;
+    // Unpack next to the packages, to move them in place.
+    let unpacked = tempfile::tempdir_in(cache)?;
+    tar::Archive::new(GzDecoder::new(File::open(archive)?))
+        .unpack(unpacked.path())
+        .map_err(|e| NbconvertError::InvalidArgument(format!("{}: invalid package archive: {}", archive.display(), e)))?;
+
+    let mut packages = Vec::new();
+    let manifest = unpacked.path().join("typst.toml");
+    if manifest.is_file() {
+        let field = |field| {
+            manifest_field(&manifest, field).ok_or_else(|| {
+                NbconvertError::InvalidArgument(format!("{}: typst.toml has no package {}", archive.display(), field))
+            })
+        };
+        let package = PackageRef { namespace: "preview".to_string(), name: field("name")?, version: field("version")? };
+        packages.push((unpacked.path().to_path_buf(), package));
+    } else {
+        for dir in subdirs(unpacked.path())? {
+            for name in subdirs(&dir)? {
+                for version in subdirs(&name)? {
+                    if version.join("typst.toml").is_file() {
+                        let part = |dir: &Path| dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+                        let package = PackageRef { namespace: part(&dir), name: part(&name), version: part(&version) };
+                        packages.push((version, package));
+                    }
+                }
+            }
+        }
+    }
+    if packages.is_empty() {
+        return Err(NbconvertError::InvalidArgument(format!("{}: no package in the archive", archive.display())));
+    }
+
+    for (dir, package) in &packages {
+        let target = cache.join(package.subdir());
+        if target.exists() {
+            fs::remove_dir_all(&target)?;
+        }
+        fs::create_dir_all(target.parent().unwrap_or(cache))?;
+        fs::rename(dir, &target)?;
+    }
+    Ok(packages.into_iter().map(|(_, package)| package).collect())
+}
+
+/// Write the installed packages to a `.tar.gz` archive, to install them with
+/// [`install_archive`] on another machine.
+pub fn export_packages(packages: &[PackageRef], archive: &Path, cache: Option<&Path>) -> Result<()> {
+    let mut builder = tar::Builder::new(GzEncoder::new(File::create(archive)?, Compression::default()));
+    for package in packages {
+        let dir = find_package(package, cache).ok_or_else(|| {
+            NbconvertError::InvalidArgument(format!("package {} is not installed, compile a notebook once to download it", package))
+        })?;
+        builder.append_dir_all(package.subdir(), dir)?;
+    }
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+fn subdirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            dirs.push(path);
+        }
+    }
+    Ok(dirs)
+}
+
+/// The data directory: `$XDG_DATA_HOME`, by default `~/.local/share`, on macOS
+/// `~/Library/Application Support`, on Windows `%APPDATA%`.
+fn data_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home_dir().map(|home| home.join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home_dir().map(|home| home.join(".local/share")))
+    }
+}
+
+/// The cache directory: `$XDG_CACHE_HOME`, by default `~/.cache`, on macOS
+/// `~/Library/Caches`, on Windows `%LOCALAPPDATA%`.
+fn cache_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home_dir().map(|home| home.join("Library/Caches"))
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home_dir().map(|home| home.join(".cache")))
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME").map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The package cache of the tests, with stand-ins of the packages of the templates.
+    fn fixture_packages() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/packages")
+    }
+
+    fn package(name: &str, version: &str) -> PackageRef {
+        PackageRef { namespace: "preview".to_string(), name: name.to_string(), version: version.to_string() }
+    }
+
+    #[test]
+    fn import_statements() {
+        let text = r##"#import "@preview/a:0.1.0": * // "@preview/b:0.1.0"
+  #include "chapter.typ"
+// #import "@preview/c:0.1.0"
+/* #import "@preview/d:0.1.0"
+#import "@preview/e:0.1.0"
+*/ #import "f.typ"
+Write #import "g.typ" in the text, or \#import "h.typ".
+#{ import "i.typ" }
+#let path = "#import \"j.typ\""
+"##;
+        assert_eq!(imports(text), vec!["@preview/a:0.1.0", "chapter.typ", "f.typ"]);
+    }
+
+    #[test]
+    fn packages_of_the_templates() {
+        let packages = template_packages();
+        assert_eq!(packages, vec![package("ansi-render", "0.8.0"), package("mitex", "0.2.5"), package("cuti", "0.3.0")]);
+        // The fixture of the tests has them all.
+        for package in &packages {
+            assert!(fixture_packages().join(package.subdir()).join("typst.toml").is_file(), "{}", package);
+        }
+    }
+
+    #[test]
+    fn missing_imported_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("main.typ"), "#import \"blocks.typ\": *\n#import \"@preview/cuti:0.3.0\": *\n").unwrap();
+        fs::write(dir.path().join("blocks.typ"), "#import \"@preview/nbconvert-missing:0.1.0\": *\n").unwrap();
+        let missing = missing_packages(&dir.path().join("main.typ"), Some(&fixture_packages()));
+        assert_eq!(missing, vec![package("nbconvert-missing", "0.1.0")]);
+    }
+
+    #[test]
+    fn install_a_registry_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        // As downloaded from the registry: the files of the package at the root.
+        let archive = dir.path().join("cuti-0.3.0.tar.gz");
+        let mut builder = tar::Builder::new(GzEncoder::new(File::create(&archive).unwrap(), Compression::default()));
+        builder.append_dir_all(".", fixture_packages().join("preview/cuti/0.3.0")).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let cache = dir.path().join("cache");
+        assert_eq!(install_archive(&archive, &cache).unwrap(), vec![package("cuti", "0.3.0")]);
+        assert!(cache.join("preview/cuti/0.3.0/lib.typ").is_file());
+        // Installed again, it replaces the package, and leaves nothing else in the cache.
+        assert_eq!(install_archive(&archive, &cache).unwrap(), vec![package("cuti", "0.3.0")]);
+        assert_eq!(subdirs(&cache).unwrap(), vec![cache.join("preview")]);
+    }
+
+    #[test]
+    fn install_an_exported_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("packages.tar.gz");
+        let packages = [package("mitex", "0.2.5"), package("cuti", "0.3.0")];
+        export_packages(&packages, &archive, Some(&fixture_packages())).unwrap();
+
+        let cache = dir.path().join("cache");
+        let mut installed = install_archive(&archive, &cache).unwrap();
+        installed.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(installed, vec![package("cuti", "0.3.0"), package("mitex", "0.2.5")]);
+        assert!(cache.join("preview/mitex/0.2.5/typst.toml").is_file());
+    }
+
+    #[test]
+    fn install_an_invalid_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("empty.tar.gz");
+        let builder = tar::Builder::new(GzEncoder::new(File::create(&archive).unwrap(), Compression::default()));
+        builder.into_inner().unwrap().finish().unwrap();
+        let error = install_archive(&archive, &dir.path().join("cache")).unwrap_err();
+        assert!(error.to_string().contains("no package in the archive"), "{}", error);
+    }
+}
@@ -0,0 +1,293 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use crate::compile::CompileDiagnostic;
+use crate::document::CellKind;
+use crate::error::Severity;
+
+/// Where the lines of the generated Typst source come from, to report the errors of the
+/// compiler in the notebook cells.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    /// The ranges in the order of the source, they don't overlap.
+    ranges: Vec<SourceRange>,
+}
+
+/// The lines of the generated source written from a block of a cell.
+#[derive(Debug, Clone)]
+pub struct SourceRange {
+    /// The 1-based first line in the generated source, and the line after the last one.
+    pub start: usize,
+    pub end: usize,
+    pub cell: Arc<CellSource>,
+    /// The 1-based line of the cell source where the block starts, `None` for an output.
+    pub line: Option<usize>,
+}
+
+/// A cell the generated source is written from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CellSource {
+    /// The notebook, only set for the chapters of a book.
+    pub notebook: Option<Arc<str>>,
+    /// The 0-based index of the cell in the notebook.
+    pub index: usize,
+    /// The `id` of the cell, only in nbformat >= 4.5.
+    pub id: Option<String>,
+    pub kind: CellKind,
+    pub source: String,
+}
+
+/// A line of the generated source, in terms of the notebook.
+#[derive(Debug, Clone, Copy)]
+pub struct CellPosition<'a> {
+    pub cell: &'a CellSource,
+    /// The 1-based line in the cell source, `None` in an output.
+    pub line: Option<usize>,
+}
+
+impl SourceMap {
+    /// Map the lines `start..end` of the generated source to a block of `cell`.
+    pub fn push(&mut self, start: usize, end: usize, cell: &Arc<CellSource>, line: Option<usize>) {
+        if start < end {
+            self.ranges.push(SourceRange { start, end, cell: cell.clone(), line });
+        }
+    }
+
+    /// Move the ranges down by `lines`, after lines are inserted before them.
+    pub fn shift(&mut self, lines: usize) {
+        for range in &mut self.ranges {
+            range.start += lines;
+            range.end += lines;
+        }
+    }
+
+    /// Add the ranges of the source written after `offset` lines.
+    pub fn append(&mut self, mut other: SourceMap, offset: usize) {
+        other.shift(offset);
+        self.ranges.extend(other.ranges);
+    }
+
+    pub fn ranges(&self) -> &[SourceRange] {
+        &self.ranges
+    }
+
+    /// The cell of a 1-based line of the generated source, and the line in the cell source.
+    /// In a block spanning several lines, the line in the cell is counted from the start of
+    /// the block, as most blocks keep the lines of their source.
+    pub fn locate(&self, line: usize) -> Option<CellPosition<'_>> {
+        let index = self.ranges.partition_point(|range| range.end <= line);
+        let range = self.ranges.get(index).filter(|range| range.start <= line)?;
+        let line = range.line.map(|start| {
+            let lines = range.cell.source.lines().count().max(1);
+            (start + line - range.start).min(lines)
+        });
+        Some(CellPosition { cell: &range.cell, line })
+    }
+
+    /// The diagnostic of the compiler in terms of the notebook, with the line of the cell
+    /// source, e.g. `error: cell 7 (markdown), line 3: unknown variable: foo`.
+    /// `None` if the diagnostic isn't in a cell: `file` is the generated source.
+    pub fn map_diagnostic(&self, diagnostic: &CompileDiagnostic, file: &str) -> Option<String> {
+        if diagnostic.file.as_deref() != Some(file) {
+            return None;
+        }
+        let position = self.locate(diagnostic.line?)?;
+        let cell = position.cell;
+
+        let mut result = String::new();
+        match diagnostic.severity {
+            Severity::Error => result += "error: ",
+            Severity::Warning => result += "warning: ",
+        }
+        if let Some(notebook) = &cell.notebook {
+            let _ = write!(result, "{}: ", notebook);
+        }
+        let _ = write!(result, "cell {} ({})", cell.index, cell.kind.name());
+        match position.line {
+            Some(line) => {
+                let _ = write!(result, ", line {}: {}", line, diagnostic.message);
+                result += &snippet(&cell.source, line);
+            }
+            None => {
+                let _ = write!(result, ", output: {}", diagnostic.message);
+            }
+        }
+        for hint in &diagnostic.hints {
+            let _ = write!(result, "\n  hint: {}", hint);
+        }
+        Some(result)
+    }
+}
+
+/// The line of the source with the ones around it, numbered, the line marked with `>`.
+fn snippet(source: &str, line: usize) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let first = line.saturating_sub(1).max(1);
+    let last = (line + 1).min(lines.len());
+    let width = last.to_string().len();
+
+    let mut result = String::new();
+    for number in first..=last {
+        let marker = if number == line { '>' } else { ' ' };
+        let _ = write!(result, "\n  {} {:>width$} | {}", marker, number, lines[number - 1], width = width);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{Block, Cell, Document, Inline, Metadata};
+    use crate::typst_writer::write_document_mapped;
+
+    fn cell(index: usize, kind: CellKind, source: &str) -> Arc<CellSource> {
+        Arc::new(CellSource { notebook: None, index, id: None, kind, source: source.to_string() })
+    }
+
+    fn position(map: &SourceMap, line: usize) -> Option<(usize, Option<usize>)> {
+        map.locate(line).map(|position| (position.cell.index, position.line))
+    }
+
+    fn error(file: &str, line: usize, message: &str) -> CompileDiagnostic {
+        CompileDiagnostic {
+            severity: Severity::Error,
+            message: message.to_string(),
+            file: Some(file.to_string()),
+            line: Some(line),
+            column: Some(1),
+            hints: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn locate_lines() {
+        let markdown = cell(0, CellKind::Markdown, "# Title\n\nfirst\nsecond\nthird");
+        let code = cell(2, CellKind::Code, "x = 1");
+        let mut map = SourceMap::default();
+        map.push(1, 3, &markdown, Some(1));
+        map.push(3, 7, &markdown, Some(3));
+        // An empty block isn't mapped.
+        map.push(7, 7, &markdown, Some(6));
+        map.push(8, 10, &code, Some(1));
+        map.push(10, 12, &code, None);
+
+        assert_eq!(map.ranges().len(), 4);
+        assert_eq!(position(&map, 0), None);
+        assert_eq!(position(&map, 1), Some((0, Some(1))));
+        assert_eq!(position(&map, 2), Some((0, Some(2))));
+        // Counted from the start of the block, up to the last line of the cell.
+        assert_eq!(position(&map, 4), Some((0, Some(4))));
+        assert_eq!(position(&map, 6), Some((0, Some(5))));
+        // A line between the blocks, e.g. of the preface.
+        assert_eq!(position(&map, 7), None);
+        assert_eq!(position(&map, 9), Some((2, Some(1))));
+        assert_eq!(position(&map, 11), Some((2, None)));
+        assert_eq!(position(&map, 12), None);
+    }
+
+    #[test]
+    fn shift_and_append() {
+        let first = cell(0, CellKind::Markdown, "a");
+        let second = cell(1, CellKind::Markdown, "b");
+        let mut map = SourceMap::default();
+        map.push(1, 3, &first, Some(1));
+        let mut chapter = SourceMap::default();
+        chapter.push(1, 3, &second, Some(1));
+
+        map.append(chapter, 4);
+        assert_eq!(position(&map, 4), None);
+        assert_eq!(position(&map, 5), Some((1, Some(1))));
+
+        map.shift(10);
+        assert_eq!(position(&map, 1), None);
+        assert_eq!(position(&map, 11), Some((0, Some(1))));
+        assert_eq!(position(&map, 15), Some((1, Some(1))));
+    }
+
+    #[test]
+    fn map_written_document() {
+        let text = |text: &str| Inline::Text(text.to_string());
+        let document = Document {
+            metadata: Metadata::default(),
+            cells: vec![
+                Cell {
+                    index: 0,
+                    id: None,
+                    kind: CellKind::Markdown,
+                    actions: Vec::new(),
+                    input: vec![
+                        Block::Heading { level: 1, content: vec![text("Title")] },
+                        Block::Paragraph(vec![text("Some text.")]),
+                    ],
+                    input_lines: vec![1, 3],
+                    outputs: Vec::new(),
+                    source: "# Title\n\nSome text.".to_string(),
+                },
+                Cell {
+                    index: 1,
+                    id: None,
+                    kind: CellKind::Code,
+                    actions: Vec::new(),
+                    input: vec![Block::Code { lang: "python".to_string(), source: "print(1)".to_string(), execution_count: Some(1) }],
+                    input_lines: vec![1],
+                    outputs: vec![Block::Output("1".to_string())],
+                    source: "print(1)".to_string(),
+                },
+            ],
+        };
+        let (source, map) = write_document_mapped(&document, None);
+        let lines: Vec<&str> = source.lines().collect();
+
+        assert_eq!(lines[0], "= Title");
+        assert_eq!(position(&map, 1), Some((0, Some(1))));
+        assert_eq!(lines[2], "Some text.");
+        assert_eq!(position(&map, 3), Some((0, Some(3))));
+        let code = lines.iter().position(|line| line.contains("print(1)")).unwrap() + 1;
+        assert_eq!(position(&map, code), Some((1, Some(1))));
+        assert_eq!(position(&map, lines.len()), Some((1, None)));
+        assert_eq!(position(&map, lines.len() + 1), None);
+    }
+
+    #[test]
+    fn map_diagnostics() {
+        let markdown = cell(3, CellKind::Markdown, "intro\n#foo\nend");
+        let code = cell(4, CellKind::Code, "x = 1");
+        let mut map = SourceMap::default();
+        map.push(10, 13, &markdown, Some(1));
+        map.push(13, 15, &code, None);
+
+        assert_eq!(
+            map.map_diagnostic(&error("report.typ", 11, "unknown variable: foo"), "report.typ").unwrap(),
+            "error: cell 3 (markdown), line 2: unknown variable: foo\n    1 | intro\n  > 2 | #foo\n    3 | end"
+        );
+
+        let mut warning = error("report.typ", 14, "unknown font family: simsun");
+        warning.severity = Severity::Warning;
+        warning.hints.push("check the fonts".to_string());
+        assert_eq!(
+            map.map_diagnostic(&warning, "report.typ").unwrap(),
+            "warning: cell 4 (code), output: unknown font family: simsun\n  hint: check the fonts"
+        );
+
+        // Not in the generated source, or not in a cell.
+        assert_eq!(map.map_diagnostic(&error("template.typ", 11, "x"), "report.typ"), None);
+        assert_eq!(map.map_diagnostic(&error("report.typ", 2, "x"), "report.typ"), None);
+    }
+
+    #[test]
+    fn map_diagnostics_of_chapters() {
+        let chapter = Arc::new(CellSource {
+            notebook: Some("chapters/intro.ipynb".into()),
+            index: 0,
+            id: Some("a1".to_string()),
+            kind: CellKind::Raw,
+            source: "#bad".to_string(),
+        });
+        let mut map = SourceMap::default();
+        map.push(1, 2, &chapter, Some(1));
+        assert_eq!(
+            map.map_diagnostic(&error("book.typ", 1, "unknown variable: bad"), "book.typ").unwrap(),
+            "error: chapters/intro.ipynb: cell 0 (raw), line 1: unknown variable: bad\n  > 1 | #bad"
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::{collections::HashMap, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{NbconvertError, Result};
+
+/// What to do with a cell carrying a tag, like the `TagRemovePreprocessor` of nbconvert.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TagAction {
+    /// Drop the whole cell.
+    RemoveCell,
+    /// Drop the source of the cell, keep its outputs.
+    RemoveInput,
+    /// Drop the outputs of the cell.
+    RemoveOutput,
+    /// Replace the source with a placeholder.
+    HideInput,
+    /// Replace the outputs with a placeholder.
+    HideOutput,
+    /// The error of the cell is expected, render it without a warning.
+    RaisesException,
+}
+
+/// The built-in tags, named after their action.
+pub const DEFAULT_TAG_ACTIONS: &[(&str, TagAction)] = &[
+    ("remove-cell", TagAction::RemoveCell),
+    ("remove-input", TagAction::RemoveInput),
+    ("remove-output", TagAction::RemoveOutput),
+    ("hide-input", TagAction::HideInput),
+    ("hide-output", TagAction::HideOutput),
+    ("raises-exception", TagAction::RaisesException),
+];
+
+impl FromStr for TagAction {
+    type Err = NbconvertError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        DEFAULT_TAG_ACTIONS
+            .iter()
+            .find(|(name, _)| *name == s.trim())
+            .map(|(_, action)| *action)
+            .ok_or_else(|| {
+                let names: Vec<&str> = DEFAULT_TAG_ACTIONS.iter().map(|(name, _)| *name).collect();
+                NbconvertError::InvalidArgument(format!(
+                    "unknown tag action \"{}\", expected one of {}",
+                    s.trim(),
+                    names.join(", ")
+                ))
+            })
+    }
+}
+
+/// The actions of the cell tags.
+#[derive(Debug, Clone)]
+pub struct TagOptions {
+    /// The action per tag, the built-in tags are included.
+    pub actions: HashMap<String, TagAction>,
+}
+
+impl Default for TagOptions {
+    fn default() -> Self {
+        TagOptions {
+            actions: DEFAULT_TAG_ACTIONS
+                .iter()
+                .map(|(name, action)| (name.to_string(), *action))
+                .collect(),
+        }
+    }
+}
+
+impl TagOptions {
+    /// The actions asked for by the tags of a cell.
+    pub fn actions(&self, tags: &[String]) -> Vec<TagAction> {
+        tags.iter()
+            .filter_map(|tag| self.actions.get(tag.trim()).copied())
+            .collect()
+    }
+}
+
+/// Parse a `tag=action` pair, e.g. `scratch=remove-cell`.
+pub fn parse_tag_action(pair: &str) -> Result<(String, TagAction)> {
+    let (tag, action) = pair.split_once('=').ok_or_else(|| {
+        NbconvertError::InvalidArgument(format!("expected tag=action, got \"{}\"", pair))
+    })?;
+    Ok((tag.trim().to_string(), action.parse()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn action_names() {
+        for (name, action) in DEFAULT_TAG_ACTIONS {
+            assert_eq!(name.parse::<TagAction>().unwrap(), *action);
+            // The names of the config file are the same.
+            assert_eq!(serde_json::to_value(action).unwrap(), *name);
+        }
+        let error = "remove".parse::<TagAction>().unwrap_err().to_string();
+        assert!(error.contains("unknown tag action \"remove\""), "{}", error);
+    }
+
+    #[test]
+    fn tag_action_pairs() {
+        assert_eq!(parse_tag_action("scratch=remove-cell").unwrap(), ("scratch".to_string(), TagAction::RemoveCell));
+        assert_eq!(parse_tag_action(" debug = hide-output ").unwrap(), ("debug".to_string(), TagAction::HideOutput));
+        assert!(parse_tag_action("scratch").is_err());
+        assert!(parse_tag_action("scratch=drop").is_err());
+    }
+
+    #[test]
+    fn actions_of_tags() {
+        let mut options = TagOptions::default();
+        options.actions.insert("solution".to_string(), TagAction::RemoveCell);
+        let tags = ["hide-input", "other", " solution "].map(String::from);
+        assert_eq!(options.actions(&tags), vec![TagAction::HideInput, TagAction::RemoveCell]);
+        assert!(options.actions(&[]).is_empty());
+    }
+}
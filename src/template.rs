@@ -0,0 +1,107 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{NbconvertError, Result};
+
+/// The file of the blocks used by the generated source (code cells, outputs...),
+/// written next to it at every conversion.
+pub const BLOCKS_FILE_NAME: &str = "nbconvert.typ";
+
+/// The source of `nbconvert.typ`.
+pub const BLOCKS: &str = include_str!("../template/nbconvert.typ");
+
+/// A template embedded in the binary, it defines `project`.
+#[derive(Debug, Clone, Copy)]
+pub struct BuiltinTemplate {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub source: &'static str,
+}
+
+/// The built-in templates, the first one is the default.
+pub const BUILTIN_TEMPLATES: &[BuiltinTemplate] = &[
+    BuiltinTemplate {
+        name: "article",
+        description: "the title, the authors and the abstract above the body",
+        source: include_str!("../template/article.typ"),
+    },
+    BuiltinTemplate {
+        name: "report",
+        description: "a title page, the table of contents and numbered headings",
+        source: include_str!("../template/report.typ"),
+    },
+    BuiltinTemplate {
+        name: "homework",
+        description: "a cover page with the student names, IDs and emails",
+        source: include_str!("../template/homework.typ"),
+    },
+    BuiltinTemplate {
+        name: "minimal",
+        description: "the title and the authors in one line",
+        source: include_str!("../template/minimal.typ"),
+    },
+];
+
+/// The default `template.typ`, the `article` template.
+pub const DEFAULT_TEMPLATE: &str = BUILTIN_TEMPLATES[0].source;
+
+/// The built-in template with the given name.
+pub fn builtin_template(name: &str) -> Option<&'static BuiltinTemplate> {
+    BUILTIN_TEMPLATES.iter().find(|template| template.name == name)
+}
+
+/// The source of a template given as a built-in name or a path to a `.typ` file.
+pub fn template_source(template: &str) -> Result<String> {
+    if let Some(builtin) = builtin_template(template) {
+        return Ok(builtin.source.to_string());
+    }
+
+    let path = Path::new(template);
+    if !path.exists() && path.extension().is_none() {
+        let names: Vec<&str> = BUILTIN_TEMPLATES.iter().map(|template| template.name).collect();
+        return Err(NbconvertError::InvalidArgument(format!(
+            "unknown template \"{}\", expected a .typ file or one of {}",
+            template,
+            names.join(", ")
+        )));
+    }
+    fs::read_to_string(path)
+        .map_err(|e| NbconvertError::InvalidArgument(format!("can't read template {}: {}", path.display(), e)))
+}
+
+/// Ensure template.typ exists in the given directory, write `source` if not,
+/// or if `replace` is true.
+/// Return the full path to the created or existing template.
+pub fn create_template(dir: &Path, source: &str, replace: bool) -> Result<PathBuf> {
+    let template_path = dir.join("template.typ");
+
+    if !template_path.exists() {
+        fs::write(&template_path, source)?;
+        println!("Created template at: {}", template_path.display());
+    } else if replace {
+        fs::write(&template_path, source)?;
+        println!("Replaced template at: {}", template_path.display());
+    } else {
+        println!("Using existing template: {}", template_path.display());
+    }
+
+    Ok(template_path)
+}
+
+/// Write `nbconvert.typ` and `template.typ` in the given directory: the given template, or
+/// the default one unless a template exists. The notebooks converted to the same directory
+/// share them.
+pub fn write_support_files(dir: &Path, template: Option<&str>) -> Result<()> {
+    create_blocks(dir)?;
+    create_template(dir, template.unwrap_or(DEFAULT_TEMPLATE), template.is_some())?;
+    Ok(())
+}
+
+/// Write `nbconvert.typ` in the given directory, replacing an older version.
+pub fn create_blocks(dir: &Path) -> Result<PathBuf> {
+    let blocks_path = dir.join(BLOCKS_FILE_NAME);
+    if fs::read_to_string(&blocks_path).ok().as_deref() != Some(BLOCKS) {
+        fs::write(&blocks_path, BLOCKS)?;
+    }
+    Ok(blocks_path)
+}
@@ -1,95 +1,687 @@
 
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::document::Metadata;
+use crate::error::{NbconvertError, Result};
+use crate::locale::{date_format, DEFAULT_LANG};
+
 pub struct TypstContent {
   pub content: String,
 }
 
+/// An author of the document, in the author grid of the template.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(try_from = "Value", rename_all = "kebab-case")]
 pub struct Author {
     pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub email: Option<String>,
-    pub affiliation: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub affiliations: Vec<String>,
+    /// The ORCID iD without the `https://orcid.org/` prefix, e.g. `0000-0002-1825-0097`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orcid: Option<String>,
+    /// Marked as the corresponding author.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub corresponding: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub student_id: Option<String>,
+}
+
+/// An author given as a table.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct AuthorFields {
+    name: String,
+    email: Option<String>,
+    #[serde(alias = "affiliations")]
+    affiliation: Option<Affiliations>,
+    orcid: Option<String>,
+    #[serde(default)]
+    corresponding: bool,
+    #[serde(alias = "student_id")]
+    student_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Affiliations {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl TryFrom<Value> for Author {
+    type Error = NbconvertError;
+
+    /// An author in a file or a front matter: a `"Name <email> (Affiliation)"` string or a table.
+    fn try_from(value: Value) -> Result<Self> {
+        let fields: AuthorFields = match value {
+            Value::String(author) => return author.parse(),
+            value => serde_json::from_value(value)
+                .map_err(|e| NbconvertError::InvalidArgument(format!("invalid author: {}", e)))?,
+        };
+        let author = Author {
+            name: fields.name,
+            email: fields.email,
+            affiliations: match fields.affiliation {
+                Some(Affiliations::One(affiliation)) => vec![affiliation],
+                Some(Affiliations::Many(affiliations)) => affiliations,
+                None => Vec::new(),
+            },
+            orcid: fields.orcid,
+            corresponding: fields.corresponding,
+            student_id: fields.student_id,
+        };
+        author.normalize()
+    }
+}
+
+impl FromStr for Author {
+    type Err = NbconvertError;
+
+    /// Parse `Name <email> (Affiliation) (Another affiliation) [orcid: ..., student-id: ..., corresponding]`,
+    /// everything but the name is optional.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |message: String| NbconvertError::InvalidArgument(format!("invalid author \"{}\": {}", s.trim(), message));
+
+        let mut author = Author::default();
+        let mut chars = s.chars();
+        while let Some(open) = chars.next() {
+            let close = match open {
+                '<' => '>',
+                '(' => ')',
+                '[' => ']',
+                '>' | ')' | ']' => return Err(invalid(format!("unmatched '{}'", open))),
+                _ => {
+                    author.name.push(open);
+                    continue;
+                }
+            };
+
+            // An affiliation may contain parentheses, e.g. "(School of EECS (PKU))".
+            let mut part = String::new();
+            let mut depth = 0;
+            let mut closed = false;
+            for c in chars.by_ref() {
+                if c == close && depth == 0 {
+                    closed = true;
+                    break;
+                }
+                if open == '(' && c == '(' {
+                    depth += 1;
+                } else if open == '(' && c == ')' {
+                    depth -= 1;
+                }
+                part.push(c);
+            }
+            if !closed {
+                return Err(invalid(format!("missing '{}'", close)));
+            }
+
+            match open {
+                '<' if author.email.is_some() => return Err(invalid("more than one email".to_string())),
+                '<' => author.email = Some(part),
+                '(' => author.affiliations.push(part),
+                _ => {
+                    for option in part.split(',').filter(|option| !option.trim().is_empty()) {
+                        author.set_option(option).map_err(invalid)?;
+                    }
+                }
+            }
+        }
+
+        author.normalize().map_err(|err| match err {
+            NbconvertError::InvalidArgument(message) => invalid(message),
+            err => err,
+        })
+    }
 }
 
+impl Author {
+    pub fn new(name: impl Into<String>) -> Self {
+        Author { name: name.into(), ..Default::default() }
+    }
+
+    /// Set an option in the brackets: `orcid: ...`, `student-id: ...` or `corresponding`.
+    fn set_option(&mut self, option: &str) -> std::result::Result<(), String> {
+        let (key, value) = match option.find([':', '=']) {
+            Some(i) => (option[..i].trim(), Some(option[i + 1..].trim().to_string())),
+            None => (option.trim(), None),
+        };
+        match (key, value) {
+            ("corresponding", None) => self.corresponding = true,
+            ("orcid", Some(value)) => self.orcid = Some(value),
+            ("student-id" | "student_id", Some(value)) => self.student_id = Some(value),
+            _ => return Err(format!("unknown option \"{}\", expected orcid: ..., student-id: ... or corresponding", option.trim())),
+        }
+        Ok(())
+    }
+
+    /// Trim the fields, drop the empty ones and check the name, the email and the ORCID iD.
+    fn normalize(self) -> Result<Self> {
+        let field = |value: Option<String>| value.map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+
+        let author = Author {
+            name: self.name.split_whitespace().collect::<Vec<&str>>().join(" "),
+            email: field(self.email),
+            affiliations: self.affiliations.into_iter().filter_map(|affiliation| field(Some(affiliation))).collect(),
+            orcid: field(self.orcid).map(|orcid| {
+                orcid
+                    .trim_start_matches("https://")
+                    .trim_start_matches("http://")
+                    .trim_start_matches("orcid.org/")
+                    .to_string()
+            }),
+            corresponding: self.corresponding,
+            student_id: field(self.student_id),
+        };
+
+        if author.name.is_empty() {
+            return Err(NbconvertError::InvalidArgument("missing name".to_string()));
+        }
+        if let Some(email) = &author.email {
+            if !email.contains('@') {
+                return Err(NbconvertError::InvalidArgument(format!("invalid email \"{}\"", email)));
+            }
+        }
+        if let Some(orcid) = &author.orcid {
+            if !is_valid_orcid(orcid) {
+                return Err(NbconvertError::InvalidArgument(format!(
+                    "invalid ORCID iD \"{}\", expected 0000-0000-0000-0000",
+                    orcid
+                )));
+            }
+        }
+        Ok(author)
+    }
+}
+
+/// Check the format and the ISO 7064 11,2 check digit of an ORCID iD.
+fn is_valid_orcid(orcid: &str) -> bool {
+    let groups: Vec<&str> = orcid.split('-').collect();
+    if groups.len() != 4 || groups.iter().any(|group| group.len() != 4) {
+        return false;
+    }
+    let chars: Vec<char> = groups.concat().chars().collect();
+    let mut total = 0;
+    for c in &chars[..15] {
+        match c.to_digit(10) {
+            Some(digit) => total = (total + digit) * 2,
+            None => return false,
+        }
+    }
+    let check = (12 - total % 11) % 11;
+    let expected = if check == 10 { 'X' } else { char::from_digit(check, 10).unwrap_or('?') };
+    chars[15].to_ascii_uppercase() == expected
+}
+
+/// Read the authors from a TOML (`[[authors]]` tables) or JSON (an array, or an `authors` array) file.
+pub fn read_authors(path: &Path) -> Result<Vec<Author>> {
+    let invalid = |message: String| NbconvertError::InvalidArgument(format!("invalid authors file {}: {}", path.display(), message));
+
+    let content = fs::read_to_string(path)?;
+    let value: Value = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&content).map_err(|e| invalid(e.to_string()))?,
+        _ => toml::from_str(&content).map_err(|e| invalid(e.to_string()))?,
+    };
+    let authors = match value {
+        Value::Array(authors) => authors,
+        Value::Object(mut table) => match table.remove("authors") {
+            Some(Value::Array(authors)) => authors,
+            _ => return Err(invalid("expected an authors array".to_string())),
+        },
+        _ => return Err(invalid("expected an authors array".to_string())),
+    };
+    authors
+        .into_iter()
+        .map(|author| {
+            Author::try_from(author).map_err(|err| match err {
+                NbconvertError::InvalidArgument(message) => invalid(message),
+                err => err,
+            })
+        })
+        .collect()
+}
+
+/// The font families of the template, `None` keeps the default of the template.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Fonts {
+    /// The text, e.g. `["New Computer Modern"]`.
+    pub body: Option<Vec<String>>,
+    /// The CJK text, after the body fonts.
+    pub cjk: Option<Vec<String>>,
+    /// The emphasized CJK text, e.g. `["KaiTi"]`.
+    pub emphasis: Option<Vec<String>>,
+    /// The code and the outputs.
+    pub mono: Option<Vec<String>>,
+}
+
+/// The page and font settings passed to `project`.
+#[derive(Debug, Clone, Default)]
+pub struct Style {
+    /// The Typst paper name, e.g. `a4` or `us-letter`.
+    pub paper: Option<String>,
+    pub landscape: bool,
+    pub fonts: Fonts,
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Date {
     pub year: i32,
     pub month: i32,
     pub day: i32,
 }
 
-/// Escape all the special characters in code (raw string) to typst format.
-/// But the parameter is a &Vec<String>
-pub fn escape_vec_code(code: &Vec<String>) -> String {
-    let mut result = String::new();
-    for line in code {
-        // Escape the special characters.
-        let escaped_line = escape_content(line);
-        result += &escaped_line;
+impl FromStr for Date {
+    type Err = NbconvertError;
+
+    /// Parse a `YYYY-MM-DD` date, a time after it (e.g. `2024-05-01T12:00:00`) is ignored.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || NbconvertError::InvalidArgument(format!("invalid date \"{}\", expected YYYY-MM-DD", s.trim()));
+
+        let day_part = s.trim().split(['T', ' ']).next().unwrap_or_default();
+        let parts: Vec<&str> = day_part.split('-').collect();
+        if parts.len() != 3 {
+            return Err(invalid());
+        }
+        let date = Date {
+            year: parts[0].parse().map_err(|_| invalid())?,
+            month: parts[1].parse().map_err(|_| invalid())?,
+            day: parts[2].parse().map_err(|_| invalid())?,
+        };
+
+        let days_in_month = match date.month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if date.year % 4 == 0 && (date.year % 100 != 0 || date.year % 400 == 0) => 29,
+            2 => 28,
+            _ => return Err(invalid()),
+        };
+        if date.day < 1 || date.day > days_in_month {
+            return Err(invalid());
+        }
+
+        Ok(date)
     }
-    result
 }
 
+impl Date {
+    /// The UTC date of a Unix timestamp, in seconds.
+    pub fn from_timestamp(secs: i64) -> Date {
+        // Howard Hinnant's days_from_civil, inverted.
+        let days = secs.div_euclid(86400) + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days.rem_euclid(146097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        Date { year: year as i32, month: month as i32, day: day as i32 }
+    }
 
-/// Escape all the special characters in code (raw string) to typst format.
-pub fn escape_code(code: &String) -> String {
-    let mut result = String::new();
-    for line in code.lines() {
-        // Escape the special characters.
-        let escaped_line = escape_content(line);
-        result += &escaped_line;
-        result += "\n";
+    /// The UTC date of a `SystemTime`, e.g. the modification time of a file.
+    pub fn from_system_time(time: SystemTime) -> Date {
+        let secs = match time.duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        };
+        Date::from_timestamp(secs)
     }
-    result
-}
 
+    /// Today, in UTC.
+    pub fn today() -> Date {
+        Date::from_system_time(SystemTime::now())
+    }
+
+    /// The date given as `YYYY-MM-DD`, `today`, `file-mtime` (the latest change of the
+    /// sources) or `SOURCE_DATE_EPOCH` (the timestamp in that environment variable).
+    pub fn resolve(date: &str, sources: &[PathBuf]) -> Result<Date> {
+        match date.trim() {
+            "today" => Ok(Date::today()),
+            "file-mtime" => {
+                let mut latest = None;
+                for path in sources {
+                    let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).map_err(|e| {
+                        NbconvertError::InvalidArgument(format!("can't read the modification time of {}: {}", path.display(), e))
+                    })?;
+                    latest = latest.max(Some(modified));
+                }
+                latest
+                    .map(Date::from_system_time)
+                    .ok_or_else(|| NbconvertError::InvalidArgument("file-mtime needs a notebook".to_string()))
+            }
+            "SOURCE_DATE_EPOCH" => {
+                let epoch = env::var("SOURCE_DATE_EPOCH").map_err(|_| {
+                    NbconvertError::InvalidArgument("the date is SOURCE_DATE_EPOCH, but it is not set".to_string())
+                })?;
+                let secs = epoch.trim().parse().map_err(|_| {
+                    NbconvertError::InvalidArgument(format!("invalid SOURCE_DATE_EPOCH \"{}\", expected a Unix timestamp", epoch))
+                })?;
+                Ok(Date::from_timestamp(secs))
+            }
+            date => date.parse().map_err(|_| {
+                NbconvertError::InvalidArgument(format!(
+                    "invalid date \"{}\", expected YYYY-MM-DD, today, file-mtime or SOURCE_DATE_EPOCH",
+                    date
+                ))
+            }),
+        }
+    }
+}
 
-/// Escape special characters in content to be used in typst format.
+/// Escape special characters in content to be used in a Typst string literal.
 /// This function replaces:
 /// - Backslashes (`\`) with double backslashes (`\\`)
 /// - Double quotes (`"`) with escaped double quotes (`\"`)
-/// - Backticks (`` ` ``) with escaped backticks (`\\``)
 pub fn escape_content(content: &str) -> String {
     content
         .replace("\\", "\\\\") // Escape backslashes
         .replace("\"", "\\\"") // Escape double quotes
-        .replace("`", "\\`")   // Escape backticks
 }
 
+/// Escape text to be used as Typst markup, e.g. in a paragraph.
+/// The characters starting a markup (`#`, `$`, `@`, `<`, `*`, `_`, `` ` ``, `[`, `]`, `~`, `\`)
+/// and comments (`//`, `/*`) are escaped everywhere. `=`, `-`, `+`, `/` and `1.` are only
+/// escaped at the start of a line, where they make a heading, a list or a term.
+/// `line_start` tells whether the text begins a line.
+pub fn escape_markup(text: &str, line_start: bool) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut at_line_start = line_start;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' | '#' | '$' | '@' | '<' | '*' | '_' | '`' | '[' | ']' | '~' => {
+                result.push('\\');
+                result.push(c);
+            }
+            '/' if matches!(chars.peek(), Some('/') | Some('*')) => {
+                result.push_str("\\/");
+            }
+            '=' | '-' | '+' | '/' if at_line_start => {
+                result.push('\\');
+                result.push(c);
+            }
+            '0'..='9' if at_line_start => {
+                // `1.` starts a numbered list.
+                result.push(c);
+                while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    result.push(digit);
+                }
+                if chars.next_if_eq(&'.').is_some() {
+                    result.push_str("\\.");
+                }
+            }
+            _ => result.push(c),
+        }
+        at_line_start = c == '\n' || (at_line_start && c.is_whitespace());
+    }
+
+    result
+}
+
+/// Escape a link URL to be used in a Typst string literal.
+/// The characters not allowed in a URL (spaces, quotes, backslashes...) are percent-encoded.
+pub fn escape_url(url: &str) -> String {
+    let mut result = String::with_capacity(url.len());
+    for c in url.chars() {
+        if c.is_whitespace() || c.is_control() || matches!(c, '"' | '\\' | '<' | '>') {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                result += format!("%{:02X}", byte).as_str();
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// A Typst string literal, or `none`.
+fn string_or_none(value: Option<&str>) -> String {
+    match value {
+        Some(value) => format!("\"{}\"", escape_content(value)),
+        None => "none".to_string(),
+    }
+}
+
+/// A JSON value as a Typst value: `none`, a bool, an int, a float, a string,
+/// an array or a dictionary.
+pub fn typst_value(value: &Value) -> String {
+    match value {
+        Value::Null => "none".to_string(),
+        Value::Bool(value) => value.to_string(),
+        Value::Number(number) => match (number.as_i64(), number.as_f64()) {
+            (Some(int), _) => int.to_string(),
+            // A float keeps its dot or exponent, so that Typst reads it as a float.
+            (None, Some(float)) if float.is_finite() => format!("{:?}", float),
+            _ => number.to_string(),
+        },
+        Value::String(value) => format!("\"{}\"", escape_content(value)),
+        // The trailing comma keeps a single item an array.
+        Value::Array(items) => format!("({})", items.iter().map(|item| format!("{},", typst_value(item))).collect::<String>()),
+        Value::Object(map) if map.is_empty() => "(:)".to_string(),
+        Value::Object(map) => {
+            let entries: Vec<String> = map
+                .iter()
+                .map(|(key, value)| format!("\"{}\": {}", escape_content(key), typst_value(value)))
+                .collect();
+            format!("({})", entries.join(", "))
+        }
+    }
+}
 
 impl TypstContent {
     /// Add the preface in the beginning of the content.
-    pub fn add_preface(&mut self, title: &str, authors: &Vec<Author>, date: Option<&Date>) {
+    /// `date: None` is the date of compilation.
+    pub fn add_preface(&mut self, metadata: &Metadata, style: &Style) {
         let mut preface = String::new();
-        preface += "#import \"template.typ\": *";
-        preface += "\n\n";
+        // Only `project` is taken from the template, the blocks come from `nbconvert.typ`.
+        preface += "#import \"nbconvert.typ\": *\n";
+        preface += "#import \"template.typ\": project\n\n";
         preface += "#show: project.with(\n";
-        preface += format!("  title: \"{}\",\n", title).as_str();
+        preface += format!("  title: \"{}\",\n", escape_content(metadata.title.as_deref().unwrap_or_default())).as_str();
         preface += "authors: (";
             
-        for author in authors {
-            preface += format!("(name: \"{}\", ", author.name).as_str();
-            if let Some(email) = author.email.as_ref() {
-                preface += format!("email: \"{}\", ", email).as_str();
-            } else {
-                preface += "email: none, ";
-            }
-            if let Some(affiliation) = author.affiliation.as_ref() {
-                preface += format!("affiliation: \"{}\"", affiliation).as_str();
-            } else {
-                preface += "affiliation: none";
-            }
-            preface += "), ";
+        for author in &metadata.authors {
+            let affiliations: Vec<String> = author
+                .affiliations
+                .iter()
+                .map(|affiliation| format!("\"{}\"", escape_content(affiliation)))
+                .collect();
+            preface += format!(
+                "(name: \"{}\", email: {}, affiliations: ({}), orcid: {}, corresponding: {}, student-id: {}), ",
+                escape_content(&author.name),
+                string_or_none(author.email.as_deref()),
+                // The trailing comma keeps a single affiliation an array.
+                affiliations.iter().map(|affiliation| format!("{},", affiliation)).collect::<String>(),
+                string_or_none(author.orcid.as_deref()),
+                author.corresponding,
+                string_or_none(author.student_id.as_deref()),
+            ).as_str();
         }
 
         preface += "),\n";
         
-        if let Some(date) = date {
+        if let Some(date) = metadata.date {
             preface += format!("date: datetime(year: {}, month: {}, day: {})", date.year, date.month, date.day).as_str();
         } else {
             preface += "date: datetime.today()";
         }
 
-        preface += ".display(\"[year]年[month padding:space]月[day padding:space]日\"),\n";
+        let lang = metadata.lang.as_deref().unwrap_or(DEFAULT_LANG);
+        preface += format!(".display(\"{}\"),\n", date_format(lang, metadata.region.as_deref())).as_str();
+        preface += format!("lang: \"{}\",\n", escape_content(lang)).as_str();
+        if let Some(region) = &metadata.region {
+            preface += format!("region: \"{}\",\n", escape_content(region)).as_str();
+        }
+
+        if let Some(r#abstract) = &metadata.r#abstract {
+            preface += format!("abstract: \"{}\",\n", escape_content(r#abstract)).as_str();
+        }
+        if !metadata.keywords.is_empty() {
+            let keywords: Vec<String> = metadata
+                .keywords
+                .iter()
+                .map(|keyword| format!("\"{}\"", escape_content(keyword)))
+                .collect();
+            preface += format!("keywords: ({},),\n", keywords.join(", ")).as_str();
+        }
+
+        if let Some(paper) = &style.paper {
+            preface += format!("paper: \"{}\",\n", escape_content(paper)).as_str();
+        }
+        if style.landscape {
+            preface += "flipped: true,\n";
+        }
+        let fonts: Vec<String> = [
+            ("body", &style.fonts.body),
+            ("cjk", &style.fonts.cjk),
+            ("emphasis", &style.fonts.emphasis),
+            ("mono", &style.fonts.mono),
+        ]
+        .iter()
+        .filter_map(|(name, families)| {
+            let families: Vec<String> = families
+                .as_ref()?
+                .iter()
+                .map(|family| format!("\"{}\",", escape_content(family)))
+                .collect();
+            Some(format!("{}: ({})", name, families.concat()))
+        })
+        .collect();
+        if !fonts.is_empty() {
+            preface += format!("fonts: ({}),\n", fonts.join(", ")).as_str();
+        }
+        // Spread as a dictionary, so that any name is a valid argument.
+        if !metadata.vars.is_empty() {
+            let vars = metadata.vars.clone().into_iter().collect();
+            preface += format!("..{},\n", typst_value(&Value::Object(vars))).as_str();
+        }
+
         preface += ")\n\n";
 
         self.content.insert_str(0, &preface);
     }
   }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_line_start_markers() {
+        assert_eq!(escape_markup("= Title", true), "\\= Title");
+        assert_eq!(escape_markup("- item", true), "\\- item");
+        assert_eq!(escape_markup("+ item", true), "\\+ item");
+        assert_eq!(escape_markup("1. item", true), "1\\. item");
+        assert_eq!(escape_markup("12. item", true), "12\\. item");
+        assert_eq!(escape_markup("a\n  - b", false), "a\n  \\- b");
+        // Only at the start of a line.
+        assert_eq!(escape_markup("a = b - c + 1. d", true), "a = b - c + 1. d");
+        assert_eq!(escape_markup("- item", false), "- item");
+        assert_eq!(escape_markup("2024 was", true), "2024 was");
+    }
+
+    #[test]
+    fn escape_markup_characters() {
+        assert_eq!(escape_markup("costs $5", false), "costs \\$5");
+        assert_eq!(escape_markup("me@example.com", false), "me\\@example.com");
+        assert_eq!(escape_markup("<label>", false), "\\<label>");
+        assert_eq!(escape_markup("#import", false), "\\#import");
+        assert_eq!(escape_markup("*a* _b_ `c` [d] ~ \\", false), "\\*a\\* \\_b\\_ \\`c\\` \\[d\\] \\~ \\\\");
+    }
+
+    fn ymd(date: Date) -> (i32, i32, i32) {
+        (date.year, date.month, date.day)
+    }
+
+    #[test]
+    fn orcid_check_digit() {
+        assert!(is_valid_orcid("0000-0002-1825-0097"));
+        assert!(is_valid_orcid("0000-0002-1694-233X"));
+        assert!(is_valid_orcid("0000-0002-1694-233x"));
+        assert!(!is_valid_orcid("0000-0002-1825-0098"));
+        assert!(!is_valid_orcid("0000-0002-1694-2330"));
+        assert!(!is_valid_orcid("0000-0002-1825-009"));
+        assert!(!is_valid_orcid("0000-000A-1825-0097"));
+    }
+
+    #[test]
+    fn author_orcid() {
+        let author: Author = "Josiah Carberry [orcid: https://orcid.org/0000-0002-1825-0097]".parse().unwrap();
+        assert_eq!(author.orcid.as_deref(), Some("0000-0002-1825-0097"));
+        assert!("Josiah Carberry [orcid: 0000-0002-1825-0098]".parse::<Author>().is_err());
+    }
+
+    #[test]
+    fn leap_days() {
+        assert_eq!(ymd("2024-02-29".parse().unwrap()), (2024, 2, 29));
+        assert_eq!(ymd("2000-02-29T12:00:00".parse().unwrap()), (2000, 2, 29));
+        assert!("2023-02-29".parse::<Date>().is_err());
+        assert!("1900-02-29".parse::<Date>().is_err());
+        assert!("2024-04-31".parse::<Date>().is_err());
+        assert!("2024-13-01".parse::<Date>().is_err());
+        assert!("2024-05".parse::<Date>().is_err());
+    }
+
+    #[test]
+    fn dates_of_timestamps() {
+        assert_eq!(ymd(Date::from_timestamp(0)), (1970, 1, 1));
+        assert_eq!(ymd(Date::from_timestamp(86399)), (1970, 1, 1));
+        assert_eq!(ymd(Date::from_timestamp(86400)), (1970, 1, 2));
+        assert_eq!(ymd(Date::from_timestamp(-1)), (1969, 12, 31));
+        assert_eq!(ymd(Date::from_timestamp(951782400)), (2000, 2, 29));
+        assert_eq!(ymd(Date::from_timestamp(1709164800 - 1)), (2024, 2, 28));
+        assert_eq!(ymd(Date::from_timestamp(1709164800)), (2024, 2, 29));
+        assert_eq!(ymd(Date::from_timestamp(-2203891200)), (1900, 3, 1));
+        assert_eq!(ymd(Date::from_timestamp(4107542400)), (2100, 3, 1));
+    }
+
+    #[test]
+    fn escape_comments() {
+        assert_eq!(escape_markup("a // b", false), "a \\// b");
+        assert_eq!(escape_markup("/* c */", false), "\\/\\* c \\*/");
+        assert_eq!(escape_markup("a/b", false), "a/b");
+        assert_eq!(escape_markup("https://x.org", false), "https:\\//x.org");
+    }
+
+    #[test]
+    fn typst_values() {
+        use serde_json::json;
+        assert_eq!(typst_value(&json!(null)), "none");
+        assert_eq!(typst_value(&json!(true)), "true");
+        assert_eq!(typst_value(&json!(3)), "3");
+        assert_eq!(typst_value(&json!(2.0)), "2.0");
+        assert_eq!(typst_value(&json!(1e-7)), "1e-7");
+        assert_eq!(typst_value(&json!("say \"hi\" \\o/")), "\"say \\\"hi\\\" \\\\o/\"");
+        assert_eq!(typst_value(&json!(["a"])), "(\"a\",)");
+        assert_eq!(typst_value(&json!([])), "()");
+        assert_eq!(typst_value(&json!({})), "(:)");
+        assert_eq!(typst_value(&json!({"a": [1, 2], "b c": {"d": false}})), "(\"a\": (1,2,), \"b c\": (\"d\": false))");
+    }
+
+    #[test]
+    fn invalid_authors_files_name_their_file() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in [("authors.toml", "authors = ["), ("authors.json", "["), ("list.toml", "name = \"A\"")] {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            let error = read_authors(&path).unwrap_err().to_string();
+            assert!(error.contains(&format!("invalid authors file {}: ", path.display())), "{}", error);
+        }
+        let path = dir.path().join("authors.toml");
+        fs::write(&path, "[[authors]]\nname = \"A\"").unwrap();
+        assert_eq!(read_authors(&path).unwrap()[0].name, "A");
+    }
+}
@@ -0,0 +1,339 @@
+//! The Typst backend: write a [`Document`] as Typst markup using the blocks of `template.typ`.
+
+use std::sync::Arc;
+
+use crate::document::{Align, Block, Document, Inline, Table, TableCell};
+use crate::source_map::{CellSource, SourceMap};
+use crate::typst_content::{escape_content, escape_markup, escape_url};
+
+/// Write the document, with the map of its lines to the cells, numbered from 1.
+/// `notebook` names the notebook in the map, for the chapters of a book.
+pub fn write_document_mapped(document: &Document, notebook: Option<Arc<str>>) -> (String, SourceMap) {
+    let mut result = String::new();
+    let mut map = SourceMap::default();
+    let mut line = 1;
+    for cell in &document.cells {
+        let source = Arc::new(CellSource {
+            notebook: notebook.clone(),
+            index: cell.index,
+            id: cell.id.clone(),
+            kind: cell.kind,
+            source: cell.source.clone(),
+        });
+        let inputs = cell.input.iter().zip(cell.input_lines.iter().map(|line| Some(*line)).chain(std::iter::repeat(Some(1))));
+        let outputs = cell.outputs.iter().map(|block| (block, None));
+        for (block, cell_line) in inputs.chain(outputs) {
+            let written = write_block(block);
+            let end = line + written.matches('\n').count();
+            map.push(line, end, &source, cell_line);
+            line = end;
+            result += written.as_str();
+        }
+    }
+    (result, map)
+}
+
+/// Write the blocks, each one ends with a blank line.
+pub fn write_blocks(blocks: &[Block]) -> String {
+    let mut result = String::new();
+    for block in blocks {
+        result += write_block(block).as_str();
+    }
+    result
+}
+
+fn write_block(block: &Block) -> String {
+    match block {
+        Block::Heading { level, content } => {
+            format!("{} {}\n\n", "=".repeat((*level).into()), write_inlines(content))
+        }
+        Block::Paragraph(content) => {
+            format!("{}\n\n", write_inlines(content))
+        }
+        Block::BlockQuote(children) => {
+            format!("#block-quote[{}]\n\n", write_blocks(children).trim_end())
+        }
+        Block::List { ordered, items } => {
+            let mut result = String::new();
+            for item in items {
+                // 判断是 enum 还是 list.
+                result += if *ordered { "+ " } else { "- " };
+                // An item of several lines goes in a content block: re-indenting its lines
+                // under the marker would also indent the strings of `#raw(..)` and the like.
+                let blocks: Vec<String> = item
+                    .iter()
+                    .map(|block| write_block(block).trim_end().to_string())
+                    .collect();
+                let content = blocks.join("\n");
+                if content.contains('\n') {
+                    result += format!("#[{}\n]\n", content).as_str();
+                } else {
+                    result += format!("{}\n", content).as_str();
+                }
+            }
+            result += "\n";
+            result
+        }
+        Block::CodeBlock { lang, code } => match lang {
+            Some(lang) => format!(
+                "#raw(\"{}\", lang: \"{}\", block: true)\n\n",
+                escape_content(code),
+                escape_content(lang)
+            ),
+            None => format!("#raw(\"{}\", block: true)\n\n", escape_content(code)),
+        },
+        Block::Code { lang, source, execution_count } => {
+            // Refer to the [template.typ] to see the def. of code-block.
+            let count = match execution_count {
+                Some(count) => count.to_string(),
+                None => "none".to_string(),
+            };
+            format!(
+                "#code-block(\"{}\", lang: \"{}\", count: {})\n\n",
+                escape_content(source),
+                escape_content(lang),
+                count
+            )
+        }
+        Block::Output(text) => {
+            format!("#output-block(\"{}\")\n\n", escape_content(text))
+        }
+        Block::Math(tex) => {
+            format!("#mimath(\"{}\")\n\n", escape_content(tex))
+        }
+        Block::Latex(source) => {
+            format!("#mitext(\"{}\")\n\n", escape_content(source))
+        }
+        Block::Figure { path } => {
+            format!("#image(\"{}\")\n\n", escape_content(path))
+        }
+        Block::Table(table) => write_table(table),
+        Block::ThematicBreak => {
+            // The long long line. --------
+            "#line(length: 100%)\n\n".to_string()
+        }
+        Block::Placeholder(label) => {
+            format!("#placeholder-block(\"{}\")\n\n", escape_content(label))
+        }
+        Block::Error(message) => {
+            format!("#error-block(\"{}\")\n\n", escape_content(message))
+        }
+        Block::Raw(source) => {
+            format!("{}\n\n", source.trim_end())
+        }
+    }
+}
+
+fn write_inlines(inlines: &[Inline]) -> String {
+    let mut result = String::new();
+    let mut after_code = false;
+    for inline in inlines {
+        // The start of a content block counts as a line start, to be safe.
+        let line_start = result.is_empty() || result.ends_with('\n');
+        let mut written = write_inline(inline, line_start);
+        // Right after `#raw(..)`, a `.` or a `(` would continue the expression.
+        if after_code && written.starts_with(['.', '(']) {
+            written.insert(0, '\\');
+        }
+        after_code = !matches!(inline, Inline::Text(_) | Inline::LineBreak);
+        result += written.as_str();
+    }
+    result
+}
+
+fn write_inline(inline: &Inline, line_start: bool) -> String {
+    match inline {
+        Inline::Text(text) => escape_markup(text, line_start),
+        Inline::Emphasis(content) => format!("#emph[{}]", write_inlines(content)),
+        Inline::Strong(content) => format!("#strong[{}]", write_inlines(content)),
+        Inline::Underline(content) => format!("#underline[{}]", write_inlines(content)),
+        Inline::Strike(content) => format!("#strike[{}]", write_inlines(content)),
+        Inline::Code(code) => format!("#raw(\"{}\")", escape_content(code)),
+        Inline::Math(tex) => format!("#mi(\"{}\")", escape_content(tex)),
+        Inline::Link { url, content } => {
+            format!("#link(\"{}\")[{}]", escape_url(url), write_inlines(content))
+        }
+        Inline::Image { path, width } => match width {
+            Some(width) => format!(
+                "#figure(align(center, image(\"{}\", width: {}%)))",
+                escape_content(path),
+                width
+            ),
+            None => format!("#figure(align(center, image(\"{}\")))", escape_content(path)),
+        },
+        Inline::Footnote(content) => {
+            format!("#footnote[{}]", write_blocks(content).trim_end())
+        }
+        Inline::LineBreak => "\\ \n".to_string(),
+        Inline::Error(message) => {
+            format!("#error-block(\"{}\")", escape_content(message))
+        }
+    }
+}
+
+/// Emit the table as a Typst `#table`, header rows go into `table.header`.
+fn write_table(table: &Table) -> String {
+    if table.header.is_empty() && table.rows.is_empty() {
+        return String::new();
+    }
+
+    let mut result = String::new();
+    result += format!("#table(\n  columns: {},\n", table.columns.max(1)).as_str();
+
+    if !table.align.is_empty() {
+        // The trailing comma keeps a single alignment an array.
+        let align: Vec<&str> = table.align.iter().map(|align| write_align(*align)).collect();
+        result += format!("  align: ({},),\n", align.join(", ")).as_str();
+    }
+
+    if !table.header.is_empty() {
+        result += "  table.header(\n";
+        for row in &table.header {
+            result += format!("    {}\n", write_row(row)).as_str();
+        }
+        result += "  ),\n";
+    }
+
+    for row in &table.rows {
+        result += format!("  {}\n", write_row(row)).as_str();
+    }
+
+    result += ")\n\n";
+
+    if let Some(caption) = &table.caption {
+        result += format!(
+            "#align(right, text(size: 0.8em, \"{}\"))\n\n",
+            escape_content(caption)
+        ).as_str();
+    }
+
+    result
+}
+
+fn write_row(row: &[TableCell]) -> String {
+    row.iter()
+        .map(|cell| {
+            let body = write_cell_body(&cell.content);
+
+            let mut args = Vec::new();
+            if cell.rowspan > 1 {
+                args.push(format!("rowspan: {}", cell.rowspan));
+            }
+            if cell.colspan > 1 {
+                args.push(format!("colspan: {}", cell.colspan));
+            }
+            if let Some(align) = cell.align {
+                args.push(format!("align: {}", write_align(align)));
+            }
+
+            if args.is_empty() {
+                format!("{},", body)
+            } else {
+                format!("table.cell({}, {}),", args.join(", "), body)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Plain text (e.g. a DataFrame value) is written as a string, the rest as markup.
+fn write_cell_body(content: &[Inline]) -> String {
+    match content {
+        [] => "[]".to_string(),
+        [Inline::Text(text)] => format!("\"{}\"", escape_content(text.trim())),
+        [Inline::Strong(inner)] if matches!(inner.as_slice(), [Inline::Text(_)]) => {
+            format!("strong({})", write_cell_body(inner))
+        }
+        _ => format!("[{}]", write_inlines(content)),
+    }
+}
+
+fn write_align(align: Align) -> &'static str {
+    match align {
+        Align::Auto => "auto",
+        Align::Left => "left",
+        Align::Center => "center",
+        Align::Right => "right",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Inline {
+        Inline::Text(text.to_string())
+    }
+
+    #[test]
+    fn text_after_an_expression() {
+        let code = Inline::Code("x".to_string());
+        assert_eq!(write_inlines(&[code.clone(), text("(a)")]), "#raw(\"x\")\\(a)");
+        assert_eq!(write_inlines(&[code.clone(), text(".len")]), "#raw(\"x\")\\.len");
+        assert_eq!(write_inlines(&[code, text("[b]")]), "#raw(\"x\")\\[b\\]");
+
+        let emph = Inline::Emphasis(vec![text("a")]);
+        assert_eq!(write_inlines(&[emph.clone(), text("(b)")]), "#emph[a]\\(b)");
+        assert_eq!(write_inlines(&[emph, text("[b]")]), "#emph[a]\\[b\\]");
+
+        let link = Inline::Link { url: "https://x.org".to_string(), content: vec![text("x")] };
+        assert_eq!(write_inlines(&[link.clone(), text("(see)")]), "#link(\"https://x.org\")[x]\\(see)");
+        assert_eq!(write_inlines(&[link, text("[1]")]), "#link(\"https://x.org\")[x]\\[1\\]");
+    }
+
+    fn paragraph(content: &str) -> Block {
+        Block::Paragraph(vec![text(content)])
+    }
+
+    #[test]
+    fn list_of_lines() {
+        let list = Block::List {
+            ordered: false,
+            items: vec![
+                vec![paragraph("a")],
+                vec![paragraph("b"), Block::List { ordered: true, items: vec![vec![paragraph("c")]] }],
+            ],
+        };
+        assert_eq!(write_block(&list), "- a\n- #[b\n+ c\n]\n\n");
+    }
+
+    #[test]
+    fn list_with_code_block() {
+        let code = Block::CodeBlock { lang: Some("py".to_string()), code: "if x:\n    y".to_string() };
+        let list = Block::List { ordered: false, items: vec![vec![paragraph("a"), code]] };
+        // The lines of the string are kept as they are.
+        assert_eq!(
+            write_block(&list),
+            "- #[a\n#raw(\"if x:\n    y\", lang: \"py\", block: true)\n]\n\n"
+        );
+    }
+
+    #[test]
+    fn nested_list_with_figure() {
+        let figure = Block::Figure { path: "./a.png".to_string() };
+        let nested = Block::List { ordered: false, items: vec![vec![paragraph("b"), figure]] };
+        let list = Block::List { ordered: true, items: vec![vec![paragraph("a"), nested]] };
+        assert_eq!(write_block(&list), "+ #[a\n- #[b\n#image(\"./a.png\")\n]\n]\n\n");
+    }
+
+    #[test]
+    fn list_with_table() {
+        let table = Table {
+            columns: 1,
+            header: vec![vec![TableCell::new(vec![text("x")])]],
+            rows: vec![vec![TableCell::new(vec![text("1")])]],
+            ..Table::default()
+        };
+        let list = Block::List { ordered: false, items: vec![vec![paragraph("a"), Block::Table(table)]] };
+        assert_eq!(
+            write_block(&list),
+            "- #[a\n#table(\n  columns: 1,\n  table.header(\n    \"x\",\n  ),\n  \"1\",\n)\n]\n\n"
+        );
+    }
+
+    #[test]
+    fn text_after_text() {
+        assert_eq!(write_inlines(&[text("a "), text("(b)")]), "a (b)");
+        assert_eq!(write_inlines(&[text("a"), Inline::LineBreak, text("- b")]), "a\\ \n\\- b");
+    }
+}
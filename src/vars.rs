@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+use crate::error::{NbconvertError, Result};
+
+/// The arguments of `project` set by the converter, a variable can't take their names.
+pub const RESERVED_VARS: &[&str] = &[
+    "title", "authors", "date", "abstract", "keywords", "lang", "region", "paper", "flipped", "fonts",
+    "body",
+];
+
+/// Parse a `key=value` variable. The value is a bool, a number or else a string,
+/// unless the type is given as `key:type=value`, one of `str`, `int`, `float`, `bool` and `path`.
+pub fn parse_var(pair: &str) -> Result<(String, Value)> {
+    let (key, value) = pair.split_once('=').ok_or_else(|| {
+        NbconvertError::InvalidArgument(format!("expected key=value, got \"{}\"", pair))
+    })?;
+    let (name, kind) = match key.split_once(':') {
+        Some((name, kind)) => (name.trim(), Some(kind.trim())),
+        None => (key.trim(), None),
+    };
+    let invalid = |kind: &str| {
+        NbconvertError::InvalidArgument(format!("variable {}: \"{}\" is not a valid {}", name, value, kind))
+    };
+
+    let value = match kind {
+        None => match value {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => match serde_json::from_str::<serde_json::Number>(value) {
+                Ok(number) => Value::Number(number),
+                Err(_) => Value::String(value.to_string()),
+            },
+        },
+        Some("str") => Value::String(value.to_string()),
+        Some("int") => Value::from(value.trim().parse::<i64>().map_err(|_| invalid("int"))?),
+        Some("float") => value
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number)
+            .ok_or_else(|| invalid("float"))?,
+        Some("bool") => Value::Bool(value.trim().parse().map_err(|_| invalid("bool"))?),
+        Some("path") => path_var(value),
+        Some(kind) => {
+            return Err(NbconvertError::InvalidArgument(format!(
+                "variable {}: unknown type \"{}\", expected str, int, float, bool or path",
+                name, kind
+            )))
+        }
+    };
+
+    check_var_name(name)?;
+    Ok((name.to_string(), value))
+}
+
+/// Parse a `key=<JSON>` variable, e.g. `tags=["a", "b"]`.
+pub fn parse_var_json(pair: &str) -> Result<(String, Value)> {
+    let (name, json) = pair.split_once('=').ok_or_else(|| {
+        NbconvertError::InvalidArgument(format!("expected key=<JSON>, got \"{}\"", pair))
+    })?;
+    let name = name.trim();
+    let value = serde_json::from_str(json)
+        .map_err(|e| NbconvertError::InvalidArgument(format!("variable {}: invalid JSON: {}", name, e)))?;
+    check_var_name(name)?;
+    Ok((name.to_string(), value))
+}
+
+/// Check that a variable has a name, and not the name of an argument of `project`.
+pub fn check_var_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(NbconvertError::InvalidArgument("a variable has an empty name".to_string()));
+    }
+    if RESERVED_VARS.contains(&name) {
+        return Err(NbconvertError::InvalidArgument(format!(
+            "variable {} is an argument of project, set it with its own option",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// A path variable, written `{ path = "..." }` in a config file or a front matter.
+/// The file is copied next to the output, and the variable becomes its path.
+pub fn path_var(path: &str) -> Value {
+    let mut map = Map::new();
+    map.insert("path".to_string(), Value::String(path.to_string()));
+    Value::Object(map)
+}
+
+/// The path of a path variable.
+pub fn var_path(value: &Value) -> Option<&str> {
+    match value {
+        Value::Object(map) if map.len() == 1 => map.get("path")?.as_str(),
+        _ => None,
+    }
+}
+
+/// Resolve the relative paths of the path variables against `dir`.
+pub fn resolve_var_paths(vars: &mut BTreeMap<String, Value>, dir: &Path) {
+    for value in vars.values_mut() {
+        if let Some(path) = var_path(value) {
+            *value = path_var(&dir.join(path).display().to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn var(pair: &str) -> Value {
+        parse_var(pair).unwrap().1
+    }
+
+    #[test]
+    fn inferred_types() {
+        assert_eq!(parse_var("course=CS101").unwrap(), ("course".to_string(), json!("CS101")));
+        assert_eq!(var("draft=true"), json!(true));
+        assert_eq!(var("week=3"), json!(3));
+        assert_eq!(var("ratio=0.5"), json!(0.5));
+        assert_eq!(var("id=007"), json!("007"));
+        assert_eq!(var("note=a=b"), json!("a=b"));
+        assert_eq!(var("empty="), json!(""));
+    }
+
+    #[test]
+    fn given_types() {
+        assert_eq!(var("id:str=007"), json!("007"));
+        assert_eq!(var("week:int= 3"), json!(3));
+        assert_eq!(var("ratio:float=2"), json!(2.0));
+        assert_eq!(var("draft:bool=false"), json!(false));
+        assert_eq!(var("logo:path=images/logo.png"), json!({"path": "images/logo.png"}));
+        assert_eq!(var_path(&var("logo:path=images/logo.png")), Some("images/logo.png"));
+
+        assert!(parse_var("week:int=three").is_err());
+        assert!(parse_var("ratio:float=inf").is_err());
+        assert!(parse_var("draft:bool=yes").is_err());
+        let error = parse_var("logo:file=a.png").unwrap_err().to_string();
+        assert!(error.contains("unknown type \"file\""), "{}", error);
+        assert!(parse_var("course").is_err());
+    }
+
+    #[test]
+    fn json_values() {
+        assert_eq!(parse_var_json("tags=[\"a\", \"b\"]").unwrap(), ("tags".to_string(), json!(["a", "b"])));
+        assert_eq!(parse_var_json(" logo = null").unwrap().1, Value::Null);
+        assert!(parse_var_json("tags=[a]").is_err());
+        assert!(parse_var_json("tags").is_err());
+    }
+
+    #[test]
+    fn reserved_names() {
+        for name in RESERVED_VARS {
+            let error = parse_var(&format!("{}=x", name)).unwrap_err().to_string();
+            assert!(error.contains("is an argument of project"), "{}", error);
+            assert!(parse_var_json(&format!("{}=1", name)).is_err());
+        }
+        assert!(parse_var("=x").is_err());
+        assert!(parse_var(" title2 =x").is_ok());
+    }
+
+    #[test]
+    fn resolve_paths() {
+        let mut vars = BTreeMap::from([("logo".to_string(), path_var("logo.png")), ("course".to_string(), json!("logo.png"))]);
+        resolve_var_paths(&mut vars, Path::new("notes"));
+        assert_eq!(var_path(&vars["logo"]), Some(Path::new("notes").join("logo.png").to_str().unwrap()));
+        assert_eq!(vars["course"], json!("logo.png"));
+    }
+}
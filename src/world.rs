@@ -0,0 +1,398 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use typst::diag::{self, FileError, FileResult, PackageError, PackageResult, SourceDiagnostic, Warned};
+use typst::foundations::{Bytes, Datetime, Smart};
+use typst::layout::{PageRanges, PagedDocument};
+use typst::syntax::package::PackageSpec;
+use typst::syntax::{FileId, Source, VirtualPath};
+use typst::text::{Font, FontBook, FontInfo};
+use typst::utils::LazyHash;
+use typst::{Library, World};
+use typst_pdf::{PdfOptions, Timestamp};
+
+use crate::compile::{page_path, CompileDiagnostic, CompileOptions, ExportOptions, OutputFormat};
+use crate::error::{NbconvertError, Result, Severity};
+use crate::packages::{find_package, package_cache_dir, PackageRef};
+use crate::typst_content::Date;
+
+/// Compile a Typst file in-process, and return the warnings.
+pub fn compile(typ: &Path, output: &Path, options: &CompileOptions, export: &ExportOptions) -> Result<Vec<CompileDiagnostic>> {
+    if export.format == OutputFormat::Html {
+        return Err(NbconvertError::InvalidArgument(
+            "the embedded typst can't export HTML, give a typst binary 0.13 or later with --typst-bin".to_string(),
+        ));
+    }
+    let world = NotebookWorld::new(typ, options)?;
+    let Warned { output: result, warnings } = typst::compile::<PagedDocument>(&world);
+
+    match result {
+        Ok(document) => {
+            write_document(&world, document, output, export)?;
+            Ok(warnings.iter().map(|warning| world.diagnostic(warning)).collect())
+        }
+        Err(errors) => Err(NbconvertError::TypstError(
+            errors.iter().chain(&warnings).map(|diagnostic| world.diagnostic(diagnostic)).collect(),
+        )),
+    }
+}
+
+/// Write the pages of `export` of a compiled document, a PDF or an image per page.
+fn write_document(world: &NotebookWorld, document: PagedDocument, output: &Path, export: &ExportOptions) -> Result<()> {
+    let total = document.pages.len();
+    let pages: Vec<_> = document.pages.iter().zip(1..).filter(|(_, number)| export.exports(*number)).collect();
+    if pages.is_empty() {
+        return Err(NbconvertError::InvalidArgument(format!("the document has no page in the pages to export, it has {}", total)));
+    }
+    match export.format {
+        OutputFormat::Pdf => {
+            // The pages are filtered by the exporter, which keeps the links, the outline
+            // and the named destinations of the whole document.
+            let page_ranges = (!export.pages.is_empty()).then(|| {
+                PageRanges::new(
+                    export.pages.iter().map(|range| range.start.and_then(NonZeroUsize::new)..=range.end.and_then(NonZeroUsize::new)).collect(),
+                )
+            });
+            let options = PdfOptions {
+                ident: Smart::Auto,
+                timestamp: world.today(Some(0)).map(Timestamp::new_utc),
+                page_ranges,
+                ..PdfOptions::default()
+            };
+            let pdf = typst_pdf::pdf(&document, &options).map_err(|errors| {
+                NbconvertError::TypstError(errors.iter().map(|diagnostic| world.diagnostic(diagnostic)).collect())
+            })?;
+            fs::write(output, pdf)?;
+        }
+        OutputFormat::Png => {
+            for (page, number) in &pages {
+                let pixmap = typst_render::render(page, export.dpi / 72.0);
+                let png = pixmap.encode_png().map_err(|e| NbconvertError::CompileError(e.to_string()))?;
+                fs::write(page_path(output, *number, total), png)?;
+            }
+        }
+        OutputFormat::Svg => {
+            for (page, number) in &pages {
+                fs::write(page_path(output, *number, total), typst_svg::svg(page))?;
+            }
+        }
+        OutputFormat::Typst | OutputFormat::Html => {}
+    }
+    Ok(())
+}
+
+/// The font families of the embedded compiler: the ones of the font paths, of the system,
+/// and the ones embedded in Typst.
+pub fn font_families(font_paths: &[PathBuf]) -> Vec<String> {
+    FontSearch::get(font_paths).book.families().map(|(family, _)| family.to_string()).collect()
+}
+
+/// The world of a compilation: the Typst file, the files next to it (the template and the
+/// assets) and the packages, read once and kept in memory.
+struct NotebookWorld {
+    /// The directory of the Typst file, the files out of it can't be read.
+    root: PathBuf,
+    main: FileId,
+    library: LazyHash<Library>,
+    book: LazyHash<FontBook>,
+    fonts: Arc<FontSearch>,
+    options: CompileOptions,
+    sources: Mutex<HashMap<FileId, FileResult<Source>>>,
+    files: Mutex<HashMap<FileId, FileResult<Bytes>>>,
+}
+
+impl NotebookWorld {
+    fn new(typ: &Path, options: &CompileOptions) -> Result<NotebookWorld> {
+        let typ = fs::canonicalize(typ)?;
+        let root = typ.parent().unwrap_or(Path::new("/")).to_path_buf();
+        let main = FileId::new(None, VirtualPath::within_root(&typ, &root).unwrap_or_else(|| VirtualPath::new("main.typ")));
+        let main_source = Source::new(main, fs::read_to_string(&typ)?);
+        let fonts = FontSearch::get(&options.font_paths);
+
+        Ok(NotebookWorld {
+            root,
+            main,
+            library: LazyHash::new(Library::builder().build()),
+            book: LazyHash::new(fonts.book.clone()),
+            fonts,
+            options: options.clone(),
+            sources: Mutex::new(HashMap::from([(main, Ok(main_source))])),
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The path of a file on disk, in the root or in the directory of its package.
+    fn path(&self, id: FileId) -> FileResult<PathBuf> {
+        let root = match id.package() {
+            Some(spec) => package_dir(spec, &self.options)?,
+            None => self.root.clone(),
+        };
+        id.vpath().resolve(&root).ok_or(FileError::AccessDenied)
+    }
+
+    fn read(&self, id: FileId) -> FileResult<Vec<u8>> {
+        let path = self.path(id)?;
+        if path.is_dir() {
+            return Err(FileError::IsDirectory);
+        }
+        fs::read(&path).map_err(|e| FileError::from_io(e, &path))
+    }
+
+    /// The name of a file in the diagnostics, e.g. `template.typ` or
+    /// `@preview/cuti:0.2.1/lib.typ`.
+    fn name(&self, id: FileId) -> String {
+        let path = id.vpath().as_rootless_path().display();
+        match id.package() {
+            Some(spec) => format!("{}/{}", spec, path),
+            None => path.to_string(),
+        }
+    }
+
+    fn diagnostic(&self, diagnostic: &SourceDiagnostic) -> CompileDiagnostic {
+        let location = diagnostic.span.id().and_then(|id| {
+            let source = self.source(id).ok()?;
+            let start = source.range(diagnostic.span)?.start;
+            Some((self.name(id), source.byte_to_line(start)? + 1, source.byte_to_column(start)? + 1))
+        });
+        let (file, line, column) = match location {
+            Some((file, line, column)) => (Some(file), Some(line), Some(column)),
+            None => (None, None, None),
+        };
+        CompileDiagnostic {
+            severity: match diagnostic.severity {
+                diag::Severity::Error => Severity::Error,
+                diag::Severity::Warning => Severity::Warning,
+            },
+            message: diagnostic.message.to_string(),
+            file,
+            line,
+            column,
+            hints: diagnostic.hints.iter().map(|hint| hint.to_string()).collect(),
+        }
+    }
+}
+
+impl World for NotebookWorld {
+    fn library(&self) -> &LazyHash<Library> {
+        &self.library
+    }
+
+    fn book(&self) -> &LazyHash<FontBook> {
+        &self.book
+    }
+
+    fn main(&self) -> FileId {
+        self.main
+    }
+
+    fn source(&self, id: FileId) -> FileResult<Source> {
+        let mut sources = self.sources.lock().unwrap_or_else(|e| e.into_inner());
+        sources
+            .entry(id)
+            .or_insert_with(|| {
+                let text = String::from_utf8(self.read(id)?).map_err(|_| FileError::InvalidUtf8)?;
+                Ok(Source::new(id, text))
+            })
+            .clone()
+    }
+
+    fn file(&self, id: FileId) -> FileResult<Bytes> {
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        files.entry(id).or_insert_with(|| self.read(id).map(Bytes::new)).clone()
+    }
+
+    fn font(&self, index: usize) -> Option<Font> {
+        self.fonts.slots.get(index)?.get()
+    }
+
+    /// The date in UTC, with the offset if given, as the local time zone isn't known.
+    fn today(&self, offset: Option<i64>) -> Option<Datetime> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+        let date = Date::from_timestamp(now + offset.unwrap_or(0) * 3600);
+        Datetime::from_ymd(date.year, date.month.try_into().ok()?, date.day.try_into().ok()?)
+    }
+}
+
+/// The fonts found for some font paths, read when first used.
+struct FontSearch {
+    book: FontBook,
+    slots: Vec<FontSlot>,
+}
+
+struct FontSlot {
+    /// The file, `None` for the fonts embedded in Typst, loaded from the start.
+    path: Option<PathBuf>,
+    index: u32,
+    font: OnceLock<Option<Font>>,
+}
+
+impl FontSlot {
+    fn get(&self) -> Option<Font> {
+        self.font
+            .get_or_init(|| {
+                let data = fs::read(self.path.as_ref()?).ok()?;
+                Font::new(Bytes::new(data), self.index)
+            })
+            .clone()
+    }
+}
+
+impl FontSearch {
+    /// The fonts of the font paths, then of the system, then the ones embedded in Typst,
+    /// in the order Typst prefers them. The search is cached per font paths.
+    fn get(font_paths: &[PathBuf]) -> Arc<FontSearch> {
+        static CACHE: Mutex<BTreeMap<Vec<PathBuf>, Arc<FontSearch>>> = Mutex::new(BTreeMap::new());
+
+        let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        cache.entry(font_paths.to_vec()).or_insert_with(|| Arc::new(FontSearch::search(font_paths))).clone()
+    }
+
+    fn search(font_paths: &[PathBuf]) -> FontSearch {
+        let mut database = fontdb::Database::new();
+        for path in font_paths {
+            database.load_fonts_dir(path);
+        }
+        database.load_system_fonts();
+
+        let mut search = FontSearch { book: FontBook::new(), slots: Vec::new() };
+        for face in database.faces() {
+            let path = match &face.source {
+                fontdb::Source::File(path) | fontdb::Source::SharedFile(path, _) => path,
+                fontdb::Source::Binary(_) => continue,
+            };
+            if let Some(info) = database.with_face_data(face.id, FontInfo::new).flatten() {
+                search.book.push(info);
+                search.slots.push(FontSlot { path: Some(path.clone()), index: face.index, font: OnceLock::new() });
+            }
+        }
+        for data in typst_assets::fonts() {
+            for (index, font) in Font::iter(Bytes::new(data)).enumerate() {
+                search.book.push(font.info().clone());
+                search.slots.push(FontSlot { path: None, index: index as u32, font: OnceLock::from(Some(font)) });
+            }
+        }
+        search
+    }
+}
+
+/// The directory of a package: in the local packages, else in the package cache, where
+/// the `@preview` ones are downloaded unless offline, as the typst binary does.
+fn package_dir(spec: &PackageSpec, options: &CompileOptions) -> PackageResult<PathBuf> {
+    let package = PackageRef {
+        namespace: spec.namespace.to_string(),
+        name: spec.name.to_string(),
+        version: spec.version.to_string(),
+    };
+    if let Some(dir) = find_package(&package, options.package_cache.as_deref()) {
+        return Ok(dir);
+    }
+    if options.offline {
+        return Err(PackageError::Other(Some(
+            format!("{} is not installed, and can't be downloaded offline", spec).into(),
+        )));
+    }
+    match package_cache_dir(options.package_cache.as_deref()) {
+        Some(cache) if spec.namespace == "preview" => {
+            let dir = cache.join(package.subdir());
+            download_package(spec, &dir)?;
+            Ok(dir)
+        }
+        _ => Err(PackageError::NotFound(spec.clone())),
+    }
+}
+
+/// Download a `@preview` package from the Typst registry and unpack it into `dir`.
+/// It is unpacked next to `dir` and moved in place, so that a package is never seen
+/// half-written, by another compilation or after a failure.
+fn download_package(spec: &PackageSpec, dir: &Path) -> PackageResult<()> {
+    let url = format!("https://packages.typst.org/preview/{}-{}.tar.gz", spec.name, spec.version);
+    eprintln!("Downloading {}", spec);
+
+    let response = reqwest::blocking::get(&url).and_then(|response| response.error_for_status()).map_err(|e| {
+        match e.status() {
+            Some(status) if status.as_u16() == 404 => PackageError::NotFound(spec.clone()),
+            _ => PackageError::NetworkFailed(Some(e.to_string().into())),
+        }
+    })?;
+    let data = response.bytes().map_err(|e| PackageError::NetworkFailed(Some(e.to_string().into())))?;
+
+    let failed = |e: std::io::Error| PackageError::Other(Some(format!("failed to install {}: {}", spec, e).into()));
+    let parent = dir.parent().unwrap_or(dir);
+    fs::create_dir_all(parent).map_err(failed)?;
+    let unpacked = tempfile::tempdir_in(parent).map_err(failed)?;
+    let archive = flate2::read::GzDecoder::new(&data[..]);
+    tar::Archive::new(archive)
+        .unpack(unpacked.path())
+        .map_err(|e| PackageError::MalformedArchive(Some(e.to_string().into())))?;
+    match fs::rename(unpacked.path(), dir) {
+        Ok(()) => Ok(()),
+        // Installed meanwhile, e.g. by another notebook of the batch.
+        Err(_) if dir.join("typst.toml").is_file() => Ok(()),
+        Err(e) => Err(failed(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::parse_pages;
+    use crate::Converter;
+
+    const NOTEBOOK: &str = r##"{
+ "nbformat": 4,
+ "nbformat_minor": 5,
+ "metadata": {},
+ "cells": [
+  {"cell_type": "markdown", "id": "title", "metadata": {}, "source": ["# Smoke test\n", "\n", "Some math: $x^2 + \\frac{1}{2}$, and **bold** text."]},
+  {"cell_type": "code", "id": "code", "metadata": {}, "execution_count": 1, "source": ["print(\"hello\")"],
+   "outputs": [{"output_type": "stream", "name": "stdout", "text": ["\u001b[31mhello\u001b[0m\n"]}]}
+ ]
+}"##;
+
+    /// The package cache of the tests, with stand-ins of the packages of the templates.
+    fn fixture_packages() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/packages")
+    }
+
+    /// Convert the notebook and compile it to PDF and to a PNG of the first page.
+    fn compile_notebook(options: &CompileOptions) {
+        let dir = tempfile::tempdir().unwrap();
+        let notebook = dir.path().join("smoke.ipynb");
+        fs::write(&notebook, NOTEBOOK).unwrap();
+        let conversion = Converter::new()
+            .asset_dir(dir.path().join("downloads"), "./downloads")
+            .convert_path(&notebook)
+            .unwrap();
+        let typ = dir.path().join("smoke.typ");
+        conversion.write(&typ).unwrap();
+
+        let pdf = dir.path().join("smoke.pdf");
+        compile(&typ, &pdf, options, &ExportOptions::default()).unwrap();
+        assert!(fs::read(&pdf).unwrap().starts_with(b"%PDF"));
+
+        let png = dir.path().join("smoke.png");
+        let export = ExportOptions { format: OutputFormat::Png, pages: parse_pages("1").unwrap(), ..ExportOptions::default() };
+        compile(&typ, &png, options, &export).unwrap();
+        assert!(fs::read(&png).unwrap().starts_with(b"\x89PNG"));
+    }
+
+    /// The default template compiles with the embedded compiler, offline.
+    #[test]
+    fn compiles_a_notebook() {
+        let options = CompileOptions { package_cache: Some(fixture_packages()), offline: true, ..CompileOptions::default() };
+        compile_notebook(&options);
+    }
+
+    /// The same with the packages of the registry, downloaded to an empty cache.
+    #[test]
+    #[ignore = "downloads the packages from the Typst registry"]
+    fn compiles_with_the_registry_packages() {
+        let cache = tempfile::tempdir().unwrap();
+        let options = CompileOptions { package_cache: Some(cache.path().to_path_buf()), ..CompileOptions::default() };
+        compile_notebook(&options);
+    }
+}
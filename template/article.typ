@@ -0,0 +1,82 @@
+// The article template of nbconvert-rust: the title, the authors and the abstract
+// above the body.
+//
+// A template defines `project`, which the generated source calls as
+// `#show: project.with(title: ..., authors: ..., ...)`. See "Templates" in the README
+// for its arguments, a template must accept (and may ignore) all of them.
+
+#import "nbconvert.typ": with-fonts, localized
+
+// The project function defines how your document looks.
+// It takes your content and some metadata and formats it.
+// Go ahead and customize it to your liking!
+#let project(
+  title: "",
+  authors: (),
+  date: none,
+  abstract: none,
+  keywords: (),
+  lang: "en",
+  region: none,
+  paper: "a4",
+  flipped: false,
+  fonts: (:),
+  // The arguments added by later versions, or the variables of other templates.
+  ..args,
+  body,
+) = {
+  // Set the document's basic properties.
+  set document(author: authors.map(a => a.name), title: title)
+  set page(paper: paper, flipped: flipped, numbering: "1", number-align: center)
+  set text(lang: lang, region: region)
+  show: with-fonts.with(fonts)
+
+  // Title row.
+  align(center)[
+    #block(text(weight: 700, 1.75em, title))
+  ]
+
+  // Author information.
+  pad(
+    top: 0.5em,
+    bottom: 0.5em,
+    x: 2em,
+    grid(
+      columns: (1fr,) * calc.min(3, authors.len()),
+      gutter: 1em,
+      ..authors.map(author => align(center)[
+        *#author.name*#if author.corresponding { super[\*] }
+        #if author.orcid != none {
+          link("https://orcid.org/" + author.orcid, text(fill: rgb(166, 206, 57), size: 0.8em, "ORCID"))
+        }
+        #if author.student-id != none { linebreak(); author.student-id }
+        #if author.email != none { linebreak(); link("mailto:" + author.email, author.email) }
+        #for affiliation in author.affiliations { linebreak(); affiliation }
+      ]),
+    ),
+  )
+  if authors.any(author => author.corresponding) {
+    align(center, text(size: 0.8em)[\* Corresponding author])
+  }
+
+  align(center)[
+    #v(1em, weak: true)
+    #date
+  ]
+
+  // Abstract and keywords.
+  if abstract != none {
+    pad(x: 2em, top: 1em, bottom: 0.5em)[
+      #align(center, text(weight: 700, localized("abstract")))
+      #abstract
+    ]
+  }
+  if keywords.len() > 0 {
+    pad(x: 2em, bottom: 0.5em)[*#localized("keywords"):* #keywords.join(", ")]
+  }
+
+  // Main body.
+  set par(justify: true)
+
+  body
+}
@@ -0,0 +1,76 @@
+// The homework template of nbconvert-rust: a cover page with the student information
+// (name, student ID, email, affiliation), then the body with the names in the header.
+// It reads two variables, e.g. `--var course=CS101 --var logo:path=logo.png`.
+//
+// A template defines `project`, which the generated source calls as
+// `#show: project.with(title: ..., authors: ..., ...)`. See "Templates" in the README
+// for its arguments, a template must accept (and may ignore) all of them.
+
+#import "nbconvert.typ": with-fonts, localized
+
+#let project(
+  title: "",
+  authors: (),
+  date: none,
+  abstract: none,
+  keywords: (),
+  lang: "en",
+  region: none,
+  paper: "a4",
+  flipped: false,
+  fonts: (:),
+  // The arguments added by later versions, or the variables of other templates.
+  ..args,
+  body,
+) = {
+  set document(author: authors.map(a => a.name), title: title)
+  set page(paper: paper, flipped: flipped)
+  set text(lang: lang, region: region)
+  show: with-fonts.with(fonts)
+
+  let vars = args.named()
+  let course = vars.at("course", default: none)
+  let logo = vars.at("logo", default: none)
+
+  // Cover page.
+  if logo != none {
+    align(center, image(logo, height: 3cm))
+  }
+  v(2fr)
+  if course != none {
+    align(center, text(1.2em, str(course)))
+  }
+  align(center, block(text(weight: 700, 2em, title)))
+  v(1fr)
+  let value(value) = if value == none { [] } else { value }
+  align(center, table(
+    columns: 4,
+    inset: 8pt,
+    align: center + horizon,
+    stroke: (x, y) => if y == 0 { (bottom: 1pt) } else { (bottom: 0.5pt + luma(180)) },
+    table.header(..("name", "student-id", "email", "affiliation").map(key => strong(localized(key)))),
+    ..authors.map(author => (
+      author.name,
+      value(author.student-id),
+      value(author.email),
+      author.affiliations.join(linebreak()),
+    )).flatten(),
+  ))
+  v(1fr)
+  align(center, date)
+  v(2fr)
+  pagebreak()
+
+  // Main body.
+  set page(
+    numbering: "1 / 1",
+    number-align: center,
+    header: text(size: 0.8em, fill: luma(100))[
+      #title #h(1fr) #authors.map(author => author.name).join(", ")
+    ],
+  )
+  counter(page).update(1)
+  set par(justify: true)
+
+  body
+}
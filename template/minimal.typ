@@ -0,0 +1,35 @@
+// The minimal template of nbconvert-rust: the title and the author names in one line,
+// then the body.
+//
+// A template defines `project`, which the generated source calls as
+// `#show: project.with(title: ..., authors: ..., ...)`. See "Templates" in the README
+// for its arguments, a template must accept (and may ignore) all of them.
+
+#import "nbconvert.typ": with-fonts
+
+#let project(
+  title: "",
+  authors: (),
+  date: none,
+  abstract: none,
+  keywords: (),
+  lang: "en",
+  region: none,
+  paper: "a4",
+  flipped: false,
+  fonts: (:),
+  // The arguments added by later versions, or the variables of other templates.
+  ..args,
+  body,
+) = {
+  set document(author: authors.map(a => a.name), title: title)
+  set page(paper: paper, flipped: flipped, numbering: "1", number-align: center)
+  set text(lang: lang, region: region)
+  show: with-fonts.with(fonts)
+
+  text(weight: 700, 1.5em, title)
+  linebreak()
+  text(fill: luma(100), (authors.map(author => author.name) + (date,).filter(d => d != none)).join(" · "))
+
+  body
+}
@@ -0,0 +1,136 @@
+// The blocks of the cells and the outputs, used by the source generated by nbconvert-rust.
+// It is written next to the source at every conversion: customize the look of the document
+// in `template.typ` instead.
+
+#import "@preview/ansi-render:0.8.0": * // Render a terminal-like output.
+
+#import "@preview/mitex:0.2.5": * // LaTex Support for Typst.
+
+#import "@preview/cuti:0.3.0": show-cn-fakebold // Fake bold for CJK
+
+#let radius = 3pt
+#let inset = 8pt
+
+// The fonts of `project`, each one is a list of families.
+#let default-fonts = (
+  body: ("New Computer Modern",),
+  cjk: ("SimSun",),
+  emphasis: ("KaiTi",),
+  mono: ("Maple Mono NF",),
+)
+#let mono-fonts = state("mono-fonts", default-fonts.mono)
+
+// Set the fonts of the body, `fonts` overrides some of the `default-fonts`.
+#let with-fonts(fonts, body) = {
+  let fonts = default-fonts + fonts
+  set text(font: fonts.body + fonts.cjk)
+  show raw: set text(font: fonts.mono)
+  show emph: set text(font: fonts.body + fonts.emphasis)
+  show: show-cn-fakebold
+  mono-fonts.update(fonts.mono)
+  body
+}
+
+// The words of the templates per language (and `lang-region`), English for the others.
+// Typst already translates the figures, the tables and the outline.
+#let labels = (
+  en: (abstract: "Abstract", keywords: "Keywords", name: "Name", student-id: "Student ID", email: "Email", affiliation: "Affiliation"),
+  zh: (abstract: "摘要", keywords: "关键词", name: "姓名", student-id: "学号", email: "邮箱", affiliation: "单位"),
+  zh-tw: (abstract: "摘要", keywords: "關鍵詞", name: "姓名", student-id: "學號", email: "電子郵件", affiliation: "單位"),
+  ja: (abstract: "概要", keywords: "キーワード", name: "氏名", student-id: "学籍番号", email: "メール", affiliation: "所属"),
+  ko: (abstract: "초록", keywords: "키워드", name: "이름", student-id: "학번", email: "이메일", affiliation: "소속"),
+  de: (abstract: "Zusammenfassung", keywords: "Schlüsselwörter", name: "Name", student-id: "Matrikelnummer", email: "E-Mail", affiliation: "Einrichtung"),
+  fr: (abstract: "Résumé", keywords: "Mots-clés", name: "Nom", student-id: "Numéro d'étudiant", email: "Courriel", affiliation: "Affiliation"),
+  es: (abstract: "Resumen", keywords: "Palabras clave", name: "Nombre", student-id: "Matrícula", email: "Correo", affiliation: "Afiliación"),
+)
+
+// Whether the template shows a table of contents: a template calling `outline` sets it,
+// so that a book doesn't add another one.
+#let has-outline = state("has-outline", false)
+
+// The table of contents of a book, unless the template shows one.
+#let book-outline() = context {
+  if not has-outline.final() {
+    outline(indent: auto)
+  }
+}
+
+// The word for `key` in the language of the text.
+#let localized(key) = context {
+  let words = if text.region != none { labels.at(text.lang + "-" + lower(text.region), default: none) }
+  if words == none { words = labels.at(text.lang, default: labels.en) }
+  words.at(key)
+}
+
+// Form a code block, with execution count to its left.
+#let code-block(body, lang: "python", count: none) = context {
+  block(
+    raw(body, lang: lang),
+    fill: luma(230),
+    inset: inset,
+    radius: radius,
+    width: 100%
+  )
+  v(0pt, weak: true)
+  let c = if count == none { raw("[ ]:") } else { raw("[" + str(count) + "]:") }
+  let size = measure(c)
+  box(height: 0pt, move(dx: -size.width, dy: -size.height - inset, c))
+}
+
+#let output-block(body) = context {
+  v(0pt, weak: true)
+  ansi-render(
+    body,
+    radius: radius,
+    inset: inset,
+    width: 100%,
+    font: mono-fonts.get()
+  )
+}
+
+#let block-quote(body) = context {
+  let size = measure(body)
+  grid(
+    columns: (4pt, auto),
+    rows: auto,
+    gutter: 0pt,
+    rect(
+      fill: luma(180),
+      height: size.height + 2 * inset,
+      radius: (left: radius),
+    ),
+    block(
+      fill: luma(240),
+      height: size.height + 2 * inset,
+      inset: inset,
+      radius: (right: radius),
+      width: 100%,
+      body,
+    ),
+  )
+}
+
+// A labelled box standing in for an output that can't be rendered.
+#let placeholder-block(label) = {
+  v(0pt, weak: true)
+  block(
+    text(fill: luma(100), style: "italic", label),
+    stroke: (paint: luma(150), dash: "dashed"),
+    inset: inset,
+    radius: radius,
+    width: 100%
+  )
+}
+
+// A box standing in for a cell that failed to convert.
+#let error-block(message) = {
+  v(0pt, weak: true)
+  block(
+    text(fill: rgb(176, 0, 32), raw(message)),
+    fill: rgb(253, 236, 234),
+    stroke: rgb(176, 0, 32),
+    inset: inset,
+    radius: radius,
+    width: 100%
+  )
+}
@@ -1,10 +1,11 @@
-#import "template.typ": *
+#import "nbconvert.typ": *
+#import "article.typ": project
 
 #show: project.with(
   title: "Was",
   authors: (
-    (name: "VectorPikachu", email: none, affiliation: "Peking University"),
-    (name: "Pikachu", email: "oiakkakak@333.com", affiliation: "EECS"),
+    (name: "VectorPikachu", email: none, affiliations: ("Peking University",), orcid: none, corresponding: false, student-id: none),
+    (name: "Pikachu", email: "oiakkakak@333.com", affiliations: ("EECS",), orcid: none, corresponding: false, student-id: none),
   ),
   date: datetime(year: 2015, month: 6, day: 19).display("[year]年[month padding:space]月[day padding:space]日")
 )
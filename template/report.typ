@@ -0,0 +1,79 @@
+// The report template of nbconvert-rust: a title page, the table of contents,
+// and numbered headings with the title in the page header.
+//
+// A template defines `project`, which the generated source calls as
+// `#show: project.with(title: ..., authors: ..., ...)`. See "Templates" in the README
+// for its arguments, a template must accept (and may ignore) all of them.
+
+#import "nbconvert.typ": with-fonts, localized, has-outline
+
+#let project(
+  title: "",
+  authors: (),
+  date: none,
+  abstract: none,
+  keywords: (),
+  lang: "en",
+  region: none,
+  paper: "a4",
+  flipped: false,
+  fonts: (:),
+  // The arguments added by later versions, or the variables of other templates.
+  ..args,
+  body,
+) = {
+  set document(author: authors.map(a => a.name), title: title)
+  set page(paper: paper, flipped: flipped)
+  set text(lang: lang, region: region)
+  show: with-fonts.with(fonts)
+
+  // Title page.
+  v(1fr)
+  align(center)[
+    #block(text(weight: 700, 2.5em, title))
+    #v(2em)
+    #for author in authors [
+      #text(1.2em, author.name)#if author.corresponding { super[\*] } \
+      #if author.email != none [#link("mailto:" + author.email, author.email) \ ]
+      #for affiliation in author.affiliations [#affiliation \ ]
+      #v(0.5em)
+    ]
+    #v(2em)
+    #date
+  ]
+  v(1fr)
+  if abstract != none {
+    pad(x: 3em)[
+      #align(center, text(weight: 700, localized("abstract")))
+      #abstract
+    ]
+  }
+  if keywords.len() > 0 {
+    pad(x: 3em)[*#localized("keywords"):* #keywords.join(", ")]
+  }
+  v(1fr)
+  pagebreak()
+
+  // Table of contents.
+  set page(numbering: "i", number-align: center)
+  counter(page).update(1)
+  outline(indent: auto)
+  has-outline.update(true)
+  pagebreak()
+
+  // Main body.
+  set page(
+    numbering: "1",
+    number-align: center,
+    header: context {
+      if counter(page).get().first() > 1 {
+        align(right, text(size: 0.8em, fill: luma(100), title))
+      }
+    },
+  )
+  counter(page).update(1)
+  set heading(numbering: "1.1")
+  set par(justify: true)
+
+  body
+}
@@ -0,0 +1,4 @@
+#let ansi-render(body, font: none, size: none, width: auto, height: auto, breakable: false, radius: 0pt, inset: 0pt, outset: 0pt, spacing: none, above: none, below: none, clip: false, theme: none, bold-is-bright: false) = {
+  set text(font: font) if font != none
+  block(raw(body), width: width, radius: radius, inset: inset)
+}
@@ -0,0 +1 @@
+#let show-cn-fakebold(body) = body
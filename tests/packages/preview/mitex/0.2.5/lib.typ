@@ -0,0 +1,3 @@
+#let mi(it, ..args) = if type(it) == str { raw(it) } else { it }
+#let mimath(it, ..args) = block(if type(it) == str { raw(it) } else { it })
+#let mitext(it, ..args) = if type(it) == str { raw(it) } else { it }